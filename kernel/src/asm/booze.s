@@ -1,165 +1,174 @@
-# THis is the second version of the Bootloader.  
-# The difference is that it supports running the kernel in both Machine mode and Supervisor mode
-# THe Kernel is now divided ito two parts :
-#   1. kinit()
-#   2. kmain()
-
-# kinit()
-    #  The function of kinit does the following
-    # 1. It initializes physical memory
-    # 2. It defines the memory allocators (page allocator and byte allocator)
-    # 3. It identity maps the pages used by the kernel under a Root_table that will be used by the kernel while in supervisor mode
-    # 4. It passes the satp value to the kmain function via a return address
-
-# kmain()
-    # 1. kmain is the kernel code that runs in supervisor mode
-
-
-
-#  The main functions of this Bootloader are : 
+# THis is the third version of the Bootloader.
+# The kernel is linked in the higher half (see lds/virt.lds) but QEMU loads it, and enters it, at the start of the RAM.
+# So the bootloader first runs at the physical load address, turns the MMU on with a small boot page table,
+# and only then jumps to the link address of the kernel.
+#
+# Machine mode never runs Rust code. Everything the kernel needs from machine mode is set up here in assembly,
+# and the traps that stay in machine mode are served by the assembly shim in trap.s (asm_trap_vector).
+#
+# THe Kernel is still divided ito two parts, both run in Supervisor mode :
+#   1. kinit() : initializes the drivers and the memory allocators, and builds the kernel address space
+#   2. kmain() : runs once kinit is done, with supervisor interrupts enabled
+
+#  The main functions of this Bootloader are :
     # _choose_bootloading_HART
     # _clear_BSS_section
-    # _initialize_registers_for_kmain (prepare its environment)
-        #   for esecuting code : [stack_pointer, global_pointer]
-        #   for exception handling and interrupt handling [mstatus, mepc, mie]
-    # _call_kinit (give kernel code control)
-    # _call kmain( run kernel code in Supervisor mode)
-
+    # _initialize_machine_mode (trap delegation, the shim, PMP, paging mode probe)
+    # _boot_supervisor_trampoline (turn on the MMU and jump to the higher half)
+    # _boot_higher_half (drop the identity map, call kinit then kmain)
 
+# Every label is reached with pc-relative addressing (lla) until the jump to the higher half :
+# the code runs at its load address, and the load address is KERNEL_VIRT_OFFSET below the link address
+.set KERNEL_VIRT_OFFSET, 0xffffffc000000000
 
 # notify the assembler that we will not be using Riscv Compressed instructions
 # we need simplicity and predictability more than we need memory efficient code
 .option norvc
 
-# this is where we will store global initialized variables
-# and we have no global data yet
+# The boot root table. Sv39, three 1 GiB leaves (gigapages), enough to reach the kernel once the MMU is on :
+#   - index 2 identity maps the RAM (0x8000_0000 - 0xc000_0000), so the trampoline survives the satp write.
+#     It is dropped as soon as the code runs in the higher half
+#   - indices 256 - 259 map physical 0 - 4 GiB at KERNEL_VIRT_OFFSET : the image, the heap and the MMIO regions
+# A gigapage PTE holds PPN = physical address >> 12, shifted to bit 10. 0xcf = D | A | X | W | R | V
+# kinit replaces this table with the kernel address space (see map_kernel)
 .section .data
+.align 12
+boot_root_table:
+    .zero   2 * 8
+    .dword  (0x80000000 >> 2) | 0xcf    # 2   : identity map of the RAM
+    .zero   (256 - 3) * 8
+    .dword  (0x00000000 >> 2) | 0xcf    # 256 : physical 0 - 1 GiB (CLINT, PLIC, UART, virtio)
+    .dword  (0x40000000 >> 2) | 0xcf    # 257
+    .dword  (0x80000000 >> 2) | 0xcf    # 258 : the RAM, where the kernel is linked
+    .dword  (0xc0000000 >> 2) | 0xcf    # 259
+    .zero   (512 - 260) * 8
+
+# The satp MODE that stuck when machine mode tried Sv48 : 9 if the CPU supports Sv48, 0 otherwise.
+# Supervisor mode cannot probe it, writing an Sv48 satp there would switch translation on the spot. See sv39_mmu::probe_paging_mode
+.global BOOT_PROBED_SATP_MODE
+BOOT_PROBED_SATP_MODE: .dword 0
 
 # this is code that will get called before the kmain function
 # .text.init sections typically store startup code that sets up the environment for the rest of the code
-.section .text.init 
+.section .text.init
 
-# _start is declared as a global symbol so that the linker gets to detect it 
-# This will be the entry point of the bootloader
+# _start is declared as a global symbol so that the linker gets to detect it
+# This will be the entry point of the bootloader (the linker script exports its load address as _start_physical)
 .global _start
 _start:
     j   _choose_bootloading_HART
-    
-# The gp register currently contains the gp_memory address of the loader.
-# We need to update it to point to the kernel's gp
-# We numb out optimizations to make sure the update happens explicitly
-_fetch_kernel_global_pointer:
-    .option push    # save and disable all current assembler directives
-    .option norelax # disable code optimization, this is a delicate operation; we need no surprises
-    la gp, _global_pointer # load the address of _global_pointer label into the gp register
-    .option pop  # restore previous assembler directives
-    j _clear_BSS_section
-
 
 _choose_bootloading_HART:
     # fetch the ID of the current Hardware Thread (HART) and store it in the temporary register t1
-    csrr t1, mhartid 
+    csrr t1, mhartid
     bnez t1, _make_HART_sleep # If HART ID is not ZERO, make that HART sleep.
-                             # If HART IS is zero, _fetch_kernel_global_pointer
-    j   _fetch_kernel_global_pointer  # after choosing the HART, we move on to configure essential registers 
-                                      # [gp, sp, ]
-    
+    j   _clear_BSS_section    # If HART IS is zero, move on
 
-# this does not completely shut down the HART
+
+# this does not completely shut down the HART. It is used in machine mode (the other harts) and in supervisor mode (after kmain)
 _make_HART_sleep:
     wfi                 # power off and wait for an interrupt
     j _make_HART_sleep  # continuously make HART sleep, we are running a single_core OS
 
 # the bootloader needs to make sure that all uninitialized dlobal values of...
-# ...the kernel are ZEROED out
+# ...the kernel are ZEROED out. The MMU is off : the bss is reached at its load address
 _clear_BSS_section:
-    la a1, _bss_start
-    la a2, _bss_end
+    lla a1, _bss_start
+    lla a2, _bss_end
     j _clear_BSS_section_loop
 
 _clear_BSS_section_loop:
-    sd      zero, (a1)                          # store z mepc, mieero in the 64bit memory space referenced by a1
+    sd      zero, (a1)                          # store zero in the 64bit memory space referenced by a1
     addi    a1, a1, 8                           # increment the address by 64 bits. (8 bytes)
     bltu    a1, a2, _clear_BSS_section_loop     # loop until we reach the last address of the bss section
-    j       _initialize_registers_for_kinit     # if we have zeroed out the BSS section, _initialize_registers_for_kinit()
-
-_initialize_registers_for_kinit:
-    la		sp, _stack_end                          # setup the stack pointer
-    li		t0, (0b11 << 11) | (1 << 7) | (1 << 3)  # Set MPP field to 11 (Machine Mode), kinit will execute in Machine mode
-                                                    # Bit 7, sets MPIE bit to 0 ; once we get into kinit, we will not need any interference
-                                                    # Bit 3, Sets the MIE bit to 0 ; '_initialize_registers_for_kinit' does not need interference 
-    csrw	mstatus, t0
-    li t3, 0b1111111111111111
-    csrw mie, t3
-
-
-    # set kinit to be the value that will be pasted tp the PC counter after calling mret
-    la		t1, kinit
-	csrw	mepc, t1   
-
-    # set the Machine trap vector
-    la		t2, asm_trap_vector
-	csrw	mtvec, t2  
-
-    #  set the return address to point to the address of the  "_initialize_registers_for_kmain" code. After kinit does its thing, it goes to this place
-    la      ra, _initialize_environment_for_kmain
-
-    # call kmain (indirectly, this is because mret will make the cpu program counter to point to the value in mepc(kmain))
-    mret
-    # mret makes PC point to mepc(kinit)
-    # sets MIE to value in the MPIE 
-    # sets MPIE to the previous 1
-    # sets the CPU priviledge mode to mstatus.mpp
-
-_initialize_environment_for_kmain:
-    # this code is meant to prepare the CPU to execute the kernel in supervisor mode
-    # registers like the global pointer, stackpointer, threadpointer can remain the way they are
-    # However, the return address, will point to "Shutdown". Shutdown in this case means making the HART sleep endlessly.
-    # I currently do not know how to make a CPU to shut down for Real real. Truly shutting down looks like dark magic. Magic that will be learned another time
-
-    # set the mstatus register, prepare it to skip to supervisor mode
-    li      t0, (0b01 << 11)|(1 << 7)|(1 << 5)
-    csrw    mstatus, t0 
-
-
-    # Enable All Interrupts. All interrupts will be handled by the Machine mode
-    li t3, 0b1111111111111111
-    csrw mie, t3
-
-    # Enable all exceptions by setting all of th 16 bits of medeleg to 1
-    # Now all exceptions that happen in bot Supervisor and Usermode will be handled in Superisor mode
-    # li      t2, (1 << 1)| (1 << 5) | (1 << 9)
-    # csrw    medeleg, t2 
-
-    # Set the stvec register
-    # in our case, the stvec will still point to the address of the vector that was called  while we were in machine mode
-    # It's the same thing. The code in it was not Machine-mode-specific
-    la      t0, asm_trap_vector
-    csrw    mtvec, t0  
-
-    # Define the start point of kernel 
-    la      t0, kmain
+    j       _initialize_machine_mode            # if we have zeroed out the BSS section, _initialize_machine_mode()
+
+_initialize_machine_mode:
+    csrr    tp, mhartid                             # keep the hart ID in tp, supervisor mode cannot read mhartid (see riscv::hart_id)
+
+    # the machine mode shim (trap.s). It saves the few registers it uses in a per-hart area, pointed at by mscratch
+    lla     t0, machine_scratch_areas
+    slli    t1, tp, 5                               # 32 bytes per hart, see delegation.rs
+    add     t0, t0, t1
+    csrw    mscratch, t0
+    lla     t0, asm_trap_vector
+    csrw    mtvec, t0
+
+    # delegate every exception except SupervisorEnvironmentCall (9), which is how supervisor mode calls the shim.
+    # 0-8 : misaligned/faulting accesses, IllegalInstruction, Breakpoint, UserEnvironmentCall. 12, 13, 15 : page faults
+    li      t0, 0x1ff | (1 << 12) | (1 << 13) | (1 << 15)
+    csrw    medeleg, t0
+    # delegate the supervisor interrupts : software (1), timer (5), external (9)
+    li      t0, (1 << 1) | (1 << 5) | (1 << 9)
+    csrw    mideleg, t0
+    # MSIE : another hart wants a TLB shootdown, the shim passes it on as a SupervisorSoftwareInterrupt.
+    # MTIE stays off until supervisor mode arms the timer (SHIM_CALL_SET_TIMER)
+    li      t0, (1 << 1) | (1 << 3) | (1 << 5) | (1 << 9)
+    csrw    mie, t0
+
+    # let supervisor mode access the whole physical address space : one NAPOT PMP region, RWX
+    li      t0, -1
+    srli    t0, t0, 10
+    csrw    pmpaddr0, t0
+    li      t0, 0x1f
+    csrw    pmpcfg0, t0
+
+    # probe Sv48 : satp is WARL, an unsupported MODE does not stick. Machine mode accesses are not translated
+    li      t0, (9 << 60)
+    csrw    satp, t0
+    csrr    t1, satp
+    csrw    satp, zero
+    srli    t1, t1, 60
+    lla     t0, BOOT_PROBED_SATP_MODE
+    sd      t1, 0(t0)
+
+    # drop to supervisor mode : MPP = 01, MPIE = 0, FS = Off (the first FP instruction traps, see floating_point.rs)
+    li      t0, (0b01 << 11)
+    csrw    mstatus, t0
+    lla     t0, _boot_supervisor_trampoline
     csrw    mepc, t0
-    la      ra, _make_HART_sleep  // the kernel shuts down after execution
-    
-    # Update the value in the satp. The satp value was returned by kinit() via register a0
-    # sfence.vma 
-    # csrw    satp, a0 
-    # sfence.vma 
-    # force the CPU to use a fresh satp and corresponding translation tables. This instruction tells the CPU :
-    # "Do not use cached information, use new and up-to-date information. Refresh things"
-    # What sfence.vma does in the backgroud is to fulfill all "write" operations that are pending. Our ain is to make sure
-    # that the above instruction "csrw satp, a0" gets completed before calling sret
-
     mret
-    
-
-
-
 
+# Supervisor mode, MMU still off
+_boot_supervisor_trampoline:
+    # turn on the boot page table. The next instruction is fetched through its identity map
+    lla     t0, boot_root_table
+    srli    t0, t0, 12
+    li      t1, (8 << 60)                           # Sv39
+    or      t0, t0, t1
+    sfence.vma
+    csrw    satp, t0
+    sfence.vma
+
+    # jump to the link address
+    lla     t0, _boot_higher_half
+    li      t1, KERNEL_VIRT_OFFSET
+    add     t0, t0, t1
+    jr      t0
+
+_boot_higher_half:
+    # from here on, la resolves to link addresses
+    .option push    # save and disable all current assembler directives
+    .option norelax # disable code optimization, this is a delicate operation; we need no surprises
+    la      gp, _global_pointer
+    .option pop     # restore previous assembler directives
+    la      sp, _stack_end
 
+    # a trap before kinit installs the real supervisor trap vector can only be a bug : park the hart
+    la      t0, _make_HART_sleep
+    csrw    stvec, t0
 
+    # nothing runs at the load address anymore : drop the identity map
+    la      t0, boot_root_table
+    sd      zero, 16(t0)                            # index 2
+    sfence.vma
 
-    
+    call    kinit
 
+    # Bit 1 sets the SIE bit : supervisor mode takes the delegated interrupts as soon as kmain starts
+    li      t0, (1 << 1)
+    csrs    sstatus, t0
+    call    kmain
 
+    # the kernel shuts down after execution
+    j       _make_HART_sleep
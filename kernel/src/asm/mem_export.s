@@ -20,6 +20,10 @@ RODATA_START: .dword _rodata_start
 .global RODATA_END
 RODATA_END: .dword _rodata_end
 
+.global KSYMTAB_START
+KSYMTAB_START: .dword _ksymtab_start
+.global KSYMTAB_END
+KSYMTAB_END: .dword _ksymtab_end
 .global DATA_START
 DATA_START: .dword _data_start
 
@@ -32,6 +36,9 @@ BSS_START: .dword _bss_start
 .global BSS_END
 BSS_END: .dword _bss_end
 
+.global KERNEL_STACK_GUARD
+KERNEL_STACK_GUARD: .dword _stack_guard
+
 .global KERNEL_STACK_START
 KERNEL_STACK_START: .dword _stack_start 
 
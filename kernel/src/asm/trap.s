@@ -1,8 +1,7 @@
-
 # trap.S
-# This module contains the asm_trap_vector.
-# The asm trap vector is a function that gets called by the CPU when an interrupt or exception occurs
-# So the asm trap vector is stored in the mtvec register of the cpu.	
+# This module contains the trap vectors.
+# 1. asm_trap_vector (mtvec) is the machine mode shim. It is pure assembly and runs without translation, at the load address of the image
+# 2. asm_supervisor_trap_vector (stvec) saves the context into its trap frame and hands it over to the Rust handlers
 
 
 .option norvc
@@ -10,7 +9,12 @@
 .set NUM_GP_REGS, 32  # Number of registers per context
 .set NUM_FP_REGS, 32
 .set REG_SIZE, 8   # Register size (in bytes)
-.set MAX_CPUS, 8   # Maximum number of CPUs
+
+# the machine mode shim : the calls it serves, and the CLINT registers it drives (physical addresses, machine mode is not translated)
+.set SHIM_CALL_SET_TIMER, 0x54494d45   # "TIME", see interrupt_and_exception_handling::set_timer
+.set CLINT_MSIP, 0x02000000            # one 32-bit register per hart
+.set CLINT_MTIMECMP, 0x02004000        # one 64-bit register per hart
+.set UART_BASE, 0x10000000
 
 # Use macros for saving and restoring multiple registers
 .macro save_gp i, basereg=t6
@@ -27,82 +31,246 @@
 .endm
 
 
+# The save areas of the machine mode shim (t0, t1, t2 and t6), one per hart, are machine_scratch_areas.
+# They are sized with MAX_HARTS, so they live on the Rust side (see delegation.rs). booze.s points mscratch at the area of the hart
+
+.section .rodata
+machine_fatal_message:
+	.string "\nmachine mode : unexpected trap, the hart stops. mcause = 0x"
+
 
 .section .text
 .global asm_trap_vector
 # This must be aligned by 4 since the last two bits
 # of the mtvec register do not contribute to the address
 # of this vector.
+# 
+# The machine mode shim. Everything else is delegated to supervisor mode (see booze.s), so only three traps end up here :
+# 1. MachineTimerInterrupt : mtimecmp only raises machine timer interrupts. The shim forwards it as a SupervisorTimerInterrupt (mip.STIP)
+#    and masks the machine timer, otherwise it would fire again right away
+# 2. SupervisorEnvironmentCall : the shim calls. SHIM_CALL_SET_TIMER (a7) writes mtimecmp (a0), clears mip.STIP and unmasks the machine timer.
+#    Unknown calls return -1 in a0
+# 3. MachineSoftwareInterrupt : cannot be delegated. Another hart wants a TLB shootdown : the shim clears MSIP and raises a SupervisorSoftwareInterrupt (mip.SSIP)
+# Any other trap is a bug. It gets reported straight to the UART and the hart stops.
+#
+# The shim runs without a stack and only uses t0, t1, t2 and t6, saved in the area that mscratch points at.
+# It never touches the FP registers, so mstatus.FS is left alone : the lazy FP state of supervisor mode stays valid
 .align 4  # the mtvec register requires that the address stored it its BASE to be aligned to a multiple of 4. ie Last 2 zeroes get truncated by MUST
 asm_trap_vector:
-	j save_context_to_trap_frame
+	csrrw	t6, mscratch, t6
+	sd		t0, 0(t6)
+	sd		t1, 8(t6)
+	sd		t2, 16(t6)
+
+	csrr	t0, mcause
+	li		t1, (1 << 63) | 7
+	beq		t0, t1, machine_timer_interrupt
+	li		t1, (1 << 63) | 3
+	beq		t0, t1, machine_software_interrupt
+	li		t1, 9
+	beq		t0, t1, machine_shim_call
+	j		machine_fatal_trap
 
-# The function below is purposely written in assembly to avoid any small unexpected changes among the CPU registers.
-# If we wrote it in Rust, some register values would change according to the assembly code that the compiler would generate.  
-# That is not predictable at ALL.
+machine_timer_interrupt:
+	li		t0, (1 << 7)
+	csrc	mie, t0					# MTIE
+	li		t0, (1 << 5)
+	csrs	mip, t0					# STIP
+	j		machine_trap_return
 
-# The function saves all regular register values to the trapframe whose address gets been stored in the mstatus register
-# We use macros to do this
-save_context_to_trap_frame:
+machine_software_interrupt:
+	csrr	t0, mhartid
+	slli	t0, t0, 2
+	li		t1, CLINT_MSIP
+	add		t0, t0, t1
+	sw		zero, 0(t0)
+	li		t0, (1 << 1)
+	csrs	mip, t0					# SSIP
+	j		machine_trap_return
 
+machine_shim_call:
+	li		t1, SHIM_CALL_SET_TIMER
+	bne		a7, t1, machine_unknown_shim_call
+	csrr	t0, mhartid
+	slli	t0, t0, 3
+	li		t1, CLINT_MTIMECMP
+	add		t0, t0, t1
+	sd		a0, 0(t0)
+	li		t0, (1 << 5)
+	csrc	mip, t0					# STIP
+	li		t0, (1 << 7)
+	csrs	mie, t0					# MTIE
+	j		machine_skip_ecall
+machine_unknown_shim_call:
+	li		a0, -1
+machine_skip_ecall:
+	csrr	t0, mepc
+	addi	t0, t0, 4
+	csrw	mepc, t0
+
+machine_trap_return:
+	ld		t0, 0(t6)
+	ld		t1, 8(t6)
+	ld		t2, 16(t6)
 	csrrw	t6, mscratch, t6
+	mret
+
+# prints the message and mcause in hex, then parks the hart. The UART is written through its physical address.
+# The hart never returns from here, so this path uses any register it likes
+machine_fatal_trap:
+	li		t1, UART_BASE
+	lla		t0, machine_fatal_message
+machine_fatal_print_message:
+	lbu		t2, 0(t0)
+	beqz	t2, machine_fatal_print_cause
+	sb		t2, 0(t1)
+	addi	t0, t0, 1
+	j		machine_fatal_print_message
+machine_fatal_print_cause:
+	csrr	t0, mcause
+	li		t6, 60
+machine_fatal_print_digit:
+	srl		t2, t0, t6
+	andi	t2, t2, 0xf
+	addi	t2, t2, '0'
+	li		a7, '9'
+	ble		t2, a7, machine_fatal_digit_ready
+	addi	t2, t2, 'a' - '9' - 1
+machine_fatal_digit_ready:
+	sb		t2, 0(t1)
+	addi	t6, t6, -4
+	bgez	t6, machine_fatal_print_digit
+machine_fatal_hang:
+	wfi
+	j		machine_fatal_hang
+
+
+
+# The supervisor trap vector. It is stored in the stvec register and receives the traps delegated by medeleg/mideleg
+# The frame comes from sscratch : the frame of the context that was running (see interrupt_and_exception_handling)
+.global asm_supervisor_trap_vector
+.align 4
+asm_supervisor_trap_vector:
+	j save_context_to_supervisor_trap_frame
+
+save_context_to_supervisor_trap_frame:
+
+	csrrw	t6, sscratch, t6
 	.set 	i, 1
 	.rept	30
 		save_gp	%i
 		.set	i, i+1
 	.endr
 
-	# Save the actual t6 register, which we swapped into
-	# mscratch
+	# Save the actual t6 register, which we swapped into sscratch
 	mv		t5, t6
-	csrr	t6, mscratch
+	csrr	t6, sscratch
 	save_gp 31, t5
 
-	# Restore the kernel trap frame into mscratch
-	csrw	mscratch, t5
+	# Restore the supervisor trap frame into sscratch
+	csrw	sscratch, t5
 
-	# store the control status registers to the trapframe
-	csrr	t0, mscratch   # use t0 as the base register
-	csrr	t1, satp 
+	# store the supervisor control status registers to the trapframe
+	csrr	t0, sscratch
+	csrr	t1, satp
 	sd		t1, 512(t0)
-	csrr 	t1, mstatus
+	csrr 	t1, sstatus
 	sd		t1, 520(t0)
-	csrr 	t1, mepc
+	csrr 	t1, sepc
 	sd		t1, 528(t0)
-	csrr 	t1, mie
+	csrr 	t1, sie
 	sd		t1, 536(t0)
-	csrr 	t1, mcause
+	csrr 	t1, scause
 	sd		t1, 544(t0)
-	csrr 	t1, mtval
+	csrr 	t1, stval
 	sd		t1, 552(t0)
 
-	# rust_trap_handler returns the address of the next instruction
-	call	ra, rust_trap_handler
+	# switch to the kernel stack of the context (kernel_sp). The interrupted sp comes back with the other registers
+	# A trap taken while a handler runs lands in a nested frame (kernel_sp == 0, see nesting.rs) : sp is kept,
+	# the nested handler runs below the one it interrupted
+	ld		t1, 568(t0)
+	beqz	t1, supervisor_trap_keep_stack
+	mv		sp, t1
+supervisor_trap_keep_stack:
+
+	# lazy floating point : save the FP registers only if the context changed them since the last save (sstatus.FS == Dirty)
+	csrr	t1, sstatus
+	srli	t1, t1, 13
+	andi	t1, t1, 3
+	li		t2, 3
+	bne		t1, t2, supervisor_trap_skip_fp_save
+	mv		a0, t0
+	call	ra, asm_save_fp_state
+	csrr	t0, sscratch
+	li		t1, (1 << 14)
+	csrc	sstatus, t1				# Dirty -> Clean, the registers match the frame
+	ld		t1, 584(t0)
+	addi	t1, t1, 1
+	sd		t1, 584(t0)				# fp_saves
+supervisor_trap_skip_fp_save:
 
-	# update the mepc to be equal to th returned value
-	csrw	mepc, a0 
+	# rust_supervisor_trap_handler takes the trap frame of the running context (a0) and returns the address of the next instruction
+	mv		a0, t0
+	call	ra, rust_supervisor_trap_handler
 
-	# restore the non-priviledged registers
-	# Now load the trap frame back into t6
-	csrr	t6, mscratch
+	csrw	sepc, a0
 
-	# Restore all GP registers
+	# if the handler itself dirtied the FP registers, put the values of the context back
+	csrr	t1, sstatus
+	srli	t1, t1, 13
+	andi	t1, t1, 3
+	li		t2, 3
+	bne		t1, t2, supervisor_trap_skip_fp_restore
+	csrr	a0, sscratch
+	call	ra, asm_load_fp_state
+	li		t1, (1 << 14)
+	csrc	sstatus, t1
+supervisor_trap_skip_fp_restore:
+
+	csrr	t6, sscratch
 	.set	i, 1
 	.rept	31
 		load_gp %i
 		.set	i, i+1
 	.endr
 
-	mret
+	sret
+
 
+# Saves f0-f31 and fcsr into the trap frame whose address is in a0. FP must be on (FS != Off)
+.global asm_save_fp_state
+asm_save_fp_state:
+	.set	i, 0
+	.rept	NUM_FP_REGS
+		save_fp	%i, a0
+		.set	i, i+1
+	.endr
+	frcsr	t1
+	sd		t1, 576(a0)
+	ret
 
+# Loads f0-f31 and fcsr from the trap frame whose address is in a0. FP must be on (FS != Off)
+.global asm_load_fp_state
+asm_load_fp_state:
+	.set	i, 0
+	.rept	NUM_FP_REGS
+		load_fp	%i, a0
+		.set	i, i+1
+	.endr
+	ld		t1, 576(a0)
+	fscsr	t1
+	ret
 
+# Calls the machine mode shim from supervisor mode. a0 holds the argument, a1 the shim call number
+# The shim expects the call number in a7 (see asm_trap_vector) and returns its result in a0
+.global asm_shim_call
+asm_shim_call:
+	mv		a7, a1
+	ecall
+	ret
 
 # .global make_syscall
 # make_syscall:
 # 	ecall
 # 	ret
 
-
-
-# msxratch = 2147496632
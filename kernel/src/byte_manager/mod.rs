@@ -19,6 +19,7 @@ use crate::page_manager::alloc as zalloc;
 use crate::page_manager::align as align_val;
 use crate::page_manager::PAGE_SIZE;
 use crate::sv39_mmu::Table;
+use crate::map_kernel::phys_to_virt;
 use crate::{print, println};
 use core::{mem::size_of, ptr::null_mut};
 
@@ -106,14 +107,14 @@ pub fn init_kernel_byte_allocation() {
 		KMEM_ALLOC = 512;  // this is the number of pages that are dedicated to the lernel heap
 		let k_alloc_result = zalloc(KMEM_ALLOC);  // actualize those pages and zero them out
 		assert!(!k_alloc_result.is_err());                      // make sure the pages were actually allocated
-        let first_address:usize = k_alloc_result.unwrap();
+        let first_address:usize = phys_to_virt(k_alloc_result.unwrap().start_address()).as_u64() as usize; // reached through the direct map
 		KMEM_HEAD = first_address as *mut AllocList;			// Create the first Alloc List at the very Top
 		(*KMEM_HEAD).set_free();						// set the first allocList to be free
 		(*KMEM_HEAD).set_size(KMEM_ALLOC * PAGE_SIZE); // make that first allocList declare that the rest of the bytes below it are free ((512 x 4096) - 1)
 
         // allocate the Page Table that will be used
         let root_table_adress = zalloc(1).expect("unable to allocate space for the kernel root table");
-		KMEM_PAGE_TABLE = root_table_adress as *mut Table;  // create The root Page table 
+		KMEM_PAGE_TABLE = phys_to_virt(root_table_adress.start_address()).as_u64() as *mut Table;  // create The root Page table 
 	}
 }
 
@@ -0,0 +1,219 @@
+//! Crash reports.
+//!
+//! Both fatal paths end here : an exception that cannot be recovered from, and a panic.
+//! A report holds the cause, the csrs, the 31 general purpose registers of the TrapFrame and a backtrace.
+//!
+//! The reporter assumes that the rest of the kernel may be broken :
+//! - it writes straight to the UART registers (RawConsole). No heap, no stdout buffer, no UartDevice
+//! - it never waits forever on the UART
+//! - a crash inside a crash report is reported in one line, then the hart stops
+//!
+//! Code addresses are printed as function+offset when the image carries its symbol table (see ksymtab).
+//!
+//! The backtrace follows the frame pointers (the kernel is built with -Cforce-frame-pointers=yes).
+//! With frame pointers, s0 holds the stack pointer of the caller : the return address is stored at s0-8 and
+//! the s0 of the caller at s0-16.
+
+mod tests;
+
+use crate::interrupt_and_exception_handling::TrapFrame;
+use crate::map_kernel;
+use crate::ksymtab;
+use crate::riscv;
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const UART_ADDRESS : usize = map_kernel::direct_map_address(0x1000_0000);
+const UART_LSR_OFFSET : usize = 5;
+const UART_LSR_DATA_READY : u8 = 1 << 0; // a received byte is waiting
+const UART_LSR_THR_EMPTY : u8 = 1 << 5; // the transmitter can take a new byte
+const UART_SPIN_LIMIT : usize = 100_000;  // how long to wait for the transmitter before dropping a byte
+const SSTATUS_SIE : u64 = 1 << 1;
+
+/// The deepest backtrace that gets printed
+pub const MAX_BACKTRACE_DEPTH : usize = 32;
+
+/// The ABI names of x0 - x31, in TrapFrame.regs order
+pub const REGISTER_NAMES : [&str; 32] = ["zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+                                         "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+                                         "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+                                         "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6"];
+
+static CRASHING : AtomicBool = AtomicBool::new(false);
+
+/// A console that writes to the UART registers directly, byte by byte
+pub struct RawConsole;
+
+impl Write for RawConsole{
+    fn write_str(&mut self, out: &str) -> fmt::Result{
+        let uart = UART_ADDRESS as *mut u8;
+        for byte in out.bytes() {
+            let mut spins = 0;
+            while unsafe { uart.add(UART_LSR_OFFSET).read_volatile() } & UART_LSR_THR_EMPTY == 0 && spins < UART_SPIN_LIMIT {
+                spins += 1;
+                core::hint::spin_loop();
+            }
+            unsafe { uart.write_volatile(byte); }
+        }
+        Ok(())
+    }
+}
+
+impl RawConsole{
+    /// The next byte received by the UART, if there is one. Never waits
+    pub fn read_byte(&mut self) -> Option<u8>{
+        let uart = UART_ADDRESS as *mut u8;
+        if unsafe { uart.add(UART_LSR_OFFSET).read_volatile() } & UART_LSR_DATA_READY == 0 { return None; }
+        Some(unsafe { uart.read_volatile() })
+    }
+}
+
+/// The name of a trap cause, as found in mcause/scause
+pub fn cause_name(cause: usize) -> &'static str{
+    let is_interrupt = cause >> 63 == 1;
+    match (is_interrupt, cause & !(1 << 63)) {
+        (true, 0) => "UserSoftwareInterrupt",
+        (true, 1) => "SupervisorSoftwareInterrupt",
+        (true, 3) => "MachineSoftwareInterrupt",
+        (true, 4) => "UserTimerInterrupt",
+        (true, 5) => "SupervisorTimerInterrupt",
+        (true, 7) => "MachineTimerInterrupt",
+        (true, 8) => "UserExternalInterrupt",
+        (true, 9) => "SupervisorExternalInterrupt",
+        (true, 11) => "MachineExternalInterrupt",
+        (true, _) => "UnknownInterrupt",
+        (false, 0) => "InstructionAddressMisaligned",
+        (false, 1) => "InstructionAccessFault",
+        (false, 2) => "IllegalInstruction",
+        (false, 3) => "Breakpoint",
+        (false, 4) => "LoadAddressMisaligned",
+        (false, 5) => "LoadAccessFault",
+        (false, 6) => "StoreAddressMisaligned",
+        (false, 7) => "StoreAccessFault",
+        (false, 8) => "UserEnvironmentCall",
+        (false, 9) => "SupervisorEnvironmentCall",
+        (false, 11) => "MachineEnvironmentCall",
+        (false, 12) => "InstructionPageFault",
+        (false, 13) => "LoadPageFault",
+        (false, 15) => "StorePageFault",
+        (false, _) => "UnknownException",
+    }
+}
+
+/// Iterator over the return addresses of a frame pointer chain, innermost first.
+/// Stops at a frame pointer that is misaligned, outside the kernel RAM, or that does not move up the stack
+pub struct Backtrace{
+    frame_pointer : usize,
+    depth : usize
+}
+
+impl Backtrace{
+    pub fn new(frame_pointer: usize) -> Self{
+        Backtrace { frame_pointer, depth: 0 }
+    }
+
+    /// Starts from the frame pointer of the caller
+    pub fn here() -> Self{
+        let frame_pointer : usize;
+        unsafe { asm!("mv  {}, s0", out(reg) frame_pointer); }
+        Backtrace::new(frame_pointer)
+    }
+}
+
+// true if a frame record (return address and previous frame pointer) can be read below the frame pointer
+fn is_sane_frame_pointer(frame_pointer: usize) -> bool{
+    frame_pointer % 8 == 0 && frame_pointer >= 16
+        && map_kernel::is_in_kernel_ram((frame_pointer - 16) as u64)
+        && map_kernel::is_in_kernel_ram((frame_pointer - 1) as u64)
+}
+
+impl Iterator for Backtrace{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize>{
+        if self.depth >= MAX_BACKTRACE_DEPTH || is_sane_frame_pointer(self.frame_pointer) == false { return None; }
+        let return_address = unsafe { ((self.frame_pointer - 8) as *const usize).read_volatile() };
+        let previous_frame_pointer = unsafe { ((self.frame_pointer - 16) as *const usize).read_volatile() };
+        if return_address == 0 { return None; }
+
+        // the stack grows down : the frame of the caller is always higher up
+        self.frame_pointer = if previous_frame_pointer > self.frame_pointer { previous_frame_pointer } else { 0 };
+        self.depth += 1;
+        Some(return_address)
+    }
+}
+
+/// Writes a code address, followed by function+offset if the symbol table knows the address
+pub fn write_code_address(console: &mut dyn Write, address: usize) -> fmt::Result{
+    match ksymtab::lookup(address as u64) {
+        Some(location) => write!(console, "0x{:016x} {}", address, location),
+        None => write!(console, "0x{:016x}", address)
+    }
+}
+
+fn print_backtrace(console: &mut RawConsole, backtrace: Backtrace){
+    let _ = writeln!(console, "Backtrace :\r");
+    for (depth, return_address) in backtrace.enumerate() {
+        let _ = write!(console, "\t #{:<2} ", depth);
+        let _ = write_code_address(console, return_address);
+        let _ = write!(console, "\r\n");
+    }
+}
+
+// marks the start of a report. Returns false if a report is already running on some hart
+fn begin_report(console: &mut RawConsole) -> bool{
+    if CRASHING.swap(true, Ordering::SeqCst) == true {
+        let _ = writeln!(console, "\r\n!!! crashed while reporting a crash, stopping\r");
+        return false;
+    }
+    true
+}
+
+/// Prints the report of a trap that could not be handled
+pub fn report_trap_crash(trap_frame: &TrapFrame, reason: &dyn fmt::Debug){
+    let mut console = RawConsole;
+    if begin_report(&mut console) == false { return; }
+
+    let _ = writeln!(console, "\r\n========== KERNEL CRASH ==========\r");
+    let _ = writeln!(console, "Reason : {:?}\r", reason);
+    let _ = writeln!(console, "Cause  : {} (cause 0x{:x}) on hart {}\r", cause_name(trap_frame.mcause), trap_frame.mcause, trap_frame.hartid);
+    let _ = writeln!(console, "epc    : 0x{:016x}    tval : 0x{:016x}\r", trap_frame.mepc, trap_frame.mtval);
+    let _ = writeln!(console, "status : 0x{:016x}    satp : 0x{:016x}\r", trap_frame.mstatus, trap_frame.satp);
+    print_registers(&mut console, trap_frame);
+
+    // the faulting instruction first, then the chain of callers
+    let _ = write!(console, "\t at ");
+    let _ = write_code_address(&mut console, trap_frame.mepc);
+    let _ = write!(console, " (epc)\r\n");
+    print_backtrace(&mut console, Backtrace::new(trap_frame.regs[8]));
+    let _ = writeln!(console, "==================================\r");
+}
+
+/// Prints the report of a panic : the location and the message, then the backtrace of the panicking code
+pub fn report_panic(panic_info: &PanicInfo){
+    let mut console = RawConsole;
+    if begin_report(&mut console) == false { return; }
+
+    let _ = writeln!(console, "\r\n========== KERNEL PANIC ==========\r");
+    let _ = writeln!(console, "{}\r", panic_info);
+    print_backtrace(&mut console, Backtrace::here());
+    let _ = writeln!(console, "==================================\r");
+}
+
+// x1 - x31, three per line
+fn print_registers(console: &mut RawConsole, trap_frame: &TrapFrame){
+    for index in 1..32 {
+        let _ = write!(console, "{:>4} : 0x{:016x}   ", REGISTER_NAMES[index], trap_frame.regs[index]);
+        if index % 3 == 0 || index == 31 { let _ = write!(console, "\r\n"); }
+    }
+}
+
+/// Stops the hart for good. Interrupts get disabled first : wfi would return for each of them, and their handlers must not run anymore
+pub fn halt() -> !{
+    riscv::sstatus_clear_bits(SSTATUS_SIE);
+    loop {
+        unsafe { asm!("wfi"); }
+    }
+}
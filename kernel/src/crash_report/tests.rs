@@ -0,0 +1,44 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use super::{cause_name, Backtrace};
+
+#[test_case]
+pub fn crash_report_test_switch(){
+    println!("\n---------  Running crash_report tests  ---------\n");
+    test_cause_names();
+    test_backtrace_follows_frame_records();
+    test_backtrace_stops_on_garbage_frame_pointer();
+}
+
+fn test_cause_names(){
+    let res = (cause_name(13), cause_name((1 << 63) | 5), cause_name(14), cause_name((1 << 63) | 2));
+    let suc_msg = "test_cause_names    ....   [OK]";
+    let fail_msg = "test_cause_names   ....    [FAIL]";
+    custom_assert(("LoadPageFault", "SupervisorTimerInterrupt", "UnknownException", "UnknownInterrupt"), res, suc_msg, fail_msg);
+}
+
+// two frame records on the stack : each frame pointer has its return address 8 bytes below and the previous frame pointer 16 bytes below
+fn test_backtrace_follows_frame_records(){
+    let mut fake_stack = [0usize; 8];
+    let base = fake_stack.as_mut_ptr();
+    let inner_frame_pointer = unsafe { base.add(2) } as usize;
+    unsafe {
+        base.add(0).write(base.add(6) as usize); // the outer frame pointer
+        base.add(1).write(0x8000_1111);          // return address of the inner frame
+        base.add(4).write(0);                    // end of the chain
+        base.add(5).write(0x8000_2222);          // return address of the outer frame
+    }
+
+    let mut backtrace = Backtrace::new(inner_frame_pointer);
+    let res = (backtrace.next(), backtrace.next(), backtrace.next());
+    let suc_msg = "test_backtrace_follows_frame_records    ....   [OK]";
+    let fail_msg = "test_backtrace_follows_frame_records   ....    [FAIL]";
+    custom_assert((Some(0x8000_1111), Some(0x8000_2222), None), res, suc_msg, fail_msg);
+}
+
+fn test_backtrace_stops_on_garbage_frame_pointer(){
+    let res = (Backtrace::new(0).next(), Backtrace::new(0x1234_5671).next(), Backtrace::new(0x10).next());
+    let suc_msg = "test_backtrace_stops_on_garbage_frame_pointer    ....   [OK]";
+    let fail_msg = "test_backtrace_stops_on_garbage_frame_pointer   ....    [FAIL]";
+    custom_assert((None, None, None), res, suc_msg, fail_msg);
+}
@@ -0,0 +1,365 @@
+//! GDB remote serial protocol stub.
+//!
+//! QEMU's gdbstub sees the machine, the stub sees the kernel : the registers of the interrupted context (TrapFrame),
+//! and the memory through the address space that was active at the trap.
+//! The "gdb" monitor command hands the current stop over to the stub, then every breakpoint and step goes to GDB until it detaches.
+//!
+//! The stub talks over a DebugChannel. The serial console (crash_report::RawConsole) is the only one for now,
+//! so print! stays silent while GDB is attached : a stray line would break the packet stream.
+//! GDB connects to the serial port of QEMU, for example with `-serial tcp::1234,server` and `target remote :1234`.
+//!
+//! Supported packets : ? g G p P m M c s Z0 z0 D k H qSupported qAttached qC qfThreadInfo qsThreadInfo.
+//! Breakpoints and steps use the existing exception path : Z0 plants a c.ebreak, s uses the temporary breakpoints of stepping.rs.
+//! There is a single thread for now, the interrupted context. Kernel threads will be listed by qfThreadInfo once they exist.
+
+use super::{stepping, Resume};
+use crate::riscv;
+use crate::sv39_mmu::is_accessible;
+use crate::interrupt_and_exception_handling::TrapFrame;
+use crate::crash_report::RawConsole;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The largest packet that the stub accepts (advertised to GDB with qSupported)
+pub const PACKET_SIZE : usize = 1024;
+/// The most software breakpoints that can be planted at once
+pub const MAX_BREAKPOINTS : usize = 16;
+
+// register numbers of the RISC-V target description
+const PC_REGISTER : usize = 32;
+const SIGTRAP : u8 = 5;
+const SEND_RETRIES : usize = 8;
+
+static ATTACHED : AtomicBool = AtomicBool::new(false);
+
+/// A byte stream to GDB
+pub trait DebugChannel{
+    fn read_byte(&mut self) -> Option<u8>;
+    fn write_byte(&mut self, byte: u8);
+}
+
+impl DebugChannel for RawConsole{
+    fn read_byte(&mut self) -> Option<u8>{ RawConsole::read_byte(self) }
+    fn write_byte(&mut self, byte: u8){ let _ = core::fmt::Write::write_char(self, byte as char); }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint{
+    address : usize,
+    original : u16
+}
+
+static mut BREAKPOINTS : [Option<Breakpoint>; MAX_BREAKPOINTS] = [None; MAX_BREAKPOINTS];
+
+/// true while GDB is attached : breakpoints and steps are reported to GDB instead of the monitor prompt
+pub fn is_attached() -> bool{ ATTACHED.load(Ordering::Relaxed) }
+
+/// A packet being built or received. Holds the data between '$' and '#'
+pub struct Packet{
+    buffer : [u8; PACKET_SIZE],
+    length : usize
+}
+
+impl Packet{
+    pub const fn new() -> Self{ Packet { buffer: [0; PACKET_SIZE], length: 0 } }
+
+    pub fn as_bytes(&self) -> &[u8]{ &self.buffer[..self.length] }
+
+    pub fn clear(&mut self){ self.length = 0; }
+
+    /// Appends a byte. Bytes past PACKET_SIZE are dropped
+    pub fn push(&mut self, byte: u8){
+        if self.length < PACKET_SIZE { self.buffer[self.length] = byte; self.length += 1; }
+    }
+
+    pub fn push_str(&mut self, text: &str){
+        for byte in text.bytes() { self.push(byte); }
+    }
+
+    pub fn push_hex_byte(&mut self, byte: u8){
+        for digit in hex_digits(byte).iter() { self.push(*digit); }
+    }
+
+    /// A register value : 8 bytes, target (little endian) byte order
+    pub fn push_register(&mut self, value: usize){
+        for byte in (value as u64).to_le_bytes().iter() { self.push_hex_byte(*byte); }
+    }
+}
+
+/// The modulo 256 sum of the packet data
+pub fn checksum(data: &[u8]) -> u8{
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex_digits(byte: u8) -> [u8; 2]{
+    const DIGITS : &[u8; 16] = b"0123456789abcdef";
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]
+}
+
+fn hex_digit(digit: u8) -> Option<u8>{
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None
+    }
+}
+
+/// Parses a big endian hexadecimal number, as used for addresses and lengths
+pub fn parse_hex(text: &[u8]) -> Option<usize>{
+    if text.is_empty() == true || text.len() > 16 { return None; }
+    text.iter().try_fold(0usize, |value, digit| Some((value << 4) | hex_digit(*digit)? as usize))
+}
+
+// parses a hex encoded byte at "at"
+fn parse_hex_byte(text: &[u8], at: usize) -> Option<u8>{
+    Some((hex_digit(*text.get(at)?)? << 4) | hex_digit(*text.get(at + 1)?)?)
+}
+
+/// Parses a register value sent by GDB : 16 hex digits, target (little endian) byte order
+pub fn parse_register(text: &[u8]) -> Option<usize>{
+    if text.len() < 16 { return None; }
+    let mut bytes = [0u8; 8];
+    for (index, byte) in bytes.iter_mut().enumerate() { *byte = parse_hex_byte(text, index * 2)?; }
+    Some(u64::from_le_bytes(bytes) as usize)
+}
+
+// splits "text" at the first "separator"
+fn split_once(text: &[u8], separator: u8) -> Option<(&[u8], &[u8])>{
+    let position = text.iter().position(|byte| *byte == separator)?;
+    Some((&text[..position], &text[position + 1..]))
+}
+
+fn read_register(trap_frame: &TrapFrame, number: usize) -> Option<usize>{
+    match number {
+        0..=31 => Some(trap_frame.regs[number]),
+        PC_REGISTER => Some(trap_frame.mepc),
+        _ => None
+    }
+}
+
+fn write_register(trap_frame: &mut TrapFrame, number: usize, value: usize) -> bool{
+    match number {
+        0 => true, // x0 stays zero
+        1..=31 => { trap_frame.regs[number] = value; true },
+        PC_REGISTER => { trap_frame.mepc = value; true },
+        _ => false
+    }
+}
+
+/// The reply to "g" : x0 - x31 then pc
+pub fn registers_reply(trap_frame: &TrapFrame, reply: &mut Packet){
+    for number in 0..=PC_REGISTER { reply.push_register(read_register(trap_frame, number).unwrap_or(0)); }
+}
+
+// ----------------- breakpoints ----------------- //
+
+/// true if GDB planted a breakpoint at "address"
+pub fn is_gdb_breakpoint(address: usize) -> bool{
+    unsafe { BREAKPOINTS.iter().flatten().any(|breakpoint| breakpoint.address == address) }
+}
+
+fn insert_breakpoint(address: usize) -> bool{
+    if is_gdb_breakpoint(address) == true { return true; }
+    if address % 2 != 0 || is_accessible(address, 2, false) == false { return false; }
+    let breakpoints = unsafe { &mut BREAKPOINTS };
+    match breakpoints.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            let original = unsafe { (address as *const u16).read_volatile() };
+            stepping::patch_code(address, stepping::C_EBREAK);
+            *slot = Some(Breakpoint { address, original });
+            true
+        },
+        None => false
+    }
+}
+
+fn remove_breakpoint(address: usize) -> bool{
+    let breakpoints = unsafe { &mut BREAKPOINTS };
+    for slot in breakpoints.iter_mut() {
+        if let Some(breakpoint) = slot {
+            if breakpoint.address == address {
+                stepping::patch_code(breakpoint.address, breakpoint.original);
+                *slot = None;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn remove_all_breakpoints(){
+    let breakpoints = unsafe { &mut BREAKPOINTS };
+    for slot in breakpoints.iter_mut() {
+        if let Some(breakpoint) = slot.take() { stepping::patch_code(breakpoint.address, breakpoint.original); }
+    }
+}
+
+/// Where the context resumes : at its pc, unless the pc is an ebreak of the kernel itself. That one is stepped over,
+/// or the context would trap on it forever
+pub fn resume_address(trap_frame: &TrapFrame) -> usize{
+    let instruction = riscv::read_instruction(trap_frame.mepc);
+    if stepping::is_ebreak(instruction) == true && is_gdb_breakpoint(trap_frame.mepc) == false {
+        return trap_frame.mepc + riscv::instruction_length(instruction);
+    }
+    trap_frame.mepc
+}
+
+// ----------------- packets ----------------- //
+
+fn wait_byte(channel: &mut dyn DebugChannel) -> u8{
+    loop {
+        if let Some(byte) = channel.read_byte() { return byte; }
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits for a packet with a valid checksum and acknowledges it
+pub fn receive_packet(channel: &mut dyn DebugChannel, packet: &mut Packet){
+    loop {
+        // anything before '$' is noise : acks, or a Ctrl-C while the context is already stopped
+        while wait_byte(channel) != b'$' {}
+        packet.clear();
+        loop {
+            match wait_byte(channel) {
+                b'#' => break,
+                b'$' => packet.clear(), // a new packet started, the previous one got cut
+                byte => packet.push(byte)
+            }
+        }
+        let high = hex_digit(wait_byte(channel));
+        let low = hex_digit(wait_byte(channel));
+        match (high, low) {
+            (Some(high), Some(low)) if (high << 4) | low == checksum(packet.as_bytes()) => { channel.write_byte(b'+'); return; },
+            _ => channel.write_byte(b'-')
+        }
+    }
+}
+
+/// Sends a packet, and sends it again until GDB acknowledges it (a few times at most)
+pub fn send_packet(channel: &mut dyn DebugChannel, packet: &Packet){
+    for _ in 0..SEND_RETRIES {
+        channel.write_byte(b'$');
+        for byte in packet.as_bytes() { channel.write_byte(*byte); }
+        channel.write_byte(b'#');
+        for digit in hex_digits(checksum(packet.as_bytes())).iter() { channel.write_byte(*digit); }
+        if wait_byte(channel) == b'+' { return; }
+    }
+}
+
+fn stop_reply(reply: &mut Packet){
+    reply.push(b'S');
+    reply.push_hex_byte(SIGTRAP);
+}
+
+fn read_memory(arguments: &[u8], reply: &mut Packet) -> Option<()>{
+    let (address, length) = split_once(arguments, b',')?;
+    let (address, length) = (parse_hex(address)?, parse_hex(length)?.min((PACKET_SIZE - 1) / 2));
+    if length > 0 && is_accessible(address, length, false) == false { return None; }
+    for offset in 0..length { reply.push_hex_byte(unsafe { ((address + offset) as *const u8).read_volatile() }); }
+    Some(())
+}
+
+fn write_memory(arguments: &[u8]) -> Option<()>{
+    let (range, data) = split_once(arguments, b':')?;
+    let (address, length) = split_once(range, b',')?;
+    let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+    if data.len() != length * 2 { return None; }
+    if length > 0 && is_accessible(address, length, true) == false { return None; }
+    for offset in 0..length {
+        let byte = parse_hex_byte(data, offset * 2)?;
+        unsafe { ((address + offset) as *mut u8).write_volatile(byte); }
+    }
+    Some(())
+}
+
+// "Z0,addr,kind" and "z0,addr,kind"
+fn breakpoint_packet(arguments: &[u8], insert: bool) -> Option<()>{
+    let (kind, rest) = split_once(arguments, b',')?;
+    if kind != b"0" { return None; }
+    let (address, _) = split_once(rest, b',')?;
+    let address = parse_hex(address)?;
+    let done = if insert == true { insert_breakpoint(address) } else { remove_breakpoint(address) };
+    if done == true { Some(()) } else { None }
+}
+
+// "c [addr]" and "s [addr]" : the optional address replaces pc
+fn resume_packet(arguments: &[u8], trap_frame: &mut TrapFrame) -> Option<()>{
+    if arguments.is_empty() == false { trap_frame.mepc = parse_hex(arguments)?; }
+    Some(())
+}
+
+fn ok_or_error(result: Option<()>, reply: &mut Packet){
+    match result {
+        Some(()) => reply.push_str("OK"),
+        None => reply.push_str("E01")
+    }
+}
+
+/// Serves GDB until it resumes the context. "announce_stop" reports the stop right away (GDB is waiting for it after c or s)
+pub fn serve(channel: &mut dyn DebugChannel, trap_frame: &mut TrapFrame, announce_stop: bool) -> Resume{
+    ATTACHED.store(true, Ordering::Relaxed);
+    let mut packet = Packet::new();
+    let mut reply = Packet::new();
+    if announce_stop == true {
+        stop_reply(&mut reply);
+        send_packet(channel, &reply);
+    }
+
+    loop {
+        receive_packet(channel, &mut packet);
+        reply.clear();
+        let data = packet.as_bytes();
+        let (kind, arguments) = match data.split_first() {
+            Some((kind, arguments)) => (*kind, arguments),
+            None => { send_packet(channel, &reply); continue; }
+        };
+        match kind {
+            b'?' => stop_reply(&mut reply),
+            b'g' => registers_reply(trap_frame, &mut reply),
+            b'G' => {
+                let mut valid = arguments.len() >= 16 * (PC_REGISTER + 1);
+                for number in 0..=PC_REGISTER {
+                    match parse_register(arguments.get(number * 16..).unwrap_or(&[])) {
+                        Some(value) if valid == true => { write_register(trap_frame, number, value); },
+                        _ => valid = false
+                    }
+                }
+                ok_or_error(if valid == true { Some(()) } else { None }, &mut reply);
+            },
+            b'p' => match parse_hex(arguments).and_then(|number| read_register(trap_frame, number)) {
+                Some(value) => reply.push_register(value),
+                None => reply.push_str("xxxxxxxxxxxxxxxx") // a register that the stub does not have
+            },
+            b'P' => {
+                let result = split_once(arguments, b'=')
+                    .and_then(|(number, value)| Some((parse_hex(number)?, parse_register(value)?)))
+                    .and_then(|(number, value)| if write_register(trap_frame, number, value) == true { Some(()) } else { None });
+                ok_or_error(result, &mut reply);
+            },
+            b'm' => if read_memory(arguments, &mut reply).is_none() { reply.clear(); reply.push_str("E01"); },
+            b'M' => ok_or_error(write_memory(arguments), &mut reply),
+            b'Z' => ok_or_error(breakpoint_packet(arguments, true), &mut reply),
+            b'z' => ok_or_error(breakpoint_packet(arguments, false), &mut reply),
+            b'H' | b'T' => reply.push_str("OK"),
+            b'c' | b's' => {
+                if resume_packet(arguments, trap_frame).is_none() { reply.push_str("E01"); send_packet(channel, &reply); continue; }
+                return if kind == b'c' { Resume::Continue } else { Resume::Step };
+            },
+            b'D' | b'k' => {
+                remove_all_breakpoints();
+                ATTACHED.store(false, Ordering::Relaxed);
+                if kind == b'D' { reply.push_str("OK"); send_packet(channel, &reply); }
+                return Resume::Continue;
+            },
+            b'q' => {
+                if data.starts_with(b"qSupported") { reply.push_str("PacketSize=400"); } // 0x400 = PACKET_SIZE
+                else if data == b"qAttached" { reply.push(b'1'); }
+                else if data == b"qC" { reply.push_str("QC1"); }
+                else if data == b"qfThreadInfo" { reply.push_str("m1"); }
+                else if data == b"qsThreadInfo" { reply.push(b'l'); }
+            },
+            _ => {} // the empty reply : not supported
+        }
+        send_packet(channel, &reply);
+    }
+}
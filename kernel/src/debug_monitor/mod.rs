@@ -0,0 +1,324 @@
+//! The debug monitor.
+//!
+//! Breakpoints (ebreak) and panics drop into a small command prompt on the UART :
+//! ```text
+//! regs                  registers of the interrupted context (TrapFrame)
+//! m <address> [words]   reads memory, 8 bytes per word
+//! w <address> <value>   writes 8 bytes of memory
+//! t <address>           translates a virtual address with the page tables of the interrupted context
+//! pt                    dumps the page tables of the interrupted context
+//! layout                page allocator layout (page_manager::show_layout)
+//! heap                  kernel heap allocations (byte_manager::print_table)
+//! stats                 trap and interrupt counters of every hart (interrupt_and_exception_handling::dump_trap_stats)
+//! s                     single-step (see stepping.rs)
+//! c                     continue
+//! gdb                   hands the stop over to GDB (see gdb_stub.rs)
+//! ```
+//! Numbers are hexadecimal with a 0x prefix, decimal otherwise.
+//!
+//! Like the crash reporter, the monitor talks to the UART registers directly (crash_report::RawConsole) and polls for input,
+//! so it works with interrupts disabled and with a broken UART driver.
+//! Memory commands check the page tables first : a typo must not fault inside the monitor.
+//!
+//! One hart at a time owns the monitor. The other harts wait for it if they hit a breakpoint meanwhile.
+//! The monitor is not reentrant : a breakpoint that the owning hart hits inside the monitor (or in the code it calls) is fatal.
+//! After a panic there is nothing to resume : step is refused and continue leaves the monitor.
+
+mod stepping;
+pub mod gdb_stub;
+mod tests;
+
+pub use stepping::{step_targets, is_ebreak, C_EBREAK};
+
+use crate::interrupt_and_exception_handling::{self, TrapFrame, ExceptionInfo, ExceptionHandlingError};
+use crate::crash_report::{self, RawConsole, REGISTER_NAMES};
+use crate::{riscv, sv39_mmu, page_manager, byte_manager, drivers};
+use crate::sv39_mmu::VirtAddr;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const LINE_SIZE : usize = 80;
+const NO_HART : usize = usize::MAX;
+const WORD_SIZE : usize = 8;
+const SSTATUS_SIE : u64 = 1 << 1;
+/// The most words that one "m" command prints
+pub const MAX_READ_WORDS : usize = 64;
+
+static MONITOR_OWNER : AtomicUsize = AtomicUsize::new(NO_HART);
+
+/// A command typed at the monitor prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command{
+    Help,
+    Registers,
+    Read { address: usize, words: usize },
+    Write { address: usize, value: u64 },
+    Translate(usize),
+    PageTables,
+    Layout,
+    Heap,
+    Stats,
+    Step,
+    Continue,
+    Gdb
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonitorError{
+    UnknownCommand(&'static str),
+    MissingArgument(&'static str),
+    InvalidNumber(&'static str),
+    MisalignedAddress(&'static str),
+    InaccessibleAddress(&'static str),
+    NoContext(&'static str)
+}
+
+pub const MONITOR_ERROR_UnknownCommand : MonitorError = MonitorError::UnknownCommand("Unknown command, type h for the list of commands");
+pub const MONITOR_ERROR_MissingArgument : MonitorError = MonitorError::MissingArgument("The command needs more arguments");
+pub const MONITOR_ERROR_InvalidNumber : MonitorError = MonitorError::InvalidNumber("Numbers are hexadecimal with a 0x prefix, decimal otherwise");
+pub const MONITOR_ERROR_MisalignedAddress : MonitorError = MonitorError::MisalignedAddress("Memory is read and written 8 bytes at a time, the address must be divisible by 8");
+pub const MONITOR_ERROR_InaccessibleAddress : MonitorError = MonitorError::InaccessibleAddress("The address is not mapped with the needed permissions");
+pub const MONITOR_ERROR_NoContext : MonitorError = MonitorError::NoContext("There is no interrupted context to inspect or resume");
+
+/// How the monitor was left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume{
+    Continue,
+    Step,
+    Gdb // GDB takes over the stop
+}
+
+/// Parses a number : hexadecimal with a 0x prefix, decimal otherwise
+pub fn parse_number(text: &str) -> Result<usize, MonitorError>{
+    let parsed = match text.strip_prefix("0x") {
+        Some(digits) => usize::from_str_radix(digits, 16),
+        None => text.parse::<usize>()
+    };
+    parsed.map_err(|_| MONITOR_ERROR_InvalidNumber)
+}
+
+/// Parses one line typed at the prompt
+pub fn parse_command(line: &str) -> Result<Command, MonitorError>{
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return Err(MONITOR_ERROR_MissingArgument)
+    };
+    let mut next_number = || -> Result<usize, MonitorError> {
+        parse_number(words.next().ok_or(MONITOR_ERROR_MissingArgument)?)
+    };
+    match name {
+        "h" | "help" => Ok(Command::Help),
+        "regs" => Ok(Command::Registers),
+        "m" => {
+            let address = next_number()?;
+            let words = match next_number() {
+                Ok(words) => words.min(MAX_READ_WORDS),
+                Err(MonitorError::MissingArgument(_)) => 1,
+                Err(error) => return Err(error)
+            };
+            Ok(Command::Read { address, words })
+        },
+        "w" => Ok(Command::Write { address: next_number()?, value: next_number()? as u64 }),
+        "t" => Ok(Command::Translate(next_number()?)),
+        "pt" => Ok(Command::PageTables),
+        "layout" => Ok(Command::Layout),
+        "heap" => Ok(Command::Heap),
+        "stats" => Ok(Command::Stats),
+        "s" | "step" => Ok(Command::Step),
+        "c" | "continue" => Ok(Command::Continue),
+        "gdb" => Ok(Command::Gdb),
+        _ => Err(MONITOR_ERROR_UnknownCommand)
+    }
+}
+
+fn check_access(address: usize, write: bool) -> Result<(), MonitorError>{
+    if address % WORD_SIZE != 0 { return Err(MONITOR_ERROR_MisalignedAddress); }
+    if sv39_mmu::is_accessible(address, WORD_SIZE, write) == false { return Err(MONITOR_ERROR_InaccessibleAddress); }
+    Ok(())
+}
+
+// reads a line from the UART into "line", with echo and backspace. Returns the number of bytes read
+fn read_line(console: &mut RawConsole, line: &mut [u8; LINE_SIZE]) -> usize{
+    let mut length = 0;
+    loop {
+        let byte = match console.read_byte() {
+            Some(byte) => byte,
+            None => { core::hint::spin_loop(); continue; }
+        };
+        match byte {
+            b'\r' | b'\n' => { let _ = write!(console, "\r\n"); return length; },
+            0x7f | 0x08 => if length > 0 { length -= 1; let _ = write!(console, "\x08 \x08"); },
+            0x20..=0x7e if length < LINE_SIZE => {
+                line[length] = byte;
+                length += 1;
+                let _ = console.write_char(byte as char);
+            },
+            _ => {}
+        }
+    }
+}
+
+fn print_help(console: &mut RawConsole){
+    let _ = write!(console, "regs | m <address> [words] | w <address> <value> | t <address> | pt | layout | heap | stats | s | c | gdb\r\n");
+}
+
+fn print_registers(console: &mut RawConsole, trap_frame: &TrapFrame){
+    let _ = write!(console, "pc : ");
+    let _ = crash_report::write_code_address(console, trap_frame.mepc);
+    let _ = write!(console, "\r\ncause : {} (0x{:x})    tval : 0x{:016x}\r\n", crash_report::cause_name(trap_frame.mcause), trap_frame.mcause, trap_frame.mtval);
+    let _ = write!(console, "status : 0x{:016x}    satp : 0x{:016x}    hart : {}\r\n", trap_frame.mstatus, trap_frame.satp, trap_frame.hartid);
+    for index in 1..32 {
+        let _ = write!(console, "{:>4} : 0x{:016x}   ", REGISTER_NAMES[index], trap_frame.regs[index]);
+        if index % 3 == 0 || index == 31 { let _ = write!(console, "\r\n"); }
+    }
+}
+
+fn read_memory(console: &mut RawConsole, address: usize, words: usize) -> Result<(), MonitorError>{
+    for index in 0..words {
+        let word_address = address + index * WORD_SIZE;
+        check_access(word_address, false)?;
+        if index % 2 == 0 { let _ = write!(console, "0x{:016x} :", word_address); }
+        let value = unsafe { (word_address as *const u64).read_volatile() };
+        let _ = write!(console, " 0x{:016x}", value);
+        if index % 2 == 1 || index == words - 1 { let _ = write!(console, "\r\n"); }
+    }
+    Ok(())
+}
+
+fn write_memory(address: usize, value: u64) -> Result<(), MonitorError>{
+    check_access(address, true)?;
+    unsafe { (address as *mut u64).write_volatile(value); }
+    Ok(())
+}
+
+fn translate(console: &mut RawConsole, trap_frame: &TrapFrame, address: usize) -> Result<(), MonitorError>{
+    let root_table = match sv39_mmu::root_table_from_satp(trap_frame.satp) {
+        Some(root_table) => root_table,
+        None => { let _ = write!(console, "translation is off : 0x{:x} is physical\r\n", address); return Ok(()); }
+    };
+    let virt_address = match VirtAddr::new(address as u64) {
+        Ok(virt_address) => virt_address,
+        Err(error) => { let _ = write!(console, "0x{:016x} : {:?}\r\n", address, error); return Ok(()); }
+    };
+    match sv39_mmu::translate(root_table, virt_address) {
+        Ok(physical_address) => {
+            let flags = sv39_mmu::find_leaf_entry(root_table, virt_address.containing_page()).map(|entry| entry.get_val() & 0x3ff).unwrap_or(0);
+            let _ = write!(console, "0x{:016x} -> 0x{:016x} : flags {:010b}\r\n", address, physical_address.as_u64(), flags);
+        },
+        Err(error) => { let _ = write!(console, "0x{:016x} : {:?}\r\n", address, error); }
+    }
+    Ok(())
+}
+
+fn run_command(console: &mut RawConsole, trap_frame: Option<&TrapFrame>, command: Command) -> Result<Option<Resume>, MonitorError>{
+    match command {
+        Command::Help => print_help(console),
+        Command::Registers => print_registers(console, trap_frame.ok_or(MONITOR_ERROR_NoContext)?),
+        Command::Read { address, words } => read_memory(console, address, words)?,
+        Command::Write { address, value } => write_memory(address, value)?,
+        Command::Translate(address) => translate(console, trap_frame.ok_or(MONITOR_ERROR_NoContext)?, address)?,
+        Command::PageTables => {
+            let satp = trap_frame.map(|trap_frame| trap_frame.satp).unwrap_or(riscv::satp_read());
+            match sv39_mmu::root_table_from_satp(satp) {
+                Some(root_table) => sv39_mmu::show_mappings(root_table),
+                None => { let _ = write!(console, "translation is off, there are no page tables\r\n"); }
+            }
+        },
+        Command::Layout => page_manager::show_layout(),
+        Command::Heap => byte_manager::print_table(),
+        Command::Stats => { let _ = interrupt_and_exception_handling::dump_trap_stats(console); },
+        Command::Step => { trap_frame.ok_or(MONITOR_ERROR_NoContext)?; return Ok(Some(Resume::Step)); },
+        Command::Continue => return Ok(Some(Resume::Continue)),
+        Command::Gdb => { trap_frame.ok_or(MONITOR_ERROR_NoContext)?; return Ok(Some(Resume::Gdb)); }
+    }
+    Ok(None)
+}
+
+// waits until the monitor is free, then takes it for "hart_id". Returns false if the hart already owns it
+fn lock_monitor(hart_id: usize) -> bool{
+    loop {
+        match MONITOR_OWNER.compare_exchange(NO_HART, hart_id, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(owner) if owner == hart_id => return false,
+            Err(_) => core::hint::spin_loop()
+        }
+    }
+}
+
+fn unlock_monitor(){
+    MONITOR_OWNER.store(NO_HART, Ordering::Release);
+}
+
+// the prompt loop
+fn run_monitor(console: &mut RawConsole, trap_frame: Option<&TrapFrame>) -> Resume{
+    let mut line = [0u8; LINE_SIZE];
+    loop {
+        let _ = write!(console, "monitor> ");
+        let length = read_line(console, &mut line);
+        let text = core::str::from_utf8(&line[..length]).unwrap_or("");
+        if text.trim().is_empty() == true { continue; }
+        match parse_command(text).and_then(|command| run_command(console, trap_frame, command)) {
+            Ok(Some(resume)) => return resume,
+            Ok(None) => {},
+            Err(error) => { let _ = write!(console, "{:?}\r\n", error); }
+        }
+    }
+}
+
+/// Breakpoint exception handler : runs the monitor, or GDB if it is attached. Returns the address to resume at.
+/// A breakpoint planted by a step gets its instruction back first, and resumes at that instruction.
+/// A breakpoint inside the monitor is an error : the kernel cannot recover from it
+pub fn handle_breakpoint(trap_frame: &mut TrapFrame) -> Result<usize, ExceptionHandlingError<'static>>{
+    let pc = trap_frame.mepc;
+    let mut console = RawConsole;
+    if lock_monitor(trap_frame.hartid) == false {
+        return Err(ExceptionHandlingError::UnableToRecoverFromException("Breakpoint inside the debug monitor "));
+    }
+
+    let is_step = stepping::is_step_breakpoint(pc);
+    if is_step == true && stepping::disarm_step() != Some(trap_frame.hartid) {
+        // another hart ran into the step of this one. The instruction is back, it just runs it
+        unlock_monitor();
+        return Ok(pc);
+    }
+
+    let was_attached = gdb_stub::is_attached();
+    let mut resume_address = if is_step == true { pc } else { pc + riscv::instruction_length(riscv::read_instruction(pc)) };
+    let mut resume = Resume::Gdb;
+    if was_attached == false {
+        if is_step == true { let _ = write!(console, "step : "); }
+        else { let _ = write!(console, "\r\nbreakpoint on hart {} : ", trap_frame.hartid); }
+        let _ = crash_report::write_code_address(&mut console, resume_address);
+        let _ = write!(console, "\r\n");
+        resume = run_monitor(&mut console, Some(trap_frame));
+    }
+    if resume == Resume::Gdb {
+        // GDB sees the context stopped on the breakpoint itself, and may move pc
+        resume = gdb_stub::serve(&mut console, trap_frame, was_attached);
+        resume_address = gdb_stub::resume_address(trap_frame);
+    }
+
+    if resume == Resume::Step { stepping::arm_step(trap_frame.hartid, resume_address, &trap_frame.regs); }
+    unlock_monitor();
+    Ok(resume_address)
+}
+
+/// The Breakpoint entry of the trap handler table
+pub fn handle_breakpoint_exception(trap_frame: &mut TrapFrame, _info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{
+    handle_breakpoint(trap_frame)
+}
+
+/// Runs the monitor after a panic report. Returns once the user continues, the caller then halts.
+/// The panic may have happened with interrupts enabled : they get disabled, and the UART source is masked so that
+/// the UART driver does not consume the bytes that the monitor polls for
+pub fn enter_after_panic(){
+    let mut console = RawConsole;
+    riscv::sstatus_clear_bits(SSTATUS_SIE);
+    drivers::plic::disable_interrupt(drivers::UART_INTERRUPT_ID);
+    if lock_monitor(riscv::hart_id()) == false { return; }
+    stepping::disarm_step();
+    let _ = write!(console, "debug monitor, c to halt\r\n");
+    run_monitor(&mut console, None);
+    unlock_monitor();
+}
@@ -0,0 +1,153 @@
+//! Single-stepping.
+//!
+//! RISC-V has no single-step bit outside of debug mode, so a step is done with temporary breakpoints :
+//! the instruction at the resume address is decoded, a c.ebreak is written over every instruction that can run after it
+//! (the next one, and the target of a jump or a taken branch), then the hart resumes.
+//! The first of them to trap ends the step, and every temporary breakpoint gets its original instruction back.
+//!
+//! The text section is mapped Read-Execute. The breakpoints are written with the page made writable for the time of the write.
+
+use crate::riscv::{self, instruction_length, read_instruction};
+use crate::sv39_mmu;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The compressed breakpoint instruction. 2 bytes, so it fits over any instruction
+pub const C_EBREAK : u16 = 0x9002;
+const EBREAK : u32 = 0x0010_0073;
+
+const NO_HART : usize = usize::MAX;
+
+// opcodes of the 32 bit control transfer instructions
+const OPCODE_JAL : u32 = 0x6f;
+const OPCODE_JALR : u32 = 0x67;
+const OPCODE_BRANCH : u32 = 0x63;
+
+#[derive(Debug, Clone, Copy)]
+struct StepBreakpoint{
+    address : usize,
+    original : u16 // the halfword that the c.ebreak replaced
+}
+
+static mut STEP_BREAKPOINTS : [Option<StepBreakpoint>; 2] = [None; 2];
+static STEPPING_HART : AtomicUsize = AtomicUsize::new(NO_HART);
+
+/// true for ebreak and c.ebreak
+pub fn is_ebreak(instruction: u32) -> bool{
+    instruction == EBREAK || instruction == C_EBREAK as u32
+}
+
+// keeps the lowest "bits" bits of "value" and sign extends them
+fn sign_extend(value: u32, bits: u32) -> i64{
+    let shift = 64 - bits;
+    ((value as u64) << shift) as i64 >> shift
+}
+
+fn bit(instruction: u32, index: u32) -> u32{ (instruction >> index) & 1 }
+
+fn offset_address(pc: usize, offset: i64) -> usize{ (pc as i64).wrapping_add(offset) as usize }
+
+/// The addresses that can run right after the instruction at "pc" : the next instruction and, for jumps and branches, the target.
+/// "regs" are the registers of the interrupted context, they give the target of jalr/c.jr
+pub fn step_targets(instruction: u32, pc: usize, regs: &[usize; 32]) -> [Option<usize>; 2]{
+    let next = Some(pc + instruction_length(instruction));
+    if instruction_length(instruction) == 4 {
+        let rs1 = ((instruction >> 15) & 0x1f) as usize;
+        return match instruction & 0x7f {
+            OPCODE_JAL => {
+                let offset = (bit(instruction, 31) << 20) | (((instruction >> 21) & 0x3ff) << 1)
+                           | (bit(instruction, 20) << 11) | (((instruction >> 12) & 0xff) << 12);
+                [Some(offset_address(pc, sign_extend(offset, 21))), None]
+            },
+            OPCODE_JALR => {
+                let offset = sign_extend(instruction >> 20, 12);
+                [Some(offset_address(regs[rs1], offset) & !1), None]
+            },
+            OPCODE_BRANCH => {
+                let offset = (bit(instruction, 31) << 12) | (((instruction >> 25) & 0x3f) << 5)
+                           | (((instruction >> 8) & 0xf) << 1) | (bit(instruction, 7) << 11);
+                [next, Some(offset_address(pc, sign_extend(offset, 13)))]
+            },
+            _ => [next, None]
+        };
+    }
+
+    let quadrant = instruction & 0b11;
+    let funct3 = (instruction >> 13) & 0b111;
+    match (quadrant, funct3) {
+        // c.j (c.jal does not exist on RV64, funct3 1 is c.addiw)
+        (1, 5) => {
+            let offset = (bit(instruction, 12) << 11) | (bit(instruction, 11) << 4) | (((instruction >> 9) & 0b11) << 8)
+                       | (bit(instruction, 8) << 10) | (bit(instruction, 7) << 6) | (bit(instruction, 6) << 7)
+                       | (((instruction >> 3) & 0b111) << 1) | (bit(instruction, 2) << 5);
+            [Some(offset_address(pc, sign_extend(offset, 12))), None]
+        },
+        // c.beqz, c.bnez
+        (1, 6) | (1, 7) => {
+            let offset = (bit(instruction, 12) << 8) | (((instruction >> 10) & 0b11) << 3) | (((instruction >> 5) & 0b11) << 6)
+                       | (((instruction >> 3) & 0b11) << 1) | (bit(instruction, 2) << 5);
+            [next, Some(offset_address(pc, sign_extend(offset, 9)))]
+        },
+        // c.jr, c.jalr : rs2 is zero and rs1 is not
+        (2, 4) if (instruction >> 2) & 0x1f == 0 && (instruction >> 7) & 0x1f != 0 => {
+            let rs1 = ((instruction >> 7) & 0x1f) as usize;
+            [Some(regs[rs1] & !1), None]
+        },
+        _ => [next, None]
+    }
+}
+
+/// Writes a halfword into the text section, through the page tables that are active
+pub fn patch_code(address: usize, halfword: u16){
+    let page = sv39_mmu::VirtAddr::new(address as u64).ok().map(|virt_address| virt_address.containing_page());
+    let leaf_entry = sv39_mmu::root_table_from_satp(riscv::satp_read()).zip(page)
+        .and_then(|(root_table, page)| sv39_mmu::find_leaf_entry(root_table, page).ok());
+    match leaf_entry {
+        Some(leaf_entry) => {
+            let was_writable = leaf_entry.check_if_writable();
+            leaf_entry.set_as_writable();
+            riscv::sfence_vma_address(address);
+            unsafe { (address as *mut u16).write_volatile(halfword); }
+            if was_writable == false {
+                leaf_entry.set_as_non_writable();
+                riscv::sfence_vma_address(address);
+            }
+        },
+        None => unsafe { (address as *mut u16).write_volatile(halfword); }
+    }
+    // the hart must not run a stale copy of the instruction
+    unsafe { asm!("fence.i"); }
+}
+
+/// Places the temporary breakpoints for one step of the instruction at "pc", on behalf of the hart "hart_id"
+pub fn arm_step(hart_id: usize, pc: usize, regs: &[usize; 32]){
+    disarm_step();
+    let targets = step_targets(read_instruction(pc), pc, regs);
+    let step_breakpoints = unsafe { &mut STEP_BREAKPOINTS };
+    for (slot, target) in targets.iter().enumerate() {
+        // a jump to itself would overwrite the instruction that is about to run
+        let address = match target { Some(address) if *address != pc => *address, _ => continue };
+        if step_breakpoints.iter().flatten().any(|breakpoint| breakpoint.address == address) { continue; }
+        let original = unsafe { (address as *const u16).read_volatile() };
+        patch_code(address, C_EBREAK);
+        step_breakpoints[slot] = Some(StepBreakpoint { address, original });
+    }
+    STEPPING_HART.store(hart_id, Ordering::SeqCst);
+}
+
+/// true if "pc" holds one of the temporary breakpoints of a step
+pub fn is_step_breakpoint(pc: usize) -> bool{
+    unsafe { STEP_BREAKPOINTS.iter().flatten().any(|breakpoint| breakpoint.address == pc) }
+}
+
+/// Puts back the instructions under the temporary breakpoints. Returns the hart that was stepping, if a step was armed
+pub fn disarm_step() -> Option<usize>{
+    let step_breakpoints = unsafe { &mut STEP_BREAKPOINTS };
+    for slot in step_breakpoints.iter_mut() {
+        if let Some(breakpoint) = slot.take() { patch_code(breakpoint.address, breakpoint.original); }
+    }
+    match STEPPING_HART.swap(NO_HART, Ordering::SeqCst) {
+        NO_HART => None,
+        hart_id => Some(hart_id)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use crate::interrupt_and_exception_handling::TrapFrame;
+use super::{parse_command, parse_number, Command, MonitorError, step_targets, C_EBREAK, MAX_READ_WORDS};
+use crate::riscv::instruction_length;
+use super::gdb_stub::{checksum, parse_hex, parse_register, registers_reply, Packet};
+
+#[test_case]
+pub fn debug_monitor_test_switch(){
+    println!("\n---------  Running debug_monitor tests  ---------\n");
+    test_parse_number();
+    test_parse_commands();
+    test_parse_command_errors();
+    test_instruction_length();
+    test_step_targets_of_jumps_and_branches();
+    test_step_targets_of_compressed_instructions();
+    test_gdb_packet_encoding();
+    test_gdb_registers_reply();
+}
+
+fn test_parse_number(){
+    let res = (parse_number("0x8000"), parse_number("42"), parse_number("0xzz").is_err());
+    let suc_msg = "test_parse_number    ....   [OK]";
+    let fail_msg = "test_parse_number   ....    [FAIL]";
+    custom_assert((Ok(0x8000), Ok(42), true), res, suc_msg, fail_msg);
+}
+
+fn test_parse_commands(){
+    let res = (parse_command("m 0x80001000"), parse_command("m 0x80001000 1000"), parse_command("w 0x80001000 7"),
+               parse_command("t 0x1000"), parse_command("  s "), parse_command("c"), parse_command("stats"));
+    let expected = (Ok(Command::Read { address: 0x8000_1000, words: 1 }), Ok(Command::Read { address: 0x8000_1000, words: MAX_READ_WORDS }),
+                    Ok(Command::Write { address: 0x8000_1000, value: 7 }), Ok(Command::Translate(0x1000)), Ok(Command::Step), Ok(Command::Continue),
+                    Ok(Command::Stats));
+    let suc_msg = "test_parse_commands    ....   [OK]";
+    let fail_msg = "test_parse_commands   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_parse_command_errors(){
+    let res = (parse_command("w 0x1000"), parse_command("frobnicate"), parse_command("m 0xg"));
+    let is_expected = matches!(res, (Err(MonitorError::MissingArgument(_)), Err(MonitorError::UnknownCommand(_)), Err(MonitorError::InvalidNumber(_))));
+    let suc_msg = "test_parse_command_errors    ....   [OK]";
+    let fail_msg = "test_parse_command_errors   ....    [FAIL]";
+    custom_assert(true, is_expected, suc_msg, fail_msg);
+}
+
+fn test_instruction_length(){
+    let res = (instruction_length(C_EBREAK as u32), instruction_length(0x0010_0073), instruction_length(0x0001));
+    let suc_msg = "test_instruction_length    ....   [OK]";
+    let fail_msg = "test_instruction_length   ....    [FAIL]";
+    custom_assert((2, 4, 2), res, suc_msg, fail_msg);
+}
+
+fn test_step_targets_of_jumps_and_branches(){
+    let mut regs = [0usize; 32];
+    regs[1] = 0x8000_3001; // ra, the lowest bit gets cleared by jalr
+    let pc = 0x8000_1000;
+    let res = (step_targets(0x0080_006f, pc, &regs),  // jal x0, +8
+               step_targets(0xff9f_f06f, pc, &regs),  // jal x0, -8
+               step_targets(0x0000_8067, pc, &regs),  // jalr x0, 0(ra)
+               step_targets(0x0020_8863, pc, &regs),  // beq x1, x2, +16
+               step_targets(0x0000_0013, pc, &regs)); // nop
+    let expected = ([Some(0x8000_1008), None], [Some(0x8000_0ff8), None], [Some(0x8000_3000), None],
+                    [Some(0x8000_1004), Some(0x8000_1010)], [Some(0x8000_1004), None]);
+    let suc_msg = "test_step_targets_of_jumps_and_branches    ....   [OK]";
+    let fail_msg = "test_step_targets_of_jumps_and_branches   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_step_targets_of_compressed_instructions(){
+    let mut regs = [0usize; 32];
+    regs[1] = 0x8000_3000;
+    let pc = 0x8000_1000;
+    let res = (step_targets(0xa021, pc, &regs),  // c.j +8
+               step_targets(0xc119, pc, &regs),  // c.beqz a0, +6
+               step_targets(0x8082, pc, &regs),  // c.jr ra (ret)
+               step_targets(0x0001, pc, &regs)); // c.nop
+    let expected = ([Some(0x8000_1008), None], [Some(0x8000_1002), Some(0x8000_1006)], [Some(0x8000_3000), None], [Some(0x8000_1002), None]);
+    let suc_msg = "test_step_targets_of_compressed_instructions    ....   [OK]";
+    let fail_msg = "test_step_targets_of_compressed_instructions   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_gdb_packet_encoding(){
+    let res = (checksum(b"OK"), checksum(b""), parse_hex(b"80001000"), parse_hex(b"8000g"), parse_hex(b""),
+               parse_register(b"0010008000000000"), parse_register(b"00100080"), parse_command("gdb"));
+    let suc_msg = "test_gdb_packet_encoding    ....   [OK]";
+    let fail_msg = "test_gdb_packet_encoding   ....    [FAIL]";
+    custom_assert((0x9a, 0, Some(0x8000_1000), None, None, Some(0x8000_1000), None, Ok(Command::Gdb)), res, suc_msg, fail_msg);
+}
+
+// "g" : x0 - x31 then pc, 16 hex digits each, little endian
+fn test_gdb_registers_reply(){
+    let mut trap_frame = TrapFrame::zero();
+    trap_frame.regs[1] = 0x8000_1234;
+    trap_frame.mepc = 0x8000_2000;
+    let mut reply = Packet::new();
+    registers_reply(&trap_frame, &mut reply);
+    let bytes = reply.as_bytes();
+    let res = (bytes.len(), &bytes[16..32] == b"3412008000000000", &bytes[32 * 16..] == b"0020008000000000");
+    let suc_msg = "test_gdb_registers_reply    ....   [OK]";
+    let fail_msg = "test_gdb_registers_reply   ....    [FAIL]";
+    custom_assert((33 * 16, true, true), res, suc_msg, fail_msg);
+}
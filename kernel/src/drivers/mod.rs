@@ -4,8 +4,12 @@ pub mod plic;
 pub mod virtio_block;
 
 use virtio_block::virtio_protocol_abstractions::{*};
+use crate::map_kernel::direct_map_address;
 use crate::{print, println};
 
+/// The PLIC source of the UART
+pub const UART_INTERRUPT_ID : u32 = 10;
+
 // export SOLID static references to Driver Instances
 // pub static mut UART_DEVICE : UartDevice = UartDevice::init();
 
@@ -14,6 +18,7 @@ use crate::{print, println};
 pub fn init_all_hardwired_drivers(){
     uart::UartDevice::init();
     plic::init();
+    plic::request_irq(UART_INTERRUPT_ID, uart::handle_interrupt, plic::UART_PRIORITY).expect("unable to request the UART interrupt");
 }
 
 /// Probe the VirtIO bus for devices that might be out there.  
@@ -28,7 +33,8 @@ pub fn probe_and_initialize_virtio_devices() {
 		print!("Virtio probing 0x{:08x}...", addr);
 		let magicvalue;
 		let deviceid;
-		let ptr = addr as *mut u32;
+		// the registers are reached through the direct map, addr stays physical
+		let ptr = direct_map_address(addr) as *mut u32;
 		unsafe {
 			magicvalue = ptr.read_volatile();
 			deviceid = ptr.add(2).read_volatile();
@@ -72,6 +78,10 @@ pub fn probe_and_initialize_virtio_devices() {
 							VIRTIO_DEVICES[idx] =
 								Some(VirtioDevice::new_with(DeviceTypes::Block));
 						}
+						// the virtio sources are 1..=8, one per slot
+						if let Err(error) = plic::request_irq(idx as u32 + 1, handle_interrupt, plic::VIRTIO_PRIORITY) {
+							print!("{}...", error);
+						}
 						println!("setup succeeded!");
 					}
 				},
@@ -127,8 +137,8 @@ pub fn setup_entropy_device(_ptr: *mut u32) -> bool {
 	false
 }
 
-// The External pin (PLIC) trap will lead us here if it is
-// determined that interrupts 1..=8 are what caused the interrupt.
+// The External pin (PLIC) trap will lead us here for the interrupts 1..=8
+// that were requested in probe_and_initialize_virtio_devices.
 // In here, we try to figure out where to direct the interrupt
 // and then handle it.
 pub fn handle_interrupt(interrupt: u32) {
@@ -4,7 +4,9 @@ use core::{fmt, fmt::Display, error::Error};
 pub enum PlicError{
     Invalid_Interrupt_ID(&'static str),
     Invalid_Threshold_Value(&'static str),
-    Invalid_Priority_Value(&'static str)
+    Invalid_Priority_Value(&'static str),
+    Interrupt_ID_In_Use(&'static str),
+    Interrupt_ID_Not_Requested(&'static str)
 }
 
 impl Display for PlicError{
@@ -18,4 +20,6 @@ impl Error for PlicError{
 
 pub const PLIC_ERROR_Invalid_Interrupt_ID: PlicError = PlicError::Invalid_Interrupt_ID("There was an attempt to write an ID that was 0 or out of range");
 pub const PLIC_ERROR_Invalid_Threshold_Value: PlicError = PlicError::Invalid_Threshold_Value("Threshold value was either less than zero or more than 7");
-pub const PLIC_ERROR_Invalid_Priority_Value: PlicError = PlicError::Invalid_Priority_Value("Priority value was either less than zero or more than 7");
\ No newline at end of file
+pub const PLIC_ERROR_Invalid_Priority_Value: PlicError = PlicError::Invalid_Priority_Value("Priority value was either less than zero or more than 7");
+pub const PLIC_ERROR_Interrupt_ID_In_Use: PlicError = PlicError::Interrupt_ID_In_Use("A handler was already requested for the Interrupt ID");
+pub const PLIC_ERROR_Interrupt_ID_Not_Requested: PlicError = PlicError::Interrupt_ID_Not_Requested("No handler was requested for the Interrupt ID");
\ No newline at end of file
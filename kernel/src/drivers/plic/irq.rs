@@ -0,0 +1,96 @@
+//! The external interrupt registry.
+//!
+//! A driver asks for its interrupt source with request_irq(id, handler, priority) : the PLIC gets the priority of the source,
+//! the source gets enabled and every claim of that ID is routed to the handler. free_irq gives the source back.
+//! The trap module only claims, dispatches and completes (see interrupt_and_exception_handling::interrupts), so a new driver
+//! plugs in without editing it.
+//!
+//! A claim that nobody asked for is spurious : an ID without a handler, or a claim that returned no ID at all
+//! (another hart took the interrupt first). Both get counted per hart with the trap statistics (interrupt_and_exception_handling::trap_stats),
+//! the counts below add up the harts.
+
+use super::errors::{self, PlicError};
+use super::{enable_interrupt, disable_interrupt, priority_write};
+use crate::interrupt_and_exception_handling::{self, MAX_HARTS};
+use crate::{print, println, riscv};
+
+/// The number of interrupt sources that can be requested : IDs 1 to MAX_INTERRUPT_SOURCES - 1. ID 0 means "no interrupt"
+pub const MAX_INTERRUPT_SOURCES : usize = 64;
+
+/// Handles the interrupt of a source. Gets the claimed ID
+pub type IrqHandler = fn(u32);
+
+static mut IRQ_HANDLERS : [Option<IrqHandler>; MAX_INTERRUPT_SOURCES] = [None; MAX_INTERRUPT_SOURCES];
+
+fn validate_id(interrupt_id: u32) -> Result<usize, PlicError>{
+    if interrupt_id == 0 || interrupt_id as usize >= MAX_INTERRUPT_SOURCES { return Err(errors::PLIC_ERROR_Invalid_Interrupt_ID); }
+    Ok(interrupt_id as usize)
+}
+
+/// Routes the interrupts of a source to "handler" and enables the source with the given priority (1 - 7).
+/// Fails if the source already has a handler
+pub fn request_irq(interrupt_id: u32, handler: IrqHandler, priority: u8) -> Result<(), PlicError>{
+    let index = validate_id(interrupt_id)?;
+    if priority == 0 || priority > 7 { return Err(errors::PLIC_ERROR_Invalid_Priority_Value); }
+    if unsafe { IRQ_HANDLERS[index].is_some() } { return Err(errors::PLIC_ERROR_Interrupt_ID_In_Use); }
+
+    // the handler must be in place before the first claim can happen
+    unsafe { IRQ_HANDLERS[index] = Some(handler); }
+    priority_write(interrupt_id, priority)?;
+    enable_interrupt(interrupt_id);
+    Ok(())
+}
+
+/// Disables a source and forgets its handler. Fails if the source was not requested
+pub fn free_irq(interrupt_id: u32) -> Result<(), PlicError>{
+    let index = validate_id(interrupt_id)?;
+    if unsafe { IRQ_HANDLERS[index].is_none() } { return Err(errors::PLIC_ERROR_Interrupt_ID_Not_Requested); }
+
+    // no new claim once the source is disabled, then the handler can go
+    disable_interrupt(interrupt_id);
+    priority_write(interrupt_id, 0)?;
+    unsafe { IRQ_HANDLERS[index] = None; }
+    Ok(())
+}
+
+/// true if a driver requested the source
+pub fn is_requested(interrupt_id: u32) -> bool{
+    match validate_id(interrupt_id) {
+        Ok(index) => unsafe { IRQ_HANDLERS[index].is_some() },
+        Err(_) => false
+    }
+}
+
+/// Runs the handler of a claimed ID. Returns false, and counts the interrupt as spurious, if the ID has no handler
+pub fn dispatch_irq(interrupt_id: u32) -> bool{
+    let handler = match validate_id(interrupt_id) {
+        Ok(index) => unsafe { IRQ_HANDLERS[index] },
+        Err(_) => None
+    };
+    match handler {
+        Some(handler) => { handler(interrupt_id); true },
+        None => {
+            interrupt_and_exception_handling::record_spurious_claim(riscv::hart_id(), interrupt_id);
+            println!("Spurious external interrupt : no handler for ID {}", interrupt_id);
+            false
+        }
+    }
+}
+
+/// Counts a claim that returned no ID
+pub fn record_empty_claim(){
+    interrupt_and_exception_handling::record_spurious_claim(riscv::hart_id(), 0);
+}
+
+/// The number of claims of the ID that found no handler, on every hart
+pub fn spurious_count(interrupt_id: u32) -> usize{
+    match validate_id(interrupt_id) {
+        Ok(_) => (0..MAX_HARTS).map(|hart_id| interrupt_and_exception_handling::spurious_source_claims(hart_id, interrupt_id)).sum(),
+        Err(_) => 0
+    }
+}
+
+/// The number of claims that returned no ID, on every hart
+pub fn empty_claim_count() -> usize{
+    (0..MAX_HARTS).map(interrupt_and_exception_handling::empty_claims).sum()
+}
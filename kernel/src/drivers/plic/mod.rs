@@ -1,21 +1,33 @@
 //! THis module abstracts the PLIC (Platform Level Interrup Controller)
 //! It presents functions that can iteract with the underlying PLIC that cervices HART 0 Only
 //! 
+//! HART 0 has two PLIC contexts : context 0 raises MachineExternalInterrupts and context 1 raises SupervisorExternalInterrupts.
+//! The kernel runs in supervisor mode, so every function below uses context 1. The registers are reached through the direct map.
+//! 
 
 mod errors;
+mod irq;
+mod tests;
 
-use self::errors::PlicError;
+pub use self::errors::PlicError;
+pub use self::irq::{request_irq, free_irq, is_requested, dispatch_irq, record_empty_claim, spurious_count, empty_claim_count,
+                    IrqHandler, MAX_INTERRUPT_SOURCES};
+use crate::map_kernel::direct_map_address;
 use crate::{print, println};
 
-// PLIC register addresses
-const PLIC_PRIORITY: usize = 0x0c00_0000;
-const PLIC_PENDING: usize = 0x0c00_1000;
-const PLIC_INT_ENABLE: usize = 0x0c00_2000;
-const PLIC_THRESHOLD: usize = 0x0c20_0000;
-const PLIC_BUFFER: usize = 0x0c20_0004;
-
-
+// PLIC register addresses, shared by every context
+const PLIC_PRIORITY: usize = direct_map_address(0x0c00_0000);
+const PLIC_PENDING: usize = direct_map_address(0x0c00_1000);
+// the registers of context 1 (HART 0, supervisor mode)
+const PLIC_INT_ENABLE: usize = direct_map_address(0x0c00_2080);
+const PLIC_THRESHOLD: usize = direct_map_address(0x0c20_1000);
+const PLIC_BUFFER: usize = direct_map_address(0x0c20_1004);
 
+// the priorities the drivers request their sources with.
+// A handler only lets sources with a higher priority interrupt it (see interrupt_and_exception_handling::run_preemptible).
+// The UART handler may block on stdin, so the block devices rank above it
+pub const UART_PRIORITY: u8 = 5;
+pub const VIRTIO_PRIORITY: u8 = 6;
 
 /// This function reads the Interrupt ID value found in the buffer register
 pub fn read_ID_from_buffer() -> Option<u32>{
@@ -53,28 +65,34 @@ pub fn threshold_read() -> u8{
     return value as u8;
 }
 
-/// Enables the Interrupt associated with the input Interrupt ID
+/// Enables the Interrupt associated with the input Interrupt ID.
+/// Drivers go through request_irq, which also installs the handler
 pub fn enable_interrupt(interrupt_id: u32){
-    let ptr = PLIC_INT_ENABLE as *mut u32;
-    let actual_id = 1 << interrupt_id;
+    // one enable bit per source, 32 sources per word
+    let ptr = unsafe { (PLIC_INT_ENABLE as *mut u32).add(interrupt_id as usize / 32) };
+    let actual_id = 1 << (interrupt_id % 32);
     unsafe {
         ptr.write_volatile(ptr.read_volatile() | actual_id);
     }
 }
 
 pub fn check_if_enabled(interrupt_id: u32)-> bool{
-    let ptr = PLIC_INT_ENABLE as *const u32;
+    let ptr = unsafe { (PLIC_INT_ENABLE as *const u32).add(interrupt_id as usize / 32) };
     let value = unsafe {ptr.read_volatile()};
-    let mask: u32 = 1 << 10;
+    let mask: u32 = 1 << (interrupt_id % 32);
     let masked = value & mask;
     if masked == 0 { return false; }
     else { true }
 }
 
 /// Disables the Interrupt associated with the input Interrupt ID
-// pub fn disable_interrupt(interrupt_id: u32) -> Result<(), PlicError>{
-//     unimplemented!()
-// }
+pub fn disable_interrupt(interrupt_id: u32){
+    let ptr = unsafe { (PLIC_INT_ENABLE as *mut u32).add(interrupt_id as usize / 32) };
+    let actual_id = 1 << (interrupt_id % 32);
+    unsafe {
+        ptr.write_volatile(ptr.read_volatile() & !actual_id);
+    }
+}
 
 
 /// Sets the priority value of the associated interrupt
@@ -118,17 +136,10 @@ pub fn display(){
 }
 
 /// This function does the initial configurations of the plic.
-/// It enables interrupts, set their priorities and sets the overall threshold
+/// It sets the overall threshold. The sources get enabled, with their priorities, when the drivers request them (see irq.rs)
 pub fn init(){
-    // enable interrupts
-    // enable_interrupt(10); // UART iterrupts
-
     // set overall threshold
     threshold_write(0).unwrap();
-
-    // Set individual priorities
-    priority_write(10, 7);
-
 }
 
 // // THis function returns an array of all pending interrupts
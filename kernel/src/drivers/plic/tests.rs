@@ -0,0 +1,73 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use super::errors::{self, PlicError};
+use super::{request_irq, free_irq, is_requested, dispatch_irq, spurious_count, check_if_enabled, priority_read, MAX_INTERRUPT_SOURCES};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// no device of the virt machine uses this source, the tests can claim it
+const UNUSED_INTERRUPT_ID : u32 = 50;
+
+static LAST_DISPATCHED : AtomicU32 = AtomicU32::new(0);
+
+fn record_dispatch(interrupt_id: u32){
+    LAST_DISPATCHED.store(interrupt_id, Ordering::Relaxed);
+}
+
+#[test_case]
+pub fn plic_test_switch(){
+    println!("\n---------  Running PLIC tests  ---------\n");
+    test_invalid_requests_are_refused();
+    test_request_and_free_irq();
+    test_dispatch_reaches_the_handler();
+    test_unrequested_interrupts_are_spurious();
+}
+
+fn test_invalid_requests_are_refused(){
+    let res = [request_irq(0, record_dispatch, 1), request_irq(MAX_INTERRUPT_SOURCES as u32, record_dispatch, 1),
+               request_irq(UNUSED_INTERRUPT_ID, record_dispatch, 0), request_irq(UNUSED_INTERRUPT_ID, record_dispatch, 8),
+               free_irq(UNUSED_INTERRUPT_ID)];
+    let expected : [Result<(), PlicError>; 5] = [Err(errors::PLIC_ERROR_Invalid_Interrupt_ID), Err(errors::PLIC_ERROR_Invalid_Interrupt_ID),
+               Err(errors::PLIC_ERROR_Invalid_Priority_Value), Err(errors::PLIC_ERROR_Invalid_Priority_Value),
+               Err(errors::PLIC_ERROR_Interrupt_ID_Not_Requested)];
+    let suc_msg = "test_invalid_requests_are_refused    ....   [OK]";
+    let fail_msg = "test_invalid_requests_are_refused   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_request_and_free_irq(){
+    let requested = request_irq(UNUSED_INTERRUPT_ID, record_dispatch, 3);
+    let state_after_request = (is_requested(UNUSED_INTERRUPT_ID), check_if_enabled(UNUSED_INTERRUPT_ID), priority_read(UNUSED_INTERRUPT_ID));
+    let requested_twice = request_irq(UNUSED_INTERRUPT_ID, record_dispatch, 3);
+    let freed = free_irq(UNUSED_INTERRUPT_ID);
+    let state_after_free = (is_requested(UNUSED_INTERRUPT_ID), check_if_enabled(UNUSED_INTERRUPT_ID), priority_read(UNUSED_INTERRUPT_ID));
+
+    let res = (requested, state_after_request, requested_twice, freed, state_after_free);
+    let expected = (Ok(()), (true, true, 3), Err(errors::PLIC_ERROR_Interrupt_ID_In_Use), Ok(()), (false, false, 0));
+    let suc_msg = "test_request_and_free_irq    ....   [OK]";
+    let fail_msg = "test_request_and_free_irq   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_dispatch_reaches_the_handler(){
+    LAST_DISPATCHED.store(0, Ordering::Relaxed);
+    request_irq(UNUSED_INTERRUPT_ID, record_dispatch, 1).unwrap();
+    let spurious_before = spurious_count(UNUSED_INTERRUPT_ID);
+    let dispatched = dispatch_irq(UNUSED_INTERRUPT_ID);
+    free_irq(UNUSED_INTERRUPT_ID).unwrap();
+
+    let res = (dispatched, LAST_DISPATCHED.load(Ordering::Relaxed), spurious_count(UNUSED_INTERRUPT_ID) - spurious_before);
+    let suc_msg = "test_dispatch_reaches_the_handler    ....   [OK]";
+    let fail_msg = "test_dispatch_reaches_the_handler   ....    [FAIL]";
+    custom_assert((true, UNUSED_INTERRUPT_ID, 0), res, suc_msg, fail_msg);
+}
+
+fn test_unrequested_interrupts_are_spurious(){
+    LAST_DISPATCHED.store(0, Ordering::Relaxed);
+    let spurious_before = spurious_count(UNUSED_INTERRUPT_ID);
+    let dispatched = [dispatch_irq(UNUSED_INTERRUPT_ID), dispatch_irq(UNUSED_INTERRUPT_ID)];
+
+    let res = (dispatched, LAST_DISPATCHED.load(Ordering::Relaxed), spurious_count(UNUSED_INTERRUPT_ID) - spurious_before);
+    let suc_msg = "test_unrequested_interrupts_are_spurious    ....   [OK]";
+    let fail_msg = "test_unrequested_interrupts_are_spurious   ....    [FAIL]";
+    custom_assert(([false, false], 0, 2), res, suc_msg, fail_msg);
+}
@@ -1,11 +1,15 @@
 //! This module abstracts the CLINT Core Local Interruptor Timer    
-//! It exposes two registers : the mtime and mtimecmp.  
+//! It exposes two registers : the mtime and mtimecmp (read only, the machine mode shim writes it).  
 //! It also exposes time-setting functions
+//! The CLINT also holds one software interrupt register (MSIP) per hart. Writing 1 to it raises a MachineSoftwareInterrupt on that hart
 
 
-// Constants according to Qemu
-const MTIME_ADDRESS: usize = 0x0200_bff8;
-const MTIMECMP_ADDRESS: usize = 0x02004000;
+use crate::map_kernel::direct_map_address;
+
+// Constants according to Qemu. The registers are reached through the direct map
+const MTIME_ADDRESS: usize = direct_map_address(0x0200_bff8);
+const MTIMECMP_ADDRESS: usize = direct_map_address(0x02004000);
+const MSIP_ADDRESS: usize = direct_map_address(0x0200_0000); // one 32-bit register per hart
 
 const MTIME_PTR: *mut usize = MTIME_ADDRESS as *mut usize;
 const MTIMECMP_PTR: *mut usize = MTIMECMP_ADDRESS as *mut usize;
@@ -23,7 +27,24 @@ impl Timer{
         unsafe {    MTIMECMP_PTR.read_volatile() }
     }
 
-    pub fn mtimecmp_write(value: usize){
-        unsafe { MTIMECMP_PTR.write_volatile(value);    }
+    // mtimecmp belongs to the machine mode shim : supervisor mode re-arms the timer with delegation::set_timer()
+}
+
+
+pub struct SoftwareInterrupt{ /* unit struct... the MSIP registers of the CLINT */}
+
+impl SoftwareInterrupt{
+    /// Raises a MachineSoftwareInterrupt on the hart
+    pub fn raise(hart_id: usize){
+        unsafe { (MSIP_ADDRESS as *mut u32).add(hart_id).write_volatile(1); }
+    }
+
+    /// Clears the pending MachineSoftwareInterrupt of the hart
+    pub fn clear(hart_id: usize){
+        unsafe { (MSIP_ADDRESS as *mut u32).add(hart_id).write_volatile(0); }
+    }
+
+    pub fn is_pending(hart_id: usize) -> bool{
+        unsafe { (MSIP_ADDRESS as *mut u32).add(hart_id).read_volatile() & 1 == 1 }
     }
-}
\ No newline at end of file
+}
@@ -6,6 +6,8 @@ use uart_errors::UartError;
 use uart_interrupts::UartInterrupt;
 
 // attach dependent modules
+use crate::{stdin, stdout, print};
+use crate::map_kernel::direct_map_address;
 
 
 use core::{fmt, fmt::Debug, fmt::Display};
@@ -15,7 +17,7 @@ use core::fmt::Error; // The Write trait above works with formatted data. Writin
 
 
 // offsets of various UART registers in relation to the base address
-const BASE_ADDRESS_USZ : usize = 0x1000_0000;
+const BASE_ADDRESS_USZ : usize = direct_map_address(0x1000_0000); // reached through the direct map
 const BUFFER_OFFSET : usize = 0;
 const IER_OFFSET : usize = 1; // Interrupt Enable Register offset
 const ISR_OFFSET : usize = 2; // Interrupt Status Register offset
@@ -73,10 +75,10 @@ impl Display for UartDevice{
 
 impl UartDevice{
 	/// creates a new struct UartDevice that references the static mmio for the uart device	 
-	/// the uart is at 0x1000_0000
+	/// the uart is at 0x1000_0000, reached through the direct map
 	/// This function does not create a new individual instance, it just creates a new reference in the background
 	pub fn new () -> UartDevice{
-		let ptr_to_mmio = 0x1000_0000 as *mut Uart_MMIO;
+		let ptr_to_mmio = BASE_ADDRESS_USZ as *mut Uart_MMIO;
 		let ref_to_mmio = unsafe {&mut *(ptr_to_mmio)};
 		let uart_instance = UartDevice { mmio: ref_to_mmio };
 		return uart_instance;
@@ -178,3 +180,31 @@ impl Write for UartDevice {
 	}
 }
 
+
+/// The handler of the UART interrupt source, requested by drivers::init_all_hardwired_drivers (see plic::request_irq)
+pub fn handle_interrupt(_interrupt_id: u32){
+	// determine which UART interrupt it was
+	let uart_instance = UartDevice::new();
+
+	let interrupt_status_reg = uart_instance.read_interrupt_status_reg();
+	let masked_reg_value = interrupt_status_reg & 0b0000_1111;
+	match masked_reg_value {
+		0b0000_0010 => handle_THR_empty_interrupt(),
+		0b0000_0100 => handle_Data_Ready(),
+		0b0000_1100 => handle_Character_Timeout(),
+		_ => panic!("Unhandled UART interrupt value")
+	}
+}
+
+fn handle_THR_empty_interrupt(){
+	stdout::flush_std_buffer();
+	print!("c");
+}
+
+fn handle_Data_Ready(){
+	let _input = stdin::read_line().expect("read line failed");
+}
+
+fn handle_Character_Timeout(){
+	let _input = stdin::read_line().expect("read line failed");
+}
@@ -15,7 +15,10 @@
 
 pub mod virtio_protocol_abstractions;
 use crate::{byte_manager::{kfree, kmalloc},
-            page_manager::{alloc as zalloc, PAGE_SIZE},
+            page_manager::{alloc, PAGE_SIZE},
+            sv39_mmu::VirtAddr,
+            map_kernel::{virt_to_phys, phys_to_virt, direct_map_address},
+            sv39_mmu::PhysAddr,
 			print, println
 		};
 
@@ -156,7 +159,8 @@ pub fn setup_block_device(ptr: *mut u32) -> bool {
 		// ...
 		// 0x1000_8000 is index 7
 		// To get the number that changes over, we shift right 12 places (3 hex digits)
-		let idx = (ptr as usize - virtio::MMIO_VIRTIO_START) >> 12;
+		// The registers are reached through the direct map
+		let idx = (ptr as usize - direct_map_address(virtio::MMIO_VIRTIO_START)) >> 12;
 
 
 		// [Driver] Device Initialization
@@ -221,8 +225,9 @@ pub fn setup_block_device(ptr: *mut u32) -> bool {
 		// then we and the device will refer to different memory addresses
 		// and hence get the wrong data in the used ring.
 		// ptr.add(MmioOffsets::QueueAlign.scale32()).write_volatile(2);
-		let queue_ptr = zalloc(num_pages).unwrap() as *mut Queue;
-		let queue_pfn = queue_ptr as u32;
+		let queue_frame = alloc(num_pages).unwrap();
+		let queue_ptr = phys_to_virt(queue_frame.start_address()).as_u64() as *mut Queue;
+		let queue_pfn = queue_frame.as_u64() as u32;
 		ptr.add(MmioOffsets::GuestPageSize.scale32()).write_volatile(PAGE_SIZE as u32);
 		// QueuePFN is a physical page number, however it
 		// appears for QEMU we have to write the entire memory
@@ -249,6 +254,12 @@ pub fn setup_block_device(ptr: *mut u32) -> bool {
 }
 
 
+// The device reads and writes physical memory, while the kernel hands out direct map addresses.
+fn dma_address<T>(ptr: *const T) -> u64 {
+	let virt_address = VirtAddr::new(ptr as u64).expect("DMA buffer outside the virtual range");
+	virt_to_phys(virt_address).expect("DMA buffer outside the physical range").as_u64()
+}
+
 /// Fills the next available descriptor in the VirtIO queue with the provided descriptor.  
 /// Updates the current index and the "next" index in the descriptor to maintain the descriptor chain.
 pub fn fill_next_descriptor(bd: &mut BlockDevice, desc: Descriptor) -> u16 {
@@ -296,7 +307,7 @@ pub fn block_op(dev: usize, buffer: *mut u8, size: u32, offset: u64, write: bool
 			// the configuration space to ensure we stay within bounds.
 			let blk_request_size = size_of::<Request>();
 			let blk_request = kmalloc(blk_request_size) as *mut Request;
-			let desc = Descriptor { addr:  &(*blk_request).header as *const Header as u64,
+			let desc = Descriptor { addr:  dma_address(&(*blk_request).header as *const Header),
 			                        len:   size_of::<Header>() as u32,
 			                        flags: virtio::VIRTIO_DESC_F_NEXT,
 			                        next:  0, };
@@ -315,7 +326,7 @@ pub fn block_op(dev: usize, buffer: *mut u8, size: u32, offset: u64, write: bool
 			(*blk_request).data.data = buffer;
 			(*blk_request).header.reserved = 0;
 			(*blk_request).status.status = 111;
-			let desc = Descriptor { addr:  buffer as u64,
+			let desc = Descriptor { addr:  dma_address(buffer),
 			                        len:   size,
 			                        flags: virtio::VIRTIO_DESC_F_NEXT
 			                               | if false == write {
@@ -326,7 +337,7 @@ pub fn block_op(dev: usize, buffer: *mut u8, size: u32, offset: u64, write: bool
 			                               },
 			                        next:  0, };
 			let _data_idx = fill_next_descriptor(bdev, desc);
-			let desc = Descriptor { addr:  &(*blk_request).status as *const Status as u64,
+			let desc = Descriptor { addr:  dma_address(&(*blk_request).status as *const Status),
 			                        len:   size_of::<Status>() as u32,
 			                        flags: virtio::VIRTIO_DESC_F_WRITE,
 			                        next:  0, };
@@ -380,7 +391,9 @@ pub fn pending(bd: &mut BlockDevice) {
 		while bd.ack_used_idx != queue.used.idx {
 			let ref elem = queue.used.ring[bd.ack_used_idx as usize];
 			bd.ack_used_idx = (bd.ack_used_idx + 1) % VIRTIO_RING_SIZE as u16;
-			let rq = queue.desc[elem.id as usize].addr as *const Request;
+			// the descriptor holds the physical address that the device used
+			let rq_address = PhysAddr::new(queue.desc[elem.id as usize].addr).expect("virtio descriptor outside the physical range");
+			let rq = phys_to_virt(rq_address).as_u64() as *const Request;
 			kfree(rq as *mut u8);
 			// TODO: Awaken the process that will need this I/O. This is
 			// the purpose of the waiting state.
@@ -0,0 +1,55 @@
+//! Trap delegation and the machine mode shim.
+//!
+//! The kernel runs in supervisor mode, so every trap it cares about is delegated to supervisor mode. booze.s sets the delegation up
+//! before the first supervisor instruction runs :
+//! - medeleg : every exception except SupervisorEnvironmentCall (9), the way supervisor mode calls the shim.
+//!   Among them IllegalInstruction (2, for the lazy FP restore), Breakpoint (3, the debug monitor),
+//!   LoadAddressMisaligned (4) and StoreAddressMisaligned (6, emulated through the address space of the context),
+//!   InstructionPageFault (12), LoadPageFault (13), StorePageFault (15)
+//! - mideleg : SupervisorSoftwareInterrupt (1), SupervisorTimerInterrupt (5), SupervisorExternalInterrupt (9)
+//!
+//! Delegated traps go to asm_supervisor_trap_vector (stvec) and use the frame of the running context (sscratch).
+//! What must stay in machine mode is kept in a small assembly shim, asm_trap_vector (mtvec, see trap.s) :
+//! 1. The timer belongs to machine mode : mtimecmp raises MachineTimerInterrupts only.
+//!    The shim forwards each of them as a SupervisorTimerInterrupt by setting mip.STIP, and masks the machine timer.
+//! 2. Supervisor mode cannot clear mip.STIP. It re-arms the timer with set_timer(), an ecall that the shim serves :
+//!    the shim writes mtimecmp, clears mip.STIP and unmasks the machine timer.
+//! 3. MachineSoftwareInterrupts cannot be delegated. The shim turns the ones the TLB shootdowns raise into SupervisorSoftwareInterrupts.
+
+use super::{TrapFrame, MAX_HARTS};
+use crate::riscv;
+
+// interrupt codes delegated to supervisor mode (see booze.s). Their sie bits get enabled here
+const DELEGATED_INTERRUPTS : u64 = (1 << 1) | (1 << 5) | (1 << 9);
+
+/// The shim call that re-arms the timer. Passed in a7, the new mtimecmp value goes in a0. Same as SHIM_CALL_SET_TIMER in trap.s
+pub const SHIM_CALL_SET_TIMER : usize = 0x5449_4d45; // "TIME"
+
+/// The save areas of the shim, one per hart : t0, t1, t2 and t6 (32 bytes). booze.s points mscratch at the area of the hart
+#[no_mangle]
+static mut machine_scratch_areas : [[usize; 4]; MAX_HARTS] = [[0; 4]; MAX_HARTS];
+
+extern "C" {
+    fn asm_supervisor_trap_vector();
+    fn asm_shim_call(argument: usize, call: usize); // trap.s : moves "call" into a7 and performs the ecall
+}
+
+/// Installs the supervisor trap vector and enables the delegated interrupts. Runs in supervisor mode, at the start of kinit
+pub fn init_supervisor_trap_handling(){
+    // sscratch holds the frame of the kernel context of this hart, until a context change installs another one
+    let hart_id = riscv::hart_id();
+    assert!(hart_id < MAX_HARTS, "hart {} has no trap frame, MAX_HARTS is {}", hart_id, MAX_HARTS);
+    let supervisor_trapframe_ref = unsafe{ &mut crate::kernel_supervisor_trap_frames[hart_id] };
+    supervisor_trapframe_ref.hartid = hart_id;
+    supervisor_trapframe_ref.kernel_sp = super::trap_stack::supervisor_trap_stack_top(hart_id);
+    let supervisor_trapframe_address = supervisor_trapframe_ref as *mut TrapFrame as u64;
+    riscv::sscratch_write(supervisor_trapframe_address);
+    riscv::stvec_write(asm_supervisor_trap_vector as usize as u64);
+
+    riscv::sie_set_bits(DELEGATED_INTERRUPTS);
+}
+
+/// Supervisor mode side of the timer : asks the shim to fire the next timer interrupt when mtime reaches "value"
+pub fn set_timer(value: usize){
+    unsafe{ asm_shim_call(value, SHIM_CALL_SET_TIMER); }
+}
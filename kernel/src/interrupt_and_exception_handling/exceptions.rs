@@ -1,9 +1,9 @@
 use super::TrapFrame;
+use super::handlers;
 use crate::{print, println};
-use core::arch::asm;
-use crate::riscv;
+use core::convert::TryFrom;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExceptionType{
     InstructionAddressMisaligned, // 0
     InstructionAccessFault, // 1
@@ -27,79 +27,133 @@ pub enum ExceptionHandlingError<'a>{
     UnableToRecoverFromException(&'a str),
 }
 
-pub fn handle_exception(trapframe: &mut TrapFrame) -> Result<usize, ExceptionHandlingError>{
-    // get cause
-    let cause = trapframe.mcause & !(1 << 63);
+/// The errors of the cause decoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CauseError{
+    NotAnException(&'static str), // the interrupt bit of the cause is set
+    NotAnInterrupt(&'static str)  // the interrupt bit of the cause is clear
+}
+
+pub const CAUSE_ERROR_NotAnException : CauseError = CauseError::NotAnException("The cause has the interrupt bit set, it is an interrupt");
+pub const CAUSE_ERROR_NotAnInterrupt : CauseError = CauseError::NotAnInterrupt("The cause has the interrupt bit clear, it is an exception");
 
-    // match cause
-    // todo!("make the error message display the address that caused problems") 
-    match cause {
-        0   => {
-            println!("Handling InstructionAddressMisaligned");
-            let misaligned_address = riscv::mtval_read();
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("Instruction Address Misaligned Excption occured ")); 
-            
-        },
-        1   => {
-            println!("Handling InstructionAccessFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("InstructionAccessFault occured "));  
-        },
+/// Bit 63 of mcause/scause : set for interrupts
+pub const INTERRUPT_BIT : usize = 1 << 63;
 
-        2   => {
-            println!("Handling IllegalInstruction : {}", trapframe.mepc);
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("IllegalInstruction occured "));
-        },
+impl TryFrom<usize> for ExceptionType{
+    type Error = CauseError;
 
-        3   => {
-            println!("Handling Breakpoint");
-            let next_nonfaulty_instruction = trapframe.mepc + 4; // $ra + 4
-            return Ok(next_nonfaulty_instruction);
-        },
-        4   => {
-            println!("Handling LoadAddressMisaligned");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("LoadAddressMisaligned occured ")); 
-        },
-        5   => {
-            println!("Handling LoadAccessFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("LoadAccessFault occured ")); 
-        },
-        6   => {
-            println!("Handling StoreAddressMisaligned");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("StoreAddressMisaligned occured "));
-        },
-        7   => {
-            println!("Handling StoreAccessFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("StoreAccessFault occured "));
-        },
-        8   => {
-            println!("Handling UserEnvironmentCall");
-            let next_nonfaulty_instruction = trapframe.regs[1] + 4; // $ra + 4
-            return Ok(next_nonfaulty_instruction);
-        },
-        9   => {
-            println!("Handling SupervisorEnvironmentCall");
-            let next_nonfaulty_instruction = trapframe.regs[1] + 4; // $ra + 4
-            return Ok(next_nonfaulty_instruction);
-        },
-        11   => {
-            println!("Handling MachineEnvironmentCall");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("MachineEnvironmentCall occured "));
-        },
-        12   => {
-            println!("Handling InstructionPageFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("InstructionPageFault occured "));
-        },
-        13   => {
-            println!("Handling LoadPageFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("LoadPageFault occured "));
-        },
-        15   => {
-            println!("Handling StorePageFault");
-            return Err(ExceptionHandlingError::UnableToRecoverFromException("StorePageFault occured "));
-        },
+    /// Decodes the value of mcause/scause. Codes without a standard meaning become UnknownSync
+    fn try_from(cause: usize) -> Result<Self, Self::Error>{
+        if cause & INTERRUPT_BIT != 0 { return Err(CAUSE_ERROR_NotAnException); }
+        Ok(match cause {
+            0 => ExceptionType::InstructionAddressMisaligned,
+            1 => ExceptionType::InstructionAccessFault,
+            2 => ExceptionType::IllegalInstruction,
+            3 => ExceptionType::Breakpoint,
+            4 => ExceptionType::LoadAddressMisaligned,
+            5 => ExceptionType::LoadAccessFault,
+            6 => ExceptionType::StoreAddressMisaligned,
+            7 => ExceptionType::StoreAccessFault,
+            8 => ExceptionType::UserEnvironmentCall,
+            9 => ExceptionType::SupervisorEnvironmentCall,
+            11 => ExceptionType::MachineEnvironmentCall,
+            12 => ExceptionType::InstructionPageFault,
+            13 => ExceptionType::LoadPageFault,
+            15 => ExceptionType::StorePageFault,
+            code => ExceptionType::UnknownSync(code)
+        })
+    }
+}
 
-        _   => {    panic!("Unhandled exception trap cause -> {}\n", cause);  }
+impl ExceptionType{
+    /// The exception code, as found in mcause/scause
+    pub fn code(&self) -> usize{
+        match self {
+            ExceptionType::InstructionAddressMisaligned => 0,
+            ExceptionType::InstructionAccessFault => 1,
+            ExceptionType::IllegalInstruction => 2,
+            ExceptionType::Breakpoint => 3,
+            ExceptionType::LoadAddressMisaligned => 4,
+            ExceptionType::LoadAccessFault => 5,
+            ExceptionType::StoreAddressMisaligned => 6,
+            ExceptionType::StoreAccessFault => 7,
+            ExceptionType::UserEnvironmentCall => 8,
+            ExceptionType::SupervisorEnvironmentCall => 9,
+            ExceptionType::MachineEnvironmentCall => 11,
+            ExceptionType::InstructionPageFault => 12,
+            ExceptionType::LoadPageFault => 13,
+            ExceptionType::StorePageFault => 15,
+            ExceptionType::UnknownSync(code) => *code
+        }
     }
 }
 
+/// What mtval/stval holds for an exception
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrapValue{
+    None,                    // the exception does not set it, or the hart left it at 0
+    FaultingAddress(usize),  // misaligned accesses, access faults, page faults and breakpoints
+    Instruction(u32)         // IllegalInstruction : the bits of the instruction
+}
+
+/// A decoded exception : its type, and the details that came in mtval/stval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExceptionInfo{
+    pub exception : ExceptionType,
+    pub value : TrapValue
+}
 
+impl ExceptionInfo{
+    /// Decodes a cause and its trap value
+    pub fn decode(cause: usize, trap_value: usize) -> Result<Self, CauseError>{
+        let exception = ExceptionType::try_from(cause)?;
+        let value = match (exception, trap_value) {
+            (ExceptionType::IllegalInstruction, 0) => TrapValue::None,
+            (ExceptionType::IllegalInstruction, bits) => TrapValue::Instruction(bits as u32),
+            (ExceptionType::UserEnvironmentCall, _) | (ExceptionType::SupervisorEnvironmentCall, _)
+                | (ExceptionType::MachineEnvironmentCall, _) | (ExceptionType::UnknownSync(_), _) => TrapValue::None,
+            (_, address) => TrapValue::FaultingAddress(address)
+        };
+        Ok(ExceptionInfo { exception, value })
+    }
+}
+
+impl TryFrom<&TrapFrame> for ExceptionInfo{
+    type Error = CauseError;
+
+    fn try_from(trap_frame: &TrapFrame) -> Result<Self, Self::Error>{
+        ExceptionInfo::decode(trap_frame.mcause, trap_frame.mtval)
+    }
+}
+
+/// Decodes the exception of the frame and runs the handler registered for it (see handlers.rs)
+pub fn handle_exception(trapframe: &mut TrapFrame) -> Result<usize, ExceptionHandlingError<'static>>{
+    let info = match ExceptionInfo::try_from(&*trapframe) {
+        Ok(info) => info,
+        Err(_) => return Err(ExceptionHandlingError::UnableToRecoverFromException("The exception handler got an interrupt "))
+    };
+    match handlers::exception_handler(info.exception) {
+        Some(handler) => handler(trapframe, &info),
+        None => {
+            println!("Unhandled exception : {:?}", info);
+            Err(ExceptionHandlingError::UnableToRecoverFromException("No handler is registered for the exception "))
+        }
+    }
+}
+
+/// The handler of the exceptions that the kernel cannot recover from
+pub fn handle_fatal_exception(_trapframe: &mut TrapFrame, info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{
+    println!("Handling {:?} : {:?}", info.exception, info.value);
+    let message = match info.exception {
+        ExceptionType::InstructionAddressMisaligned => "Instruction Address Misaligned Excption occured ",
+        ExceptionType::InstructionAccessFault => "InstructionAccessFault occured ",
+        ExceptionType::IllegalInstruction => "IllegalInstruction occured ",
+        ExceptionType::LoadAddressMisaligned => "LoadAddressMisaligned occured ",
+        ExceptionType::LoadAccessFault => "LoadAccessFault occured ",
+        ExceptionType::StoreAddressMisaligned => "StoreAddressMisaligned occured ",
+        ExceptionType::StoreAccessFault => "StoreAccessFault occured ",
+        _ => "Unknown exception occured "
+    };
+    Err(ExceptionHandlingError::UnableToRecoverFromException(message))
+}
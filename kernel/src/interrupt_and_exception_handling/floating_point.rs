@@ -0,0 +1,110 @@
+//! Lazy floating point context switching.
+//!
+//! Saving 32 FP registers on every trap is wasted work for the contexts that never touch them.
+//! The FS field of sstatus (bits 13-14) tells what the FP registers hold :
+//! - Off : FP instructions are illegal. The registers do not belong to the running context yet
+//! - Initial / Clean : the registers match the copy saved in the trap frame of the running context
+//! - Dirty : the running context changed the registers since the last save
+//!
+//! 1. asm_supervisor_trap_vector saves the FP registers (and fcsr) into the trap frame only when FS is Dirty, then marks them Clean.
+//! 2. switch_trap_frame saves a Dirty state into the outgoing frame and turns FP off for the incoming context.
+//! 3. The first FP instruction of the incoming context raises an IllegalInstruction.
+//!    restore_on_demand loads the registers from the frame of the context, marks them Clean and retries the instruction.
+//!
+//! Every frame counts its saves and restores, see fp_usage.
+
+use super::TrapFrame;
+use crate::riscv;
+
+const FS_SHIFT : usize = 13;
+const FS_MASK : usize = 0b11 << FS_SHIFT;
+const FS_CLEAN : usize = 0b10 << FS_SHIFT;
+
+extern "C" {
+    fn asm_save_fp_state(trap_frame: *mut TrapFrame); // trap.s : f0-f31 and fcsr into the frame
+    fn asm_load_fp_state(trap_frame: *const TrapFrame); // trap.s : f0-f31 and fcsr from the frame
+}
+
+/// The values of the FS field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FpState{
+    Off,
+    Initial,
+    Clean,
+    Dirty
+}
+
+/// Extracts the FS field of an mstatus or sstatus value
+pub fn fp_state(status: usize) -> FpState{
+    match (status & FS_MASK) >> FS_SHIFT {
+        0 => FpState::Off,
+        1 => FpState::Initial,
+        2 => FpState::Clean,
+        _ => FpState::Dirty,
+    }
+}
+
+/// How much a context has used the FP registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpUsage{
+    pub saves : usize,    // times its FP registers were saved because they were Dirty
+    pub restores : usize  // times its FP registers were loaded back on demand
+}
+
+pub fn fp_usage(trap_frame: &TrapFrame) -> FpUsage{
+    FpUsage { saves: trap_frame.fp_saves, restores: trap_frame.fp_restores }
+}
+
+/// true if the instruction is a floating point instruction : FP loads and stores, FP arithmetic and fused multiply-add,
+/// or a csr instruction that accesses fflags, frm or fcsr.
+/// A compressed instruction (the two low bits are not 0b11) only uses its low 16 bits : c.fld and c.fsd (quadrant 0),
+/// c.fldsp and c.fsdsp (quadrant 2) are the FP ones, funct3 1 and 5
+pub fn is_floating_point_instruction(instruction: u32) -> bool{
+    let quadrant = instruction & 0b11;
+    if quadrant != 0b11 {
+        let funct3 = (instruction >> 13) & 0b111;
+        return (quadrant == 0b00 || quadrant == 0b10) && (funct3 == 1 || funct3 == 5);
+    }
+    let opcode = instruction & 0x7f;
+    match opcode {
+        0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53 => true,
+        0x73 => { // SYSTEM : only the csr instructions (funct3 != 0) on csr 1, 2 or 3
+            let funct3 = (instruction >> 12) & 0b111;
+            let csr = instruction >> 20;
+            funct3 != 0 && funct3 != 4 && csr >= 1 && csr <= 3
+        },
+        _ => false
+    }
+}
+
+/// Called in supervisor mode before another context gets the hart.
+/// Saves the FP registers into the frame of the outgoing context if they are Dirty, then turns FP off
+pub(super) fn save_before_switch(outgoing_trap_frame: *mut TrapFrame){
+    if outgoing_trap_frame.is_null() == false && fp_state(riscv::sstatus_read()) == FpState::Dirty {
+        unsafe {
+            asm_save_fp_state(outgoing_trap_frame);
+            (*outgoing_trap_frame).fp_saves += 1;
+        }
+    }
+    riscv::sstatus_clear_bits(FS_MASK as u64);
+}
+
+/// Handles an IllegalInstruction that was caused by the first FP instruction of a context (FS is Off).
+/// Loads the FP registers of the context and returns true : the instruction can be retried.
+/// Returns false if the illegal instruction had nothing to do with lazy FP
+pub(super) fn restore_on_demand(trap_frame: &mut TrapFrame) -> bool{
+    if fp_state(riscv::sstatus_read()) != FpState::Off { return false; }
+    // the faulting instruction is in stval. Some implementations leave stval at 0, the instruction is then fetched from sepc
+    let instruction = match trap_frame.mtval {
+        0 => riscv::read_instruction(trap_frame.mepc),
+        bits => bits as u32
+    };
+    if is_floating_point_instruction(instruction) == false { return false; }
+
+    riscv::sstatus_set_bits(FS_CLEAN as u64); // FP must be on before the registers can be loaded
+    unsafe { asm_load_fp_state(trap_frame as *const TrapFrame); }
+    riscv::sstatus_clear_bits(FS_MASK as u64);
+    riscv::sstatus_set_bits(FS_CLEAN as u64); // the loads made the state Dirty, but it matches the frame
+    trap_frame.fp_restores += 1;
+    true
+}
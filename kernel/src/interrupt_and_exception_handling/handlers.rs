@@ -0,0 +1,109 @@
+//! The trap handler table.
+//!
+//! handle_exception and handle_interrupt decode the cause (ExceptionType / InterruptType) and call the handler registered for it.
+//! The table starts with the handlers of the kernel subsystems :
+//! - page faults : page_faults.rs
+//! - environment calls : syscalls.rs
+//! - misaligned loads and stores : misaligned.rs
+//! - breakpoints : the debug monitor
+//! - the timer, software and external interrupts : interrupts.rs
+//!
+//! A subsystem can replace a handler, or take over a cause that has none, with register_exception_handler / register_interrupt_handler.
+//! Registration is meant for boot time : the table is not locked against a trap that is being dispatched on another hart.
+
+use super::TrapFrame;
+use super::exceptions::{self, ExceptionType, ExceptionInfo, ExceptionHandlingError};
+use super::interrupts::{self, InterruptType};
+use super::{page_faults, syscalls, misaligned};
+use crate::debug_monitor;
+
+/// Handles an exception. Returns the address to resume at, or an error if the kernel cannot recover
+pub type ExceptionHandler = fn(&mut TrapFrame, &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>;
+/// Handles an interrupt. The interrupted context resumes where it was
+pub type InterruptHandler = fn(&mut TrapFrame);
+
+/// The number of cause codes that can have a handler : 0 - 15, the standard codes
+pub const NUM_CAUSE_CODES : usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandlerRegistrationError{
+    UnknownCause(&'static str) // the code has no standard meaning, it has no slot in the table
+}
+
+pub const HANDLER_ERROR_UnknownCause : HandlerRegistrationError = HandlerRegistrationError::UnknownCause("Handlers can only be registered for the standard cause codes (0 - 15)");
+
+static mut EXCEPTION_HANDLERS : [Option<ExceptionHandler>; NUM_CAUSE_CODES] = [
+    Some(exceptions::handle_fatal_exception),       // 0 InstructionAddressMisaligned
+    Some(exceptions::handle_fatal_exception),       // 1 InstructionAccessFault
+    Some(exceptions::handle_fatal_exception),       // 2 IllegalInstruction
+    Some(debug_monitor::handle_breakpoint_exception), // 3 Breakpoint
+    Some(misaligned::handle_misaligned_access),     // 4 LoadAddressMisaligned
+    Some(exceptions::handle_fatal_exception),       // 5 LoadAccessFault
+    Some(misaligned::handle_misaligned_access),     // 6 StoreAddressMisaligned
+    Some(exceptions::handle_fatal_exception),       // 7 StoreAccessFault
+    Some(syscalls::handle_environment_call),        // 8 UserEnvironmentCall
+    Some(syscalls::handle_environment_call),        // 9 SupervisorEnvironmentCall
+    None,                                           // 10 reserved
+    Some(syscalls::handle_environment_call),        // 11 MachineEnvironmentCall
+    Some(page_faults::handle_page_fault),           // 12 InstructionPageFault
+    Some(page_faults::handle_page_fault),           // 13 LoadPageFault
+    None,                                           // 14 reserved
+    Some(page_faults::handle_page_fault),           // 15 StorePageFault
+];
+
+static mut INTERRUPT_HANDLERS : [Option<InterruptHandler>; NUM_CAUSE_CODES] = [
+    Some(interrupts::handle_software_interrupt),    // 0 UserSoftwareInterrupt
+    Some(interrupts::handle_software_interrupt),    // 1 SupervisorSoftwareInterrupt
+    None,                                           // 2 reserved
+    Some(interrupts::handle_software_interrupt),    // 3 MachineSoftwareInterrupt
+    Some(interrupts::handle_timer_interrupt),       // 4 UserTimerInterrupt
+    Some(interrupts::handle_timer_interrupt),       // 5 SupervisorTimerInterrupt
+    None,                                           // 6 reserved
+    Some(interrupts::handle_timer_interrupt),       // 7 MachineTimerInterrupt
+    Some(interrupts::handle_external_interrupt),    // 8 UserExternalInterrupt
+    Some(interrupts::handle_external_interrupt),    // 9 SupervisorExternalInterrupt
+    None,                                           // 10 reserved
+    Some(interrupts::handle_external_interrupt),    // 11 MachineExternalInterrupt
+    None, None, None, None                          // 12 - 15 reserved
+];
+
+// the slot of a cause code
+fn slot(code: usize) -> Result<usize, HandlerRegistrationError>{
+    if code < NUM_CAUSE_CODES { Ok(code) } else { Err(HANDLER_ERROR_UnknownCause) }
+}
+
+/// The handler registered for the exception, if any
+pub fn exception_handler(exception: ExceptionType) -> Option<ExceptionHandler>{
+    let index = slot(exception.code()).ok()?;
+    unsafe { EXCEPTION_HANDLERS[index] }
+}
+
+/// The handler registered for the interrupt, if any
+pub fn interrupt_handler(interrupt: InterruptType) -> Option<InterruptHandler>{
+    let index = slot(interrupt.code()).ok()?;
+    unsafe { INTERRUPT_HANDLERS[index] }
+}
+
+/// Makes "handler" the handler of the exception. Returns the handler that it replaces
+pub fn register_exception_handler(exception: ExceptionType, handler: ExceptionHandler) -> Result<Option<ExceptionHandler>, HandlerRegistrationError>{
+    let index = slot(exception.code())?;
+    Ok(unsafe { EXCEPTION_HANDLERS[index].replace(handler) })
+}
+
+/// Removes the handler of the exception : the exception becomes fatal. Returns the handler that was registered
+pub fn unregister_exception_handler(exception: ExceptionType) -> Result<Option<ExceptionHandler>, HandlerRegistrationError>{
+    let index = slot(exception.code())?;
+    Ok(unsafe { EXCEPTION_HANDLERS[index].take() })
+}
+
+/// Makes "handler" the handler of the interrupt. Returns the handler that it replaces
+pub fn register_interrupt_handler(interrupt: InterruptType, handler: InterruptHandler) -> Result<Option<InterruptHandler>, HandlerRegistrationError>{
+    let index = slot(interrupt.code())?;
+    Ok(unsafe { INTERRUPT_HANDLERS[index].replace(handler) })
+}
+
+/// Removes the handler of the interrupt : the interrupt gets reported and ignored. Returns the handler that was registered
+pub fn unregister_interrupt_handler(interrupt: InterruptType) -> Result<Option<InterruptHandler>, HandlerRegistrationError>{
+    let index = slot(interrupt.code())?;
+    Ok(unsafe { INTERRUPT_HANDLERS[index].take() })
+}
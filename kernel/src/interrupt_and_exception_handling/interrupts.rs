@@ -1,11 +1,19 @@
-use super::TrapFrame;
-use crate::drivers::{plic, uart, self};
+use super::{TrapFrame, MAX_HARTS};
+use crate::drivers::plic;
 use crate::{print, println};
 use crate::drivers::timer::Timer;
-use crate::{stdout, stdin};
+use crate::sv39_mmu;
+use crate::riscv;
+use super::delegation;
+use super::nesting;
+use super::handlers;
+use super::trap_stats;
+use super::exceptions::{CauseError, CAUSE_ERROR_NotAnInterrupt, INTERRUPT_BIT};
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// Interrupt enumeration
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum InterruptType
 {
     UserSoftwareInterrupt, // 0
@@ -20,97 +28,151 @@ pub enum InterruptType
     UnknownAsync(usize)// >= 16  
 }
 
-pub fn handle_interrupt(trapframe: &mut TrapFrame){
-    // get interrupt cause
-    let cause = trapframe.mcause & !(1<<63);
+impl TryFrom<usize> for InterruptType{
+    type Error = CauseError;
 
-    // match interrupt
-    match cause{
-        0 => {
-            println!(" Handling UserSoftwareInterrupt");
-        }
-        1 => {
-            println!(" Handling SupervisorSoftwareInterrupt");
-        }
-        3 => {
-            println!(" Handling MachineSoftwareInterrupt");
-        }
-        4 => {
-            println!(" Handling UserTimerInterrupt");
-        }
-        5 => {
-            println!(" Handling SupervisorTimerInterrupt");
-             Timer::mtimecmp_write(Timer::mtime_read() + 10_000_000);
-        }
-        7 => {
-            println!(" Handling MachineTimerInterrupt");
-            Timer::mtimecmp_write(Timer::mtime_read() + 10_000_000);
-        }
-        8 => {
-            println!(" Handling UserExternalInterrupt");
-            sort_external_interrupts();
-        }
-        9 => {
-            println!(" Handling SupervisorExternalInterrupt");
-            sort_external_interrupts();
-        }
-        11 => {
-            println!(" Handling MachineExternalInterrupt");
-            sort_external_interrupts();
-        }
-        _ => {
-            println!(" Captured an undefined Interrupt");
-        }
+    /// Decodes the value of mcause/scause. Codes without a standard meaning become UnknownAsync
+    fn try_from(cause: usize) -> Result<Self, Self::Error>{
+        if cause & INTERRUPT_BIT == 0 { return Err(CAUSE_ERROR_NotAnInterrupt); }
+        Ok(match cause & !INTERRUPT_BIT {
+            0 => InterruptType::UserSoftwareInterrupt,
+            1 => InterruptType::SupervisorSoftwareInterrupt,
+            3 => InterruptType::MachineSoftwareInterrupt,
+            4 => InterruptType::UserTimerInterrupt,
+            5 => InterruptType::SupervisorTimerInterrupt,
+            7 => InterruptType::MachineTimerInterrupt,
+            8 => InterruptType::UserExternalInterrupt,
+            9 => InterruptType::SupervisorExternalInterrupt,
+            11 => InterruptType::MachineExternalInterrupt,
+            code => InterruptType::UnknownAsync(code)
+        })
     }
 }
 
-fn sort_external_interrupts(){
-    // contact plic and determine which device has sent an interrupt
-    let interrupt_ID = plic::read_ID_from_buffer().expect("unable to read plic buffer");
+impl InterruptType{
+    /// The interrupt code, as found in mcause/scause without the interrupt bit
+    pub fn code(&self) -> usize{
+        match self {
+            InterruptType::UserSoftwareInterrupt => 0,
+            InterruptType::SupervisorSoftwareInterrupt => 1,
+            InterruptType::MachineSoftwareInterrupt => 3,
+            InterruptType::UserTimerInterrupt => 4,
+            InterruptType::SupervisorTimerInterrupt => 5,
+            InterruptType::MachineTimerInterrupt => 7,
+            InterruptType::UserExternalInterrupt => 8,
+            InterruptType::SupervisorExternalInterrupt => 9,
+            InterruptType::MachineExternalInterrupt => 11,
+            InterruptType::UnknownAsync(code) => *code
+        }
+    }
+}
 
-    // sort interrupt
-    match interrupt_ID {
-        1..=8 => drivers::handle_interrupt(interrupt_ID),
-        10 => handle_UART_interrupts(),
-        _  => panic!("Received an unknown external Interrupt ID")
+/// Decodes the interrupt of the frame and runs the handler registered for it (see handlers.rs)
+pub fn handle_interrupt(trapframe: &mut TrapFrame){
+    let interrupt = match InterruptType::try_from(trapframe.mcause) {
+        Ok(interrupt) => interrupt,
+        Err(_) => { println!(" The interrupt handler got an exception"); return; }
+    };
+    match handlers::interrupt_handler(interrupt) {
+        Some(handler) => handler(trapframe),
+        None => println!(" Captured an undefined Interrupt : {:?}", interrupt)
     }
+}
 
-    // Notify plic that handling is done
-    let write_result = plic::write_ID_to_buffer(interrupt_ID);
-    match write_result{
-        Ok(()) => {/* Do nothing */},
-        Err(passed_err) => println!("{}", passed_err)
+// the SSIP bit of the sip register
+const SUPERVISOR_SOFTWARE_PENDING_BIT : u64 = 1 << 1;
+
+/// Software interrupts. Another hart asking for a TLB flush raises a MachineSoftwareInterrupt, that the machine mode shim
+/// passes on as a SupervisorSoftwareInterrupt (see delegation.rs).
+/// A SupervisorSoftwareInterrupt stays pending until software clears sip.SSIP : it is cleared first, so that it fires once
+pub fn handle_software_interrupt(trapframe: &mut TrapFrame){
+    match InterruptType::try_from(trapframe.mcause) {
+        Ok(InterruptType::SupervisorSoftwareInterrupt) => {
+            riscv::sip_clear_bits(SUPERVISOR_SOFTWARE_PENDING_BIT);
+            sv39_mmu::handle_shootdown_interrupt();
+        },
+        Ok(interrupt) => println!(" Handling {:?}", interrupt),
+        Err(_) => {}
     }
+}
+
+// the time between two timer interrupts, in mtime ticks
+const TIMER_INTERVAL : usize = 10_000_000;
 
+const ZERO_TICKS : AtomicUsize = AtomicUsize::new(0);
+static TIMER_TICKS : [AtomicUsize; MAX_HARTS] = [ZERO_TICKS; MAX_HARTS];
+static mut TICK_HOOK : Option<fn(usize)> = None;
+
+/// How many timer interrupts the hart has handled
+pub fn timer_ticks(hart_id: usize) -> usize{
+    if hart_id >= MAX_HARTS { return 0; }
+    TIMER_TICKS[hart_id].load(Ordering::Relaxed)
 }
 
-fn handle_UART_interrupts(){
-    // determine which UART interrupt it was
-    let uart_instance = uart::UartDevice::new();
-
-    let interrupt_status_reg = uart_instance.read_interrupt_status_reg();
-    let masked_reg_value = interrupt_status_reg & 0b0000_1111;
-    match masked_reg_value {
-        0b0000_0010 => handle_UART_THR_empty_interrupt(),
-        0b0000_0100 => handle_UART_Data_Ready(),
-        0b0000_1100 => handle_UART_Character_Timeout(),
-        _ => panic!("Unhandled UART interrupt value")
+/// Makes "hook" run on every timer interrupt, with the ID of the hart : a scheduler, for instance. None removes it.
+/// The hook runs preemptible. Registration is meant for boot time, like the handler table (see handlers.rs)
+pub fn register_tick_hook(hook: Option<fn(usize)>) -> Option<fn(usize)>{
+    unsafe {
+        let previous_hook = TICK_HOOK;
+        TICK_HOOK = hook;
+        previous_hook
     }
 }
 
-fn handle_UART_THR_empty_interrupt(){
-    stdout::flush_std_buffer();
-    print!("c");
+// the work of a timer interrupt : count the tick and run the hook
+fn timer_tick(hart_id: usize){
+    TIMER_TICKS[hart_id].fetch_add(1, Ordering::Relaxed);
+    if let Some(hook) = unsafe { TICK_HOOK } { hook(hart_id); }
 }
 
-fn handle_UART_Data_Ready(){
-    let input = stdin::read_line().expect("read line failed");
-    // println!("{}", input);
+/// Timer interrupts
+pub fn handle_timer_interrupt(trapframe: &mut TrapFrame){
+    match InterruptType::try_from(trapframe.mcause) {
+        Ok(InterruptType::SupervisorTimerInterrupt) => { // forwarded by the machine mode shim. Only the shim can clear it, by re-arming the timer
+            delegation::set_timer(Timer::mtime_read() + TIMER_INTERVAL);
+            // re-armed : the tick may be interrupted
+            let hart_id = trapframe.hartid;
+            nesting::run_preemptible(None, || timer_tick(hart_id));
+        },
+        Ok(interrupt) => println!(" Handling {:?}", interrupt),
+        Err(_) => {}
+    }
 }
 
-fn handle_UART_Character_Timeout(){
-    let input = stdin::read_line().expect("read line failed");
-    // println!("{}", input);`
+/// External interrupts, sorted through the PLIC. Only the supervisor handler is preemptible
+pub fn handle_external_interrupt(trapframe: &mut TrapFrame){
+    let interrupt = match InterruptType::try_from(trapframe.mcause) { Ok(interrupt) => interrupt, Err(_) => return };
+    println!(" Handling {:?}", interrupt);
+    sort_external_interrupts(trapframe.hartid, interrupt == InterruptType::SupervisorExternalInterrupt);
+}
+
+/// Claims the interrupt from the PLIC and runs the handler the driver requested for it (see drivers::plic::request_irq).  
+/// In supervisor mode the handler is preemptible : only the sources with a higher priority than the claimed one can interrupt it
+fn sort_external_interrupts(hart_id: usize, preemptible: bool){
+    // contact plic and determine which device has sent an interrupt
+    let interrupt_ID = match plic::read_ID_from_buffer() {
+        Some(interrupt_ID) => interrupt_ID,
+        None => {
+            // another hart claimed it first, nothing to complete
+            plic::record_empty_claim();
+            return;
+        }
+    };
+
+    let start = trap_stats::start_timing();
+    let handle_device = || plic::dispatch_irq(interrupt_ID);
+    let dispatched = if preemptible == true { nesting::run_preemptible(Some(plic::priority_read(interrupt_ID)), handle_device) }
+                     else { handle_device() };
+    // a claim without a handler was counted by dispatch_irq
+    if dispatched == true { trap_stats::record_external_interrupt(hart_id, interrupt_ID, start); }
+
+    // Notify plic that handling is done
+    let write_result = plic::write_ID_to_buffer(interrupt_ID);
+    match write_result{
+        Ok(()) => {/* Do nothing */},
+        Err(passed_err) => println!("{}", passed_err)
+    }
+
 }
 
 
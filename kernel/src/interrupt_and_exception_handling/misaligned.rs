@@ -0,0 +1,116 @@
+//! Emulation of misaligned loads and stores.
+//!
+//! A hart may refuse a load or a store that is not aligned on its width (LoadAddressMisaligned 4, StoreAddressMisaligned 6).
+//! Everything needed to finish the access is in the trap : the instruction at mepc, the address in mtval and the registers
+//! in the TrapFrame. The access is done one byte at a time, the result goes to the target register and mepc moves past the instruction.
+//!
+//! Emulated : the integer loads and stores, 32 bit (lb ... ld, sb ... sd) and compressed (c.lw, c.ld, c.sw, c.sd and the sp relative forms).
+//! Floating point loads and stores are not emulated : the FP registers are saved lazily and may not be in the frame (see floating_point.rs).
+//! An access to memory that is not mapped with the needed permission is not emulated either. The exception then stays fatal.
+
+use super::TrapFrame;
+use super::exceptions::{self, ExceptionInfo, ExceptionHandlingError};
+use crate::{riscv, sv39_mmu};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const OPCODE_LOAD : u32 = 0x03;
+const OPCODE_STORE : u32 = 0x23;
+
+static EMULATED_LOADS : AtomicUsize = AtomicUsize::new(0);
+static EMULATED_STORES : AtomicUsize = AtomicUsize::new(0);
+
+/// How many misaligned accesses have been emulated since boot, on all harts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MisalignedCounts{
+    pub loads : usize,
+    pub stores : usize
+}
+
+pub fn misaligned_emulations() -> MisalignedCounts{
+    MisalignedCounts { loads: EMULATED_LOADS.load(Ordering::Relaxed), stores: EMULATED_STORES.load(Ordering::Relaxed) }
+}
+
+/// A load or a store, as decoded from the faulting instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryAccess{
+    pub is_store : bool,
+    pub width : usize,       // 1, 2, 4 or 8 bytes
+    pub sign_extend : bool,  // loads only : lb, lh, lw and their compressed forms
+    pub register : usize,    // rd of a load, rs2 of a store
+    pub length : usize       // the length of the instruction, 2 or 4
+}
+
+/// Decodes an integer load or store. Returns None for any other instruction
+pub fn decode_memory_access(instruction: u32) -> Option<MemoryAccess>{
+    let funct3 = (instruction >> 13) & 0b111;
+    if instruction & 0b11 != 0b11 {
+        // compressed : rd'/rs2' are x8 - x15 in the quadrant 0 forms
+        let short_register = (((instruction >> 2) & 0b111) + 8) as usize;
+        let (is_store, width, register) = match (instruction & 0b11, funct3) {
+            (0, 2) => (false, 4, short_register),                             // c.lw
+            (0, 3) => (false, 8, short_register),                             // c.ld
+            (0, 6) => (true, 4, short_register),                              // c.sw
+            (0, 7) => (true, 8, short_register),                              // c.sd
+            (2, 2) => (false, 4, ((instruction >> 7) & 0x1f) as usize),       // c.lwsp
+            (2, 3) => (false, 8, ((instruction >> 7) & 0x1f) as usize),       // c.ldsp
+            (2, 6) => (true, 4, ((instruction >> 2) & 0x1f) as usize),        // c.swsp
+            (2, 7) => (true, 8, ((instruction >> 2) & 0x1f) as usize),        // c.sdsp
+            _ => return None
+        };
+        return Some(MemoryAccess { is_store, width, sign_extend: is_store == false && width == 4, register, length: 2 });
+    }
+
+    let funct3 = (instruction >> 12) & 0b111;
+    match instruction & 0x7f {
+        OPCODE_LOAD => {
+            let (width, sign_extend) = match funct3 {
+                0 => (1, true), 1 => (2, true), 2 => (4, true), 3 => (8, false),
+                4 => (1, false), 5 => (2, false), 6 => (4, false),
+                _ => return None
+            };
+            Some(MemoryAccess { is_store: false, width, sign_extend, register: ((instruction >> 7) & 0x1f) as usize, length: 4 })
+        },
+        OPCODE_STORE if funct3 <= 3 => {
+            Some(MemoryAccess { is_store: true, width: 1 << funct3, sign_extend: false, register: ((instruction >> 20) & 0x1f) as usize, length: 4 })
+        },
+        _ => None
+    }
+}
+
+/// The handler of LoadAddressMisaligned (4) and StoreAddressMisaligned (6) in the handler table (see handlers.rs)
+pub fn handle_misaligned_access(trapframe: &mut TrapFrame, info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{
+    match emulate_misaligned_access(trapframe) {
+        Some(next_instruction) => Ok(next_instruction),
+        None => exceptions::handle_fatal_exception(trapframe, info)
+    }
+}
+
+/// Finishes the misaligned access of the trap. Returns the address of the next instruction,
+/// or None if the access cannot be emulated
+pub fn emulate_misaligned_access(trap_frame: &mut TrapFrame) -> Option<usize>{
+    let access = decode_memory_access(riscv::read_instruction(trap_frame.mepc))?;
+    let address = trap_frame.mtval;
+    if sv39_mmu::is_accessible(address, access.width, access.is_store) == false { return None; }
+
+    if access.is_store == true {
+        let bytes = (trap_frame.regs[access.register] as u64).to_le_bytes();
+        for offset in 0..access.width {
+            unsafe { ((address + offset) as *mut u8).write_volatile(bytes[offset]); }
+        }
+        EMULATED_STORES.fetch_add(1, Ordering::Relaxed);
+    }
+    else {
+        let mut bytes = [0u8; 8];
+        for offset in 0..access.width {
+            bytes[offset] = unsafe { ((address + offset) as *const u8).read_volatile() };
+        }
+        let mut value = u64::from_le_bytes(bytes);
+        if access.sign_extend == true {
+            let shift = 64 - 8 * access.width as u32;
+            value = (((value << shift) as i64) >> shift) as u64;
+        }
+        if access.register != 0 { trap_frame.regs[access.register] = value as usize; } // x0 stays zero
+        EMULATED_LOADS.fetch_add(1, Ordering::Relaxed);
+    }
+    Some(trap_frame.mepc + access.length)
+}
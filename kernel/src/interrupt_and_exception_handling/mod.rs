@@ -1,12 +1,51 @@
+//! Trap handling.
+//!
+//! Every execution context owns a TrapFrame, and a trap saves the interrupted context into the frame of that context.
+//! The kernel only handles traps in supervisor mode (the few traps that stay in machine mode are served by the assembly shim, see delegation.rs) :
+//! sscratch points at the frame of the context that is running.
+//! Each hart starts with its own kernel context frame (kernel_supervisor_trap_frames[hart]);
+//! a thread or a process brings its own frame and installs it with switch_trap_frame() on every context change.
+//!
+//! The trap vectors hand the address of the frame over to the Rust handlers, so two contexts never share a frame.
+//! They also switch to the kernel stack of the frame (kernel_sp) before calling them, see trap_stack.rs.
+//! A trap taken inside a handler gets a nested frame of its own, and supervisor handlers can re-enable interrupts with run_preemptible, see nesting.rs.
+//! Misaligned loads and stores are emulated, see misaligned.rs.
+//! The causes are decoded into ExceptionType / InterruptType and dispatched through a table of handlers, see handlers.rs.
+//! Every hart counts its traps and the time spent handling them, see trap_stats.rs.
+
 mod exceptions;
 mod interrupts;
+mod delegation;
+mod floating_point;
+mod trap_stack;
+mod nesting;
+mod misaligned;
+mod page_faults;
+mod syscalls;
+mod handlers;
+mod trap_stats;
+mod tests;
+
+pub use delegation::{init_supervisor_trap_handling, set_timer};
+pub use floating_point::{FpState, FpUsage, fp_state, fp_usage};
+pub use trap_stack::{TRAP_STACK_SIZE, is_in_trap_stack};
+pub use nesting::{run_preemptible, nesting_depth, MAX_NESTING_DEPTH};
+pub use misaligned::{MisalignedCounts, misaligned_emulations};
+pub use exceptions::{ExceptionType, ExceptionInfo, TrapValue, ExceptionHandlingError, CauseError};
+pub use interrupts::{InterruptType, timer_ticks, register_tick_hook};
+pub use handlers::{ExceptionHandler, InterruptHandler, HandlerRegistrationError, register_exception_handler, unregister_exception_handler,
+                   register_interrupt_handler, unregister_interrupt_handler};
+pub use trap_stats::{TrapCounter, exception_stats, interrupt_stats, external_interrupt_stats, spurious_claims, spurious_source_claims, empty_claims,
+                     record_spurious_claim, reset_trap_stats, dump_trap_stats};
 
 use crate::{print, println, riscv};
-use crate::kernel_trap_frame;
+use crate::crash_report;
 use core::arch::asm;
 
 
 /// The trapframe save the context of the CPU when the process that was cut-short was running
+/// The same layout is used for traps taken in supervisor mode : the csr fields then hold sstatus, sepc, sie, scause and stval
+/// The offsets are used by trap.s, keep them in sync
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -21,10 +60,19 @@ pub struct TrapFrame{
     pub mie : usize, // 536
     pub mcause : usize, // 544
     pub mtval : usize, // 552
-    // trap stack 
-    pub trap_stack : [usize; 10] // [560 - 567]
+    // the context
+    pub hartid : usize, // 560 : the hart that the context runs on
+    pub kernel_sp : usize, // 568 : the top of the stack that the trap handlers run on. 0 keeps the interrupted stack
+    // lazily saved floating point state (see floating_point.rs). fregs holds f0-f31
+    pub fcsr : usize, // 576
+    pub fp_saves : usize, // 584
+    pub fp_restores : usize, // 592
 }
 
+/// The number of harts the kernel can run on : each of them gets its own trap frames, trap stack, shim save area and TLB mailbox.
+/// The only definition, every per-hart array is sized with it
+pub const MAX_HARTS : usize = 8;
+
 impl TrapFrame {
 	pub const fn zero() -> Self {
 		TrapFrame { regs:       [0; 32],
@@ -35,24 +83,50 @@ impl TrapFrame {
                     mie:    0,
                     mcause:    0,
                     mtval:    0,
-                    trap_stack:    [0; 10],
+                    hartid:    0,
+                    kernel_sp:    0,
+                    fcsr:    0,
+                    fp_saves:    0,
+                    fp_restores:    0,
                  }
    }
+
+	/// The frame of a new execution context (thread or process) that will run on the hart "hartid"
+	pub const fn new(hartid: usize, kernel_sp: usize) -> Self {
+		let mut trap_frame = TrapFrame::zero();
+		trap_frame.hartid = hartid;
+		trap_frame.kernel_sp = kernel_sp;
+		trap_frame
+	}
 }
 
 
+/// Entry point of the delegated traps (see delegation.rs). Called by asm_supervisor_trap_vector with the frame of the
+/// running context (sscratch), returns the new sepc
 #[no_mangle]
-pub extern "C" fn rust_trap_handler()-> usize{
-
-    println!("I am in rust trap handler");
-    let trap_frame_ref = unsafe{ &mut kernel_trap_frame};
-
-
-    // change the stack pointer to point to the floor of the trapstack
-    // let trap_stack_floor_memory_ptr = (&mut trap_frame_ref.trap_stack[9]) as *mut usize;
-    // let trap_stack_floor_memory_address = trap_stack_floor_memory_ptr as usize; 
-    // unsafe { asm!("mv  sp, {}", in(reg)trap_stack_floor_memory_address)};
+pub extern "C" fn rust_supervisor_trap_handler(trap_frame_ref: &mut TrapFrame)-> usize{
+    let start = trap_stats::start_timing();
+    // IllegalInstruction : maybe the first FP instruction of the context, its FP registers get loaded
+    if trap_frame_ref.mcause == 2 && floating_point::restore_on_demand(trap_frame_ref) == true {
+        trap_stats::record_trap(trap_frame_ref.hartid, trap_frame_ref.mcause, start);
+        return trap_frame_ref.mepc; // retry the instruction
+    }
+    // a trap raised while this one is handled gets a frame of its own (see nesting.rs)
+    let hart_id = trap_frame_ref.hartid;
+    match nesting::run_nested(hart_id, || dispatch_trap(trap_frame_ref, start)) {
+        Some(next_instruction) => next_instruction,
+        None => { // this trap already overwrote the frame of a handler that is still running, nothing can be resumed
+            crash_report::report_trap_crash(trap_frame_ref, &ExceptionHandlingError::UnableToRecoverFromException("Traps nested too deep "));
+            crash_report::halt();
+        }
+    }
+}
 
+/// Hands the trap over to the interrupt or exception handlers. Returns the address of the next instruction.
+/// "start" is the time the trap was taken at (trap_stats::start_timing), its handler time is counted from there
+fn dispatch_trap(trap_frame_ref: &mut TrapFrame, start: usize) -> usize{
+    // the cause and the hart, before a handler changes the frame
+    let (hart_id, cause) = (trap_frame_ref.hartid, trap_frame_ref.mcause);
 
     // check if disturbance was an exception or interrupt
     let was_interrupt = check_if_interrupt(trap_frame_ref);
@@ -60,17 +134,17 @@ pub extern "C" fn rust_trap_handler()-> usize{
     // Handle the exception or interrupt
     if was_interrupt == true {    
         interrupts::handle_interrupt(trap_frame_ref); 
+        trap_stats::record_trap(hart_id, cause, start);
         return trap_frame_ref.mepc;
        }
     else{   
          let exception_handling_result = exceptions::handle_exception(trap_frame_ref);
+         trap_stats::record_trap(hart_id, cause, start);
          match exception_handling_result {
             Ok(address) => {    return address;  },
             Err(exception_handling_error) => {
-                println!("Exeption Handling Error Occurred : {:?}", exception_handling_error);
-                println!("Kernel will shut down");
-                riscv::cpu_shutdown();
-                return 0;
+                crash_report::report_trap_crash(trap_frame_ref, &exception_handling_error);
+                crash_report::halt();
             }
          }
     }
@@ -103,10 +177,20 @@ fn check_if_exception(trapframe: &TrapFrame) -> bool{
 }
 
 
-pub fn init_kernel_trap_handling(){
-    // store kernel trapframe into the mscratch register
-    let kernel_trapframe_ref = unsafe{&crate::kernel_trap_frame};
-    let kernel_trapframe_ptr = kernel_trapframe_ref as *const TrapFrame;
-    let kernel_trapframe_address = kernel_trapframe_ptr as u64;
-    riscv::mscratch_write(kernel_trapframe_address);
+/// Makes "trap_frame" the frame of the context that runs next on this hart. Called in supervisor mode on every context change.  
+/// The delegated traps get saved into it from now on. Returns the frame that was in use, so that it can be put back later.  
+/// The FP registers are switched lazily : they are loaded on the first FP instruction of the new context
+pub fn switch_trap_frame(trap_frame: &mut TrapFrame) -> *mut TrapFrame{
+    let hart_id = riscv::hart_id();
+    trap_frame.hartid = hart_id;
+    // from inside a handler, the frame to replace is the one the handler returns to, not its nested frame
+    let previous_trap_frame = nesting::outermost_trap_frame(hart_id);
+    floating_point::save_before_switch(previous_trap_frame);
+    nesting::replace_outermost_trap_frame(hart_id, trap_frame as *mut TrapFrame);
+    previous_trap_frame
+}
+
+/// The frame of the context that is running on this hart. Only valid in supervisor mode, once init_supervisor_trap_handling is done
+pub fn current_trap_frame() -> *mut TrapFrame{
+    riscv::sscratch_read() as *mut TrapFrame
 }
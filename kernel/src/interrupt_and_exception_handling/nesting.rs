@@ -0,0 +1,114 @@
+//! Nested traps.
+//!
+//! A trap that arrives while another one is being handled must not overwrite the frame of that trap, nor reset sp to the top
+//! of the trap stack under the running handler. Each hart has a small stack of nested frames for that :
+//! the next free one is installed in sscratch while a handler runs (run_nested). Its kernel_sp is 0 :
+//! asm_supervisor_trap_vector then keeps sp, and the nested handler runs on the trap stack below the handler it interrupted.
+//! That covers the exceptions raised inside a handler (a fault, an ebreak in the debug monitor) and the interrupts of preemptible sections.
+//! Once every nested frame of the hart is in use, one more trap would overwrite the frame of a handler that is still running :
+//! the trap handler then stops the kernel instead of resuming garbage.
+//!
+//! The trap path runs with interrupts disabled from entry to sret, so a long handler delays everything else.
+//! run_preemptible runs a piece of a supervisor handler with interrupts enabled again :
+//! 1. The section gets a nested frame of its own, and one more frame must be left for the handler of the interrupt that preempts it.
+//! 2. A nested trap overwrites sstatus.SPP and sstatus.SPIE. They are put back before the outer handler returns.
+//! 3. External interrupts are filtered with the PLIC threshold : while a source of priority p is being served,
+//!    only sources with a priority above p get through. Timer and software interrupts are not filtered.
+//!
+//! Only supervisor mode handlers can be preempted. The machine mode shim always runs to completion.
+
+use super::{TrapFrame, MAX_HARTS};
+use crate::riscv;
+use crate::drivers::plic;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many nested frames one hart has : handlers of nested traps and preemptible sections together
+pub const MAX_NESTING_DEPTH : usize = 4;
+
+const SIE_BIT : u64 = 1 << 1;
+const SPIE_BIT : u64 = 1 << 5;
+const SPP_BIT : u64 = 1 << 8;
+
+const ZERO_FRAMES : [TrapFrame; MAX_NESTING_DEPTH] = [TrapFrame::zero(); MAX_NESTING_DEPTH];
+static mut NESTED_TRAP_FRAMES : [[TrapFrame; MAX_NESTING_DEPTH]; MAX_HARTS] = [ZERO_FRAMES; MAX_HARTS];
+const ZERO_DEPTH : AtomicUsize = AtomicUsize::new(0);
+static NESTING_DEPTH : [AtomicUsize; MAX_HARTS] = [ZERO_DEPTH; MAX_HARTS];
+// the frame that was in sscratch before each nesting level, it goes back there when the level is left
+static mut OUTER_TRAP_FRAMES : [[usize; MAX_NESTING_DEPTH]; MAX_HARTS] = [[0; MAX_NESTING_DEPTH]; MAX_HARTS];
+
+/// The number of preemptible sections that are running on the hart
+pub fn nesting_depth(hart_id: usize) -> usize{
+    assert!(hart_id < MAX_HARTS, "hart {} has no nesting frames, MAX_HARTS is {}", hart_id, MAX_HARTS);
+    NESTING_DEPTH[hart_id].load(Ordering::Relaxed)
+}
+
+// installs the next nested frame of the hart in sscratch. Returns the level it took, or None if every frame is in use.
+// Interrupts must be disabled
+fn enter_nested_level(hart_id: usize) -> Option<usize>{
+    let depth = nesting_depth(hart_id);
+    if depth >= MAX_NESTING_DEPTH { return None; }
+    let nested_trap_frame = unsafe { &mut NESTED_TRAP_FRAMES[hart_id][depth] };
+    *nested_trap_frame = TrapFrame::new(hart_id, 0);
+    unsafe { OUTER_TRAP_FRAMES[hart_id][depth] = riscv::sscratch_read(); }
+    riscv::sscratch_write(nested_trap_frame as *mut TrapFrame as u64);
+    NESTING_DEPTH[hart_id].store(depth + 1, Ordering::Relaxed);
+    Some(depth)
+}
+
+// puts back the frame that was in sscratch before the level was entered
+fn leave_nested_level(hart_id: usize, level: usize){
+    NESTING_DEPTH[hart_id].store(level, Ordering::Relaxed);
+    riscv::sscratch_write(unsafe { OUTER_TRAP_FRAMES[hart_id][level] } as u64);
+}
+
+/// Runs the handler of a trap with a nested frame installed, so that a trap raised by the handler itself gets saved apart.
+/// Returns None without running the handler if the hart has no nested frame left
+pub(super) fn run_nested<R>(hart_id: usize, handler: impl FnOnce() -> R) -> Option<R>{
+    let level = enter_nested_level(hart_id)?;
+    let result = handler();
+    leave_nested_level(hart_id, level);
+    Some(result)
+}
+
+/// The frame that a context change replaces : the frame in sscratch, or the one it goes back to once the nested levels are left
+pub(super) fn outermost_trap_frame(hart_id: usize) -> *mut TrapFrame{
+    if nesting_depth(hart_id) == 0 { riscv::sscratch_read() as *mut TrapFrame }
+    else { unsafe { OUTER_TRAP_FRAMES[hart_id][0] as *mut TrapFrame } }
+}
+
+/// Makes "trap_frame" the frame that the nested levels go back to (see switch_trap_frame)
+pub(super) fn replace_outermost_trap_frame(hart_id: usize, trap_frame: *mut TrapFrame){
+    if nesting_depth(hart_id) == 0 { riscv::sscratch_write(trap_frame as u64); }
+    else { unsafe { OUTER_TRAP_FRAMES[hart_id][0] = trap_frame as usize; } }
+}
+
+/// Runs "section" with supervisor interrupts enabled.
+/// If a threshold is given, the PLIC only lets external interrupts with a higher priority through meanwhile.
+/// Without a nested frame for the section and one for the handler of a preempting interrupt, the section runs with interrupts disabled
+pub fn run_preemptible<R>(threshold: Option<u8>, section: impl FnOnce() -> R) -> R{
+    let hart_id = riscv::hart_id();
+    if nesting_depth(hart_id) + 2 > MAX_NESTING_DEPTH {
+        // even inside another preemptible section : an interrupt now would find no frame
+        let saved_status = riscv::sstatus_read() as u64;
+        riscv::sstatus_clear_bits(SIE_BIT);
+        let result = section();
+        riscv::sstatus_set_bits(saved_status & SIE_BIT);
+        return result;
+    }
+    let level = enter_nested_level(hart_id).expect("a nested frame was checked to be free");
+
+    let saved_status = riscv::sstatus_read() as u64;
+    let saved_threshold = plic::threshold_read();
+    if let Some(threshold) = threshold { plic::threshold_write(threshold).expect("invalid PLIC threshold"); }
+
+    riscv::sstatus_set_bits(SIE_BIT);
+    let result = section();
+    riscv::sstatus_clear_bits(SIE_BIT);
+
+    plic::threshold_write(saved_threshold).expect("invalid PLIC threshold");
+    riscv::sstatus_clear_bits(SPP_BIT | SPIE_BIT);
+    riscv::sstatus_set_bits(saved_status & (SPP_BIT | SPIE_BIT));
+
+    leave_nested_level(hart_id, level);
+    result
+}
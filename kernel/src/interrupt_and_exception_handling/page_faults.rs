@@ -0,0 +1,173 @@
+//! Page faults : copy-on-write, demand paging and kernel stack overflows.
+//!
+//! Registered in the handler table (see handlers.rs) for InstructionPageFault (12), LoadPageFault (13) and StorePageFault (15).
+
+use super::TrapFrame;
+use super::exceptions::{ExceptionInfo, ExceptionType, ExceptionHandlingError};
+use crate::{print, println};
+use crate::sv39_mmu::{self, VmaBacking, VirtAddr, PhysFrame, TlbFlush};
+use crate::page_manager;
+use crate::map_kernel;
+
+/// Handles the three page faults. The faulting instruction gets retried once the page is mapped or made writable
+pub fn handle_page_fault(trapframe: &mut TrapFrame, info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{
+    match info.exception {
+        ExceptionType::InstructionPageFault => {
+            println!("Handling InstructionPageFault");
+            if handle_demand_paging_fault(trapframe, FaultAccess::Instruction) == true {
+                return Ok(trapframe.mepc); // retry the fetch, the page is now mapped
+            }
+            Err(ExceptionHandlingError::UnableToRecoverFromException("InstructionPageFault occured "))
+        },
+        ExceptionType::LoadPageFault => {
+            println!("Handling LoadPageFault");
+            if report_kernel_stack_overflow(trapframe) == true {
+                return Err(ExceptionHandlingError::UnableToRecoverFromException("Kernel stack overflow "));
+            }
+            if handle_demand_paging_fault(trapframe, FaultAccess::Load) == true {
+                return Ok(trapframe.mepc); // retry the load, the page is now mapped
+            }
+            Err(ExceptionHandlingError::UnableToRecoverFromException("LoadPageFault occured "))
+        },
+        ExceptionType::StorePageFault => {
+            println!("Handling StorePageFault");
+            if report_kernel_stack_overflow(trapframe) == true {
+                return Err(ExceptionHandlingError::UnableToRecoverFromException("Kernel stack overflow "));
+            }
+            if handle_copy_on_write_fault(trapframe) == true {
+                return Ok(trapframe.mepc); // retry the store, the page is now writable
+            }
+            if handle_demand_paging_fault(trapframe, FaultAccess::Store) == true {
+                return Ok(trapframe.mepc); // retry the store, the page is now mapped
+            }
+            Err(ExceptionHandlingError::UnableToRecoverFromException("StorePageFault occured "))
+        },
+        _ => Err(ExceptionHandlingError::UnableToRecoverFromException("The page fault handler got an exception that is not a page fault "))
+    }
+}
+
+/// Resolves a StorePageFault that was caused by writing to a copy-on-write page.   
+/// The faulting virtual address is in mtval and the address space is the one that was active (satp) when the trap happened.  
+/// 1. If other address spaces still share the page, the page gets copied and the writer gets the private copy.  
+/// 2. If the writer is the last user of the page, the page is simply made writable again.   
+/// Returns false if the fault was not a copy-on-write fault. That is a real protection violation
+fn handle_copy_on_write_fault(trapframe: &TrapFrame) -> bool{
+    let root_table = match sv39_mmu::root_table_from_satp(trapframe.satp) {
+        Some(root_table) => root_table,
+        None => return false // translation was off, there are no page tables to fix
+    };
+    let faulting_page = match VirtAddr::new(trapframe.mtval as u64) {
+        Ok(virt_address) => virt_address.containing_page(),
+        Err(_) => return false
+    };
+    let leaf_entry = match sv39_mmu::find_leaf_entry(root_table, faulting_page) {
+        Ok(entry) => entry,
+        Err(_) => return false
+    };
+    if leaf_entry.check_if_copy_on_write() == false { return false; }
+
+    let shared_page = leaf_entry.get_frame();
+    if page_manager::get_share_count(shared_page) > 1 {
+        // copy the page. Both frames are reached through the direct map
+        let private_frame = page_manager::alloc(1).expect("unable to allocate a page for a copy-on-write copy");
+        let source = map_kernel::phys_to_virt(shared_page.start_address()).as_u64() as *const u8;
+        let destination = map_kernel::phys_to_virt(private_frame.start_address()).as_u64() as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping(source, destination, page_manager::PAGE_SIZE); }
+        leaf_entry.replace_address(private_frame.as_u64());
+        leaf_entry.set_as_not_copy_on_write();
+        leaf_entry.set_as_writable();
+        // no hart may keep translating to the shared page once this address space lets go of it
+        sv39_mmu::shootdown(TlbFlush::Page(faulting_page.as_u64()));
+        page_manager::release_page(shared_page).expect("unable to release a copy-on-write page");
+        return true;
+    }
+
+    leaf_entry.set_as_not_copy_on_write();
+    leaf_entry.set_as_writable();
+    sv39_mmu::shootdown(TlbFlush::Page(faulting_page.as_u64()));
+    return true;
+}
+
+/// Checks if a page fault hit the guard page below the kernel stack. If it did, the fault gets reported as a stack overflow
+fn report_kernel_stack_overflow(trapframe: &TrapFrame) -> bool{
+    if map_kernel::is_in_kernel_stack_guard(trapframe.mtval as u64) == false { return false; }
+    println!("\t Kernel stack overflow : access to 0x{:x} (guard page) from 0x{:x}, sp = 0x{:x}", trapframe.mtval, trapframe.mepc, trapframe.regs[2]);
+    return true;
+}
+
+/// The kind of access that caused a page fault
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultAccess{
+    Instruction, // 12
+    Load,        // 13
+    Store        // 15
+}
+
+impl FaultAccess{
+    // the permission bit (R, W or X) that the access needs
+    fn required_permission(&self) -> u64{
+        match self {
+            FaultAccess::Instruction => 8u64,
+            FaultAccess::Load => 2u64,
+            FaultAccess::Store => 4u64,
+        }
+    }
+}
+
+/// Maps a page on demand.  
+/// The faulting address (mtval) is looked up in the virtual memory areas of the current address space.   
+/// If it falls inside an area that allows the access, the page gets filled according to the backing of the area :   
+/// Anonymous and Stack areas get a zeroed page, Device areas map the matching physical page (not owned).   
+/// Returns false for real violations : an address outside every area, an access that the area does not allow,
+/// or a fault on a page that is already mapped (the permissions of the page forbid the access)
+fn handle_demand_paging_fault(trapframe: &TrapFrame, access: FaultAccess) -> bool{
+    let address_space = match sv39_mmu::current_address_space() {
+        Some(address_space) => address_space,
+        None => return false
+    };
+    // the handler only knows about the areas of the current address space
+    if sv39_mmu::root_table_from_satp(trapframe.satp) != Some(address_space.root_table()) { return false; }
+
+    let faulting_address = match VirtAddr::new(trapframe.mtval as u64) {
+        Ok(virt_address) => virt_address,
+        Err(_) => return false
+    };
+    let faulting_page = faulting_address.containing_page();
+    let vma = match address_space.find_area(faulting_address) {
+        Some(vma) => vma,
+        None => {
+            println!("\t Page fault at 0x{:x} : the address is not part of any area", faulting_address.as_u64());
+            return false;
+        }
+    };
+    if vma.access_map & access.required_permission() == 0 {
+        println!("\t Protection violation at 0x{:x} : {:?} access is not allowed in the area", faulting_address.as_u64(), access);
+        return false;
+    }
+    if address_space.translate(faulting_page.start_address()).is_ok() {
+        println!("\t Protection violation at 0x{:x} : the page is mapped but does not allow {:?} access", faulting_address.as_u64(), access);
+        return false;
+    }
+
+    match vma.backing {
+        VmaBacking::Anonymous | VmaBacking::Stack => {
+            let frame = match page_manager::alloc(1) {
+                Ok(frame) => frame,
+                Err(_) => return false
+            };
+            unsafe { core::ptr::write_bytes(map_kernel::phys_to_virt(frame.start_address()).as_u64() as *mut u8, 0, page_manager::PAGE_SIZE); }
+            address_space.map_owned(faulting_page, frame, vma.access_map).is_ok()
+        },
+        VmaBacking::Device { physical_start } => {
+            let frame = match PhysFrame::new(physical_start + (faulting_page.as_u64() - vma.start)) {
+                Ok(frame) => frame,
+                Err(_) => return false
+            };
+            address_space.map(faulting_page, frame, vma.access_map).is_ok()
+        },
+        VmaBacking::File { .. } => {
+            println!("\t Page fault at 0x{:x} : file backed areas are not supported yet", faulting_address.as_u64());
+            false
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Environment calls (ecall).
+//!
+//! Registered in the handler table (see handlers.rs) for UserEnvironmentCall (8), SupervisorEnvironmentCall (9)
+//! and MachineEnvironmentCall (11). Only UserEnvironmentCall is delegated : a SupervisorEnvironmentCall is a call to the
+//! machine mode shim (see delegation.rs) and never reaches these handlers.
+
+use super::TrapFrame;
+use super::exceptions::{ExceptionInfo, ExceptionType, ExceptionHandlingError};
+use crate::{print, println};
+
+/// Serves an ecall. There are no system calls yet : the caller simply resumes at $ra + 4
+pub fn handle_environment_call(trapframe: &mut TrapFrame, info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{
+    match info.exception {
+        ExceptionType::UserEnvironmentCall => {
+            println!("Handling UserEnvironmentCall");
+            let next_nonfaulty_instruction = trapframe.regs[1] + 4; // $ra + 4
+            Ok(next_nonfaulty_instruction)
+        },
+        ExceptionType::SupervisorEnvironmentCall => {
+            println!("Handling SupervisorEnvironmentCall");
+            let next_nonfaulty_instruction = trapframe.regs[1] + 4; // $ra + 4
+            Ok(next_nonfaulty_instruction)
+        },
+        _ => {
+            println!("Handling MachineEnvironmentCall");
+            Err(ExceptionHandlingError::UnableToRecoverFromException("MachineEnvironmentCall occured "))
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use crate::riscv;
+use super::{TrapFrame, switch_trap_frame, current_trap_frame};
+use super::trap_stack::{supervisor_trap_stack_top, is_in_trap_stack, TRAP_STACK_SIZE};
+use super::floating_point::{fp_state, FpState, is_floating_point_instruction};
+use super::misaligned::{decode_memory_access, emulate_misaligned_access, MemoryAccess};
+use super::exceptions::{ExceptionType, ExceptionInfo, TrapValue, ExceptionHandlingError, CauseError};
+use super::interrupts::{InterruptType, handle_timer_interrupt, timer_ticks, register_tick_hook};
+use super::handlers::{self, register_exception_handler, NUM_CAUSE_CODES};
+use super::trap_stats::{self, TrapCounter, exception_stats, interrupt_stats, external_interrupt_stats, spurious_claims, spurious_source_claims, empty_claims, reset_trap_stats, dump_trap_stats};
+use super::{MAX_HARTS, run_preemptible, nesting_depth, MAX_NESTING_DEPTH};
+use super::nesting::run_nested;
+use crate::drivers::plic;
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[test_case]
+pub fn trap_handling_test_switch(){
+    println!("\n---------  Running trap handling tests  ---------\n");
+    test_trap_frame_offsets_match_trap_s();
+    test_new_trap_frame_carries_context();
+    test_fp_state_decoding();
+    test_floating_point_instruction_detection();
+    test_compressed_floating_point_instruction_detection();
+    test_dirty_fp_state_gets_saved_before_switch();
+    test_trap_stacks_are_aligned_and_apart();
+    test_memory_access_decoding();
+    test_misaligned_accesses_get_emulated();
+    test_cause_decoding();
+    test_exception_info_carries_trap_value();
+    test_exception_handler_registration();
+    test_preemptible_section_restores_the_hart();
+    test_preemptible_sections_stop_nesting_at_the_limit();
+    test_nested_traps_get_a_frame_of_their_own();
+    test_switch_inside_a_handler_replaces_the_outer_frame();
+    test_timer_tick_takes_nested_interrupts();
+    test_trap_stats_counting();
+    test_trap_stats_dump();
+}
+
+// the byte offset of a field inside the frame
+fn offset_of(trap_frame: &TrapFrame, field: *const usize) -> usize{
+    field as usize - (trap_frame as *const TrapFrame as usize)
+}
+
+fn test_trap_frame_offsets_match_trap_s(){
+    let trap_frame = TrapFrame::zero();
+    let res = [offset_of(&trap_frame, &trap_frame.satp), offset_of(&trap_frame, &trap_frame.mstatus),
+               offset_of(&trap_frame, &trap_frame.mepc), offset_of(&trap_frame, &trap_frame.mie),
+               offset_of(&trap_frame, &trap_frame.mcause), offset_of(&trap_frame, &trap_frame.mtval),
+               offset_of(&trap_frame, &trap_frame.hartid), offset_of(&trap_frame, &trap_frame.kernel_sp),
+               offset_of(&trap_frame, &trap_frame.fcsr), offset_of(&trap_frame, &trap_frame.fp_saves)];
+    let suc_msg = "test_trap_frame_offsets_match_trap_s    ....   [OK]";
+    let fail_msg = "test_trap_frame_offsets_match_trap_s   ....    [FAIL]";
+    custom_assert([512, 520, 528, 536, 544, 552, 560, 568, 576, 584], res, suc_msg, fail_msg);
+}
+
+fn test_new_trap_frame_carries_context(){
+    let trap_frame = TrapFrame::new(3, 0x8040_0000);
+    let res = (trap_frame.hartid, trap_frame.kernel_sp, trap_frame.mepc);
+    let suc_msg = "test_new_trap_frame_carries_context    ....   [OK]";
+    let fail_msg = "test_new_trap_frame_carries_context   ....    [FAIL]";
+    custom_assert((3, 0x8040_0000, 0), res, suc_msg, fail_msg);
+}
+
+fn test_fp_state_decoding(){
+    let res = (fp_state(0), fp_state(1 << 13), fp_state(2 << 13), fp_state((3 << 13) | 0x22));
+    let suc_msg = "test_fp_state_decoding    ....   [OK]";
+    let fail_msg = "test_fp_state_decoding   ....    [FAIL]";
+    custom_assert((FpState::Off, FpState::Initial, FpState::Clean, FpState::Dirty), res, suc_msg, fail_msg);
+}
+
+fn test_floating_point_instruction_detection(){
+    let res = (is_floating_point_instruction(0x0231_00d3),  // fadd.d f1, f2, f3
+               is_floating_point_instruction(0x0005_3007),  // fld f0, 0(a0)
+               is_floating_point_instruction(0x0030_2573),  // csrr a0, fcsr
+               is_floating_point_instruction(0x0031_00b3),  // add x1, x2, x3
+               is_floating_point_instruction(0x3000_2573)); // csrr a0, mstatus
+    let suc_msg = "test_floating_point_instruction_detection    ....   [OK]";
+    let fail_msg = "test_floating_point_instruction_detection   ....    [FAIL]";
+    custom_assert((true, true, true, false, false), res, suc_msg, fail_msg);
+}
+
+fn test_compressed_floating_point_instruction_detection(){
+    let res = (is_floating_point_instruction(0x2000),  // c.fld f8, 0(s0)
+               is_floating_point_instruction(0xa000),  // c.fsd f8, 0(s0)
+               is_floating_point_instruction(0x2082),  // c.fldsp f1, 0(sp)
+               is_floating_point_instruction(0xa006),  // c.fsdsp f1, 0(sp)
+               is_floating_point_instruction(0x6108),  // c.ld a0, 0(a0)
+               is_floating_point_instruction(0x6082),  // c.ldsp ra, 0(sp)
+               is_floating_point_instruction(0x2505)); // c.addiw a0, 1 (quadrant 1, funct3 1)
+    let suc_msg = "test_compressed_floating_point_instruction_detection    ....   [OK]";
+    let fail_msg = "test_compressed_floating_point_instruction_detection   ....    [FAIL]";
+    custom_assert((true, true, true, true, false, false, false), res, suc_msg, fail_msg);
+}
+
+// a context change away from a test frame and back : the Dirty FP state of the test frame gets saved into it and FP is Off afterwards.
+// The test frame shares the trap stack of the running frame, in case a trap arrives in between. FS is put back at the end
+fn test_dirty_fp_state_gets_saved_before_switch(){
+    let fs_mask = 0b11 << 13;
+    let fs_before = riscv::sstatus_read() & fs_mask;
+    let mut test_trap_frame = TrapFrame::zero();
+    test_trap_frame.kernel_sp = unsafe { (*current_trap_frame()).kernel_sp };
+
+    riscv::sstatus_clear_bits(fs_mask as u64); // Off : nothing gets saved into the running frame
+    let previous_trap_frame = switch_trap_frame(&mut test_trap_frame);
+    riscv::sstatus_set_bits(fs_mask as u64); // Dirty
+    let switched_to = switch_trap_frame(unsafe { &mut *previous_trap_frame });
+    let after_dirty = (test_trap_frame.fp_saves, fp_state(riscv::sstatus_read()));
+    riscv::sstatus_set_bits(fs_before as u64);
+
+    let res = (switched_to == &mut test_trap_frame as *mut TrapFrame, current_trap_frame() == previous_trap_frame, after_dirty);
+    let suc_msg = "test_dirty_fp_state_gets_saved_before_switch    ....   [OK]";
+    let fail_msg = "test_dirty_fp_state_gets_saved_before_switch   ....    [FAIL]";
+    custom_assert((true, true, (1, FpState::Off)), res, suc_msg, fail_msg);
+}
+
+fn test_trap_stacks_are_aligned_and_apart(){
+    let first_top = supervisor_trap_stack_top(0);
+    let second_top = supervisor_trap_stack_top(1);
+    let res = (first_top % 16, second_top % 16, second_top - first_top,
+               is_in_trap_stack(first_top - 8), is_in_trap_stack(second_top - TRAP_STACK_SIZE), is_in_trap_stack(first_top - TRAP_STACK_SIZE - 1));
+    let suc_msg = "test_trap_stacks_are_aligned_and_apart    ....   [OK]";
+    let fail_msg = "test_trap_stacks_are_aligned_and_apart   ....    [FAIL]";
+    custom_assert((0, 0, TRAP_STACK_SIZE, true, true, false), res, suc_msg, fail_msg);
+}
+
+fn test_memory_access_decoding(){
+    let res = (decode_memory_access(0x0015_2283),  // lw t0, 1(a0)
+               decode_memory_access(0x0055_30a3),  // sd t0, 1(a0)
+               decode_memory_access(0x0005_4583),  // lbu a1, 0(a0)
+               decode_memory_access(0x6104),       // c.ld s1, 0(a0)
+               decode_memory_access(0xc004),       // c.sw s1, 0(s0)
+               decode_memory_access(0x0000_0013)); // nop
+    let expected = (Some(MemoryAccess { is_store: false, width: 4, sign_extend: true, register: 5, length: 4 }),
+                    Some(MemoryAccess { is_store: true, width: 8, sign_extend: false, register: 5, length: 4 }),
+                    Some(MemoryAccess { is_store: false, width: 1, sign_extend: false, register: 11, length: 4 }),
+                    Some(MemoryAccess { is_store: false, width: 8, sign_extend: false, register: 9, length: 2 }),
+                    Some(MemoryAccess { is_store: true, width: 4, sign_extend: false, register: 9, length: 2 }),
+                    None);
+    let suc_msg = "test_memory_access_decoding    ....   [OK]";
+    let fail_msg = "test_memory_access_decoding   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+// a fake trap : the instructions and the data live in arrays, mepc and mtval point into them
+fn test_misaligned_accesses_get_emulated(){
+    let instructions : [u32; 2] = [0x0015_2283, 0x0055_30a3]; // lw t0, 1(a0) ; sd t0, 1(a0)
+    let mut data = [0u8; 16];
+    data[1..5].copy_from_slice(&0x8765_4321u32.to_le_bytes());
+    let data_address = data.as_mut_ptr() as usize;
+
+    let mut trap_frame = TrapFrame::zero();
+    trap_frame.mepc = instructions.as_ptr() as usize;
+    trap_frame.mtval = data_address + 1;
+    let after_load = emulate_misaligned_access(&mut trap_frame);
+    let loaded = trap_frame.regs[5];
+
+    trap_frame.mepc = instructions.as_ptr() as usize + 4;
+    trap_frame.mtval = data_address + 9;
+    let after_store = emulate_misaligned_access(&mut trap_frame);
+    let stored = unsafe { ((data_address + 9) as *const u64).read_unaligned() };
+
+    let res = (after_load, loaded, after_store.is_some(), stored);
+    let suc_msg = "test_misaligned_accesses_get_emulated    ....   [OK]";
+    let fail_msg = "test_misaligned_accesses_get_emulated   ....    [FAIL]";
+    custom_assert((Some(instructions.as_ptr() as usize + 4), 0xffff_ffff_8765_4321usize, true, 0xffff_ffff_8765_4321u64), res, suc_msg, fail_msg);
+}
+
+fn test_cause_decoding(){
+    let res = (ExceptionType::try_from(13), ExceptionType::try_from(14), InterruptType::try_from((1 << 63) | 9),
+               InterruptType::try_from((1 << 63) | 20), ExceptionType::try_from((1 << 63) | 5).is_err(),
+               InterruptType::try_from(5).is_err(), ExceptionType::StorePageFault.code(), InterruptType::MachineExternalInterrupt.code());
+    let expected = (Ok(ExceptionType::LoadPageFault), Ok(ExceptionType::UnknownSync(14)), Ok(InterruptType::SupervisorExternalInterrupt),
+                    Ok(InterruptType::UnknownAsync(20)), true, true, 15, 11);
+    let suc_msg = "test_cause_decoding    ....   [OK]";
+    let fail_msg = "test_cause_decoding   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn test_exception_info_carries_trap_value(){
+    let mut trap_frame = TrapFrame::zero();
+    trap_frame.mcause = 15;
+    trap_frame.mtval = 0x8000_4000;
+    let res = (ExceptionInfo::try_from(&trap_frame), ExceptionInfo::decode(2, 0xffff_ffff), ExceptionInfo::decode(2, 0),
+               ExceptionInfo::decode(8, 0x1234).map(|info| info.value), ExceptionInfo::decode(1 << 63, 0).is_err());
+    let expected = (Ok(ExceptionInfo { exception: ExceptionType::StorePageFault, value: TrapValue::FaultingAddress(0x8000_4000) }),
+                    Ok(ExceptionInfo { exception: ExceptionType::IllegalInstruction, value: TrapValue::Instruction(0xffff_ffff) }),
+                    Ok(ExceptionInfo { exception: ExceptionType::IllegalInstruction, value: TrapValue::None }),
+                    Ok::<TrapValue, CauseError>(TrapValue::None), true);
+    let suc_msg = "test_exception_info_carries_trap_value    ....   [OK]";
+    let fail_msg = "test_exception_info_carries_trap_value   ....    [FAIL]";
+    custom_assert(expected, res, suc_msg, fail_msg);
+}
+
+fn resume_at_zero(_trap_frame: &mut TrapFrame, _info: &ExceptionInfo) -> Result<usize, ExceptionHandlingError<'static>>{ Ok(0) }
+
+// a reserved code gets a handler, then the slot is emptied again
+fn test_exception_handler_registration(){
+    let reserved = ExceptionType::UnknownSync(14);
+    let mut trap_frame = TrapFrame::zero();
+    let info = ExceptionInfo { exception: reserved, value: TrapValue::None };
+    let before = handlers::exception_handler(reserved).is_none();
+    let registered = register_exception_handler(reserved, resume_at_zero).map(|previous| previous.is_none());
+    let dispatched = handlers::exception_handler(reserved).map(|handler| handler(&mut trap_frame, &info).ok());
+    let removed = handlers::unregister_exception_handler(reserved).map(|previous| previous.is_some());
+    let out_of_table = register_exception_handler(ExceptionType::UnknownSync(NUM_CAUSE_CODES), resume_at_zero).is_err();
+
+    let res = (before, registered, dispatched, removed, out_of_table);
+    let suc_msg = "test_exception_handler_registration    ....   [OK]";
+    let fail_msg = "test_exception_handler_registration   ....    [FAIL]";
+    custom_assert((true, Ok(true), Some(Some(0)), Ok(true), true), res, suc_msg, fail_msg);
+}
+
+const SIE_BIT : u64 = 1 << 1;
+const SPIE_BIT : u64 = 1 << 5;
+const SPP_BIT : u64 = 1 << 8;
+
+// the section clobbers SPP and SPIE the way a nested trap would. The depth, the threshold, sscratch, SPP and SPIE must be back afterwards
+fn test_preemptible_section_restores_the_hart(){
+    let hart_id = riscv::hart_id();
+    let saved_status = riscv::sstatus_read() as u64;
+    riscv::sstatus_set_bits(SPP_BIT);
+    riscv::sstatus_clear_bits(SPIE_BIT);
+    let depth_before = nesting_depth(hart_id);
+    let threshold_before = plic::threshold_read();
+    let trap_frame_before = current_trap_frame();
+
+    let inside = run_preemptible(Some(7), || {
+        let inside = (nesting_depth(hart_id), plic::threshold_read(), riscv::sstatus_read() as u64 & SIE_BIT != 0, current_trap_frame() != trap_frame_before);
+        riscv::sstatus_clear_bits(SPP_BIT);
+        riscv::sstatus_set_bits(SPIE_BIT);
+        inside
+    });
+    let status_after = riscv::sstatus_read() as u64;
+    let after = (nesting_depth(hart_id), plic::threshold_read(), status_after & SPP_BIT != 0, status_after & SPIE_BIT != 0,
+                 status_after & SIE_BIT != 0, current_trap_frame() == trap_frame_before);
+
+    riscv::sstatus_clear_bits(SPP_BIT | SPIE_BIT);
+    riscv::sstatus_set_bits(saved_status & (SIE_BIT | SPP_BIT | SPIE_BIT));
+    let res = (inside, after);
+    let suc_msg = "test_preemptible_section_restores_the_hart    ....   [OK]";
+    let fail_msg = "test_preemptible_section_restores_the_hart   ....    [FAIL]";
+    custom_assert(((depth_before + 1, 7, true, true), (depth_before, threshold_before, true, false, false, true)), res, suc_msg, fail_msg);
+}
+
+// nests one section more than the frames allow : the innermost one returns the depth it saw and whether interrupts were enabled
+fn nest_preemptible_sections(levels: usize) -> (usize, bool){
+    run_preemptible(None, || {
+        if levels > 1 { nest_preemptible_sections(levels - 1) }
+        else { (nesting_depth(riscv::hart_id()), riscv::sstatus_read() as u64 & SIE_BIT != 0) }
+    })
+}
+
+fn test_preemptible_sections_stop_nesting_at_the_limit(){
+    let hart_id = riscv::hart_id();
+    let depth_before = nesting_depth(hart_id);
+    let innermost = nest_preemptible_sections(MAX_NESTING_DEPTH - depth_before);
+    let res = (innermost, nesting_depth(hart_id));
+    let suc_msg = "test_preemptible_sections_stop_nesting_at_the_limit    ....   [OK]";
+    let fail_msg = "test_preemptible_sections_stop_nesting_at_the_limit   ....    [FAIL]";
+    // the last frame stays free for the handler of an interrupt that preempts the innermost section
+    custom_assert(((MAX_NESTING_DEPTH - 1, false), depth_before), res, suc_msg, fail_msg);
+}
+
+// nests handlers the way traps raised inside handlers do, until no frame is left. Returns how many handlers ran
+fn nest_trap_handlers(hart_id: usize) -> usize{
+    match run_nested(hart_id, || nest_trap_handlers(hart_id)) {
+        Some(handlers) => handlers + 1,
+        None => 0
+    }
+}
+
+// a handler runs with a nested frame in sscratch : a trap it raises keeps sp (kernel_sp == 0) and leaves the outer frame alone.
+// Interrupts are off, as in a handler
+fn test_nested_traps_get_a_frame_of_their_own(){
+    let hart_id = riscv::hart_id();
+    let saved_status = riscv::sstatus_read() as u64;
+    riscv::sstatus_clear_bits(SIE_BIT);
+    let depth_before = nesting_depth(hart_id);
+    let outer_trap_frame = current_trap_frame();
+
+    let inside = run_nested(hart_id, || {
+        let nested_trap_frame = current_trap_frame();
+        (nesting_depth(hart_id), nested_trap_frame != outer_trap_frame, unsafe { (*nested_trap_frame).kernel_sp })
+    });
+    let handlers = nest_trap_handlers(hart_id);
+    let after = (nesting_depth(hart_id), current_trap_frame() == outer_trap_frame);
+    riscv::sstatus_set_bits(saved_status & SIE_BIT);
+
+    let res = (inside, handlers, after);
+    let suc_msg = "test_nested_traps_get_a_frame_of_their_own    ....   [OK]";
+    let fail_msg = "test_nested_traps_get_a_frame_of_their_own   ....    [FAIL]";
+    custom_assert((Some((depth_before + 1, true, 0)), MAX_NESTING_DEPTH - depth_before, (depth_before, true)), res, suc_msg, fail_msg);
+}
+
+// a context change from inside a handler : the handler keeps its nested frame, the new frame is the one it returns to
+fn test_switch_inside_a_handler_replaces_the_outer_frame(){
+    let hart_id = riscv::hart_id();
+    let saved_status = riscv::sstatus_read() as u64;
+    riscv::sstatus_clear_bits(SIE_BIT);
+    let mut test_trap_frame = TrapFrame::zero();
+    test_trap_frame.kernel_sp = unsafe { (*current_trap_frame()).kernel_sp };
+    let outer_trap_frame = current_trap_frame();
+
+    let inside = run_nested(hart_id, || {
+        let nested_trap_frame = current_trap_frame();
+        let previous_trap_frame = switch_trap_frame(&mut test_trap_frame);
+        (previous_trap_frame == outer_trap_frame, current_trap_frame() == nested_trap_frame)
+    });
+    let returns_to = current_trap_frame();
+    switch_trap_frame(unsafe { &mut *outer_trap_frame });
+    riscv::sstatus_set_bits(saved_status & SIE_BIT);
+
+    let res = (inside, returns_to == &mut test_trap_frame as *mut TrapFrame, current_trap_frame() == outer_trap_frame);
+    let suc_msg = "test_switch_inside_a_handler_replaces_the_outer_frame    ....   [OK]";
+    let fail_msg = "test_switch_inside_a_handler_replaces_the_outer_frame   ....    [FAIL]";
+    custom_assert((Some((true, true)), true, true), res, suc_msg, fail_msg);
+}
+
+// SupervisorSoftwareInterrupts taken while the tick hook of the test ran
+static SOFTWARE_INTERRUPTS_IN_TICK : AtomicUsize = AtomicUsize::new(0);
+
+// the tick hook of the test : raises a SupervisorSoftwareInterrupt (sip.SSIP) on its own hart.
+// The tick is preemptible : the interrupt is taken before the hook returns
+fn raise_software_interrupt_in_tick(hart_id: usize){
+    let taken_before = interrupt_stats(hart_id, InterruptType::SupervisorSoftwareInterrupt).taken;
+    riscv::sip_set_bits(1 << 1);
+    let taken_after = interrupt_stats(hart_id, InterruptType::SupervisorSoftwareInterrupt).taken;
+    SOFTWARE_INTERRUPTS_IN_TICK.store(taken_after - taken_before, Ordering::Relaxed);
+}
+
+// runs the timer handler the way a trap does (interrupts disabled), with the hook of the test registered.
+// A real timer interrupt may add a tick meanwhile
+fn test_timer_tick_takes_nested_interrupts(){
+    let hart_id = riscv::hart_id();
+    let saved_status = riscv::sstatus_read() as u64;
+    riscv::sstatus_clear_bits(SIE_BIT);
+    let mut trap_frame = TrapFrame::new(hart_id, 0);
+    trap_frame.mcause = InterruptType::SupervisorTimerInterrupt.code() | super::exceptions::INTERRUPT_BIT;
+    let ticks_before = timer_ticks(hart_id);
+    SOFTWARE_INTERRUPTS_IN_TICK.store(0, Ordering::Relaxed);
+
+    let previous_hook = register_tick_hook(Some(raise_software_interrupt_in_tick));
+    handle_timer_interrupt(&mut trap_frame);
+    register_tick_hook(previous_hook);
+    let res = (timer_ticks(hart_id) > ticks_before, SOFTWARE_INTERRUPTS_IN_TICK.load(Ordering::Relaxed) >= 1, riscv::sstatus_read() as u64 & SIE_BIT != 0);
+    riscv::sstatus_set_bits(saved_status & SIE_BIT);
+
+    let suc_msg = "test_timer_tick_takes_nested_interrupts    ....   [OK]";
+    let fail_msg = "test_timer_tick_takes_nested_interrupts   ....    [FAIL]";
+    custom_assert((true, true, false), res, suc_msg, fail_msg);
+}
+
+// a hart that the tests run on never has this ID, its counters belong to the tests
+const STATS_TEST_HART : usize = MAX_HARTS - 1;
+
+fn test_trap_stats_counting(){
+    reset_trap_stats(STATS_TEST_HART);
+    let start = trap_stats::start_timing();
+    trap_stats::record_trap(STATS_TEST_HART, ExceptionType::LoadPageFault.code(), start);
+    trap_stats::record_trap(STATS_TEST_HART, ExceptionType::LoadPageFault.code(), start);
+    trap_stats::record_trap(STATS_TEST_HART, InterruptType::SupervisorTimerInterrupt.code() | super::exceptions::INTERRUPT_BIT, start);
+    trap_stats::record_trap(STATS_TEST_HART, 40, start); // out of the table : not counted
+    trap_stats::record_external_interrupt(STATS_TEST_HART, 10, start);
+    trap_stats::record_spurious_claim(STATS_TEST_HART, 12);
+    trap_stats::record_spurious_claim(STATS_TEST_HART, 0); // no ID
+    trap_stats::record_spurious_claim(STATS_TEST_HART, 1000); // beyond the table : only in the total
+    let counted = (exception_stats(STATS_TEST_HART, ExceptionType::LoadPageFault).taken,
+                   interrupt_stats(STATS_TEST_HART, InterruptType::SupervisorTimerInterrupt).taken,
+                   exception_stats(STATS_TEST_HART, ExceptionType::StorePageFault).taken,
+                   external_interrupt_stats(STATS_TEST_HART, 10).taken, spurious_claims(STATS_TEST_HART),
+                   exception_stats(MAX_HARTS, ExceptionType::LoadPageFault).taken);
+    let spurious = (spurious_source_claims(STATS_TEST_HART, 12), spurious_source_claims(STATS_TEST_HART, 0), empty_claims(STATS_TEST_HART));
+    reset_trap_stats(STATS_TEST_HART);
+    let after_reset = (exception_stats(STATS_TEST_HART, ExceptionType::LoadPageFault), spurious_claims(STATS_TEST_HART));
+
+    let res = (counted, spurious, after_reset);
+    let suc_msg = "test_trap_stats_counting    ....   [OK]";
+    let fail_msg = "test_trap_stats_counting   ....    [FAIL]";
+    custom_assert(((2, 1, 0, 1, 3, 0), (1, 0, 1), (TrapCounter { taken: 0, handler_ticks: 0 }, 0)), res, suc_msg, fail_msg);
+}
+
+// collects the dump, to look for lines in it
+struct DumpBuffer{
+    bytes : [u8; 4096],
+    length : usize
+}
+
+impl Write for DumpBuffer{
+    fn write_str(&mut self, out: &str) -> fmt::Result{
+        let end = (self.length + out.len()).min(self.bytes.len());
+        self.bytes[self.length..end].copy_from_slice(&out.as_bytes()[..end - self.length]);
+        self.length = end;
+        Ok(())
+    }
+}
+
+fn test_trap_stats_dump(){
+    reset_trap_stats(STATS_TEST_HART);
+    let start = trap_stats::start_timing();
+    trap_stats::record_trap(STATS_TEST_HART, ExceptionType::Breakpoint.code(), start);
+    trap_stats::record_external_interrupt(STATS_TEST_HART, 10, start);
+    trap_stats::record_spurious_claim(STATS_TEST_HART, 12);
+    let mut dump = DumpBuffer { bytes: [0; 4096], length: 0 };
+    let written = dump_trap_stats(&mut dump);
+    reset_trap_stats(STATS_TEST_HART);
+
+    let text = core::str::from_utf8(&dump.bytes[..dump.length]).unwrap_or("");
+    // the test hart is the last one, its section ends the dump
+    let hart_section = text.rsplit("hart ").next().unwrap_or("");
+    let res = (written.is_ok(), hart_section.contains("Breakpoint"), hart_section.contains("PLIC source 10"),
+               hart_section.contains("spurious PLIC claims 1"), hart_section.contains("LoadPageFault"));
+    let suc_msg = "test_trap_stats_dump    ....   [OK]";
+    let fail_msg = "test_trap_stats_dump   ....    [FAIL]";
+    custom_assert((true, true, true, true, false), res, suc_msg, fail_msg);
+}
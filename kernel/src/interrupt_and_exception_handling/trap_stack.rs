@@ -0,0 +1,38 @@
+//! The kernel trap stacks.
+//!
+//! The trap handlers used to run on whatever stack was active when the trap hit, so a trap on a corrupted or nearly full
+//! stack crashed the handler too. Every hart now owns a trap stack for the supervisor handlers of its kernel context.
+//! (The machine mode shim in trap.s needs no stack.)
+//! The top of the stack is kept in the kernel_sp field of the trap frame. The trap vectors switch to it after saving the
+//! registers, and the interrupted sp comes back with the other registers on return.
+
+use super::MAX_HARTS;
+
+/// The size of one trap stack, in bytes
+pub const TRAP_STACK_SIZE : usize = 4 * 4096;
+
+// the RISC-V calling convention wants sp aligned to 16 bytes
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct TrapStack([u8; TRAP_STACK_SIZE]);
+
+static mut SUPERVISOR_TRAP_STACKS : [TrapStack; MAX_HARTS] = [TrapStack([0; TRAP_STACK_SIZE]); MAX_HARTS];
+
+// the address just above the stack, the stack grows down from there
+fn stack_top(stack: &TrapStack) -> usize{
+    stack as *const TrapStack as usize + TRAP_STACK_SIZE
+}
+
+/// The initial sp of the supervisor mode trap stack of the hart
+pub fn supervisor_trap_stack_top(hart_id: usize) -> usize{
+    assert!(hart_id < MAX_HARTS, "hart {} has no trap stack, MAX_HARTS is {}", hart_id, MAX_HARTS);
+    stack_top(unsafe { &SUPERVISOR_TRAP_STACKS[hart_id] })
+}
+
+/// true if the address belongs to one of the trap stacks
+pub fn is_in_trap_stack(address: usize) -> bool{
+    (0..MAX_HARTS).any(|hart_id| {
+        let supervisor_top = supervisor_trap_stack_top(hart_id);
+        address >= supervisor_top - TRAP_STACK_SIZE && address < supervisor_top
+    })
+}
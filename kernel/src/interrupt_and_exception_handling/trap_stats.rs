@@ -0,0 +1,214 @@
+//! Trap statistics.
+//!
+//! Every hart counts the traps it takes, per exception code and per interrupt code, and the time spent handling them.
+//! External interrupts are also counted per PLIC source, together with the spurious claims :
+//! a claimed ID without a handler, or a claim that returned no ID at all (see drivers::plic::dispatch_irq).
+//! These are the only spurious claim counters : drivers::plic::spurious_count and empty_claim_count add up the ones of every hart.
+//! An interrupt storm from the UART or a virtio device shows up as a source with a count that keeps climbing.
+//!
+//! The time is read with Timer::mtime_read, in mtime ticks. The time of a handler includes the time of the traps
+//! that preempted it (see nesting.rs). Codes above NUM_CAUSE_CODES are not counted.
+//! The counters are atomics : a nested trap may update them while the handler it interrupted is recording.
+
+use super::MAX_HARTS;
+use super::handlers::NUM_CAUSE_CODES;
+use super::exceptions::{ExceptionType, INTERRUPT_BIT};
+use super::interrupts::InterruptType;
+use crate::drivers::plic::MAX_INTERRUPT_SOURCES;
+use crate::drivers::timer::Timer;
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// How often a cause (or a PLIC source) was taken on a hart, and the time its handler took
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrapCounter{
+    pub taken : usize,
+    pub handler_ticks : usize  // mtime ticks, all the traps together
+}
+
+// one pair of counters
+struct Counter{
+    taken : AtomicUsize,
+    handler_ticks : AtomicUsize
+}
+
+impl Counter{
+    const fn zero() -> Self{
+        Counter { taken: AtomicUsize::new(0), handler_ticks: AtomicUsize::new(0) }
+    }
+
+    fn record(&self, ticks: usize){
+        self.taken.fetch_add(1, Ordering::Relaxed);
+        self.handler_ticks.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> TrapCounter{
+        TrapCounter { taken: self.taken.load(Ordering::Relaxed), handler_ticks: self.handler_ticks.load(Ordering::Relaxed) }
+    }
+
+    fn reset(&self){
+        self.taken.store(0, Ordering::Relaxed);
+        self.handler_ticks.store(0, Ordering::Relaxed);
+    }
+}
+
+// the counters of one hart
+struct HartStats{
+    exceptions : [Counter; NUM_CAUSE_CODES],
+    interrupts : [Counter; NUM_CAUSE_CODES],
+    external_sources : [Counter; MAX_INTERRUPT_SOURCES],
+    spurious_sources : [AtomicUsize; MAX_INTERRUPT_SOURCES], // claimed IDs without a handler. ID 0 : the claims that returned no ID
+    spurious_out_of_range : AtomicUsize // claimed IDs beyond MAX_INTERRUPT_SOURCES, they cannot have a handler
+}
+
+const ZERO_COUNTER : Counter = Counter::zero();
+const ZERO_COUNT : AtomicUsize = AtomicUsize::new(0);
+const ZERO_HART_STATS : HartStats = HartStats {
+    exceptions: [ZERO_COUNTER; NUM_CAUSE_CODES],
+    interrupts: [ZERO_COUNTER; NUM_CAUSE_CODES],
+    external_sources: [ZERO_COUNTER; MAX_INTERRUPT_SOURCES],
+    spurious_sources: [ZERO_COUNT; MAX_INTERRUPT_SOURCES],
+    spurious_out_of_range: AtomicUsize::new(0)
+};
+static TRAP_STATS : [HartStats; MAX_HARTS] = [ZERO_HART_STATS; MAX_HARTS];
+
+/// The time to pass to the record functions once the handler is done
+pub fn start_timing() -> usize{
+    Timer::mtime_read()
+}
+
+fn elapsed_since(start: usize) -> usize{
+    Timer::mtime_read().wrapping_sub(start)
+}
+
+/// Counts a trap of cause "cause" (the value of mcause/scause) whose handling started at "start"
+pub fn record_trap(hart_id: usize, cause: usize, start: usize){
+    if hart_id >= MAX_HARTS { return; }
+    let code = cause & !INTERRUPT_BIT;
+    if code >= NUM_CAUSE_CODES { return; }
+    let stats = &TRAP_STATS[hart_id];
+    let counter = if cause & INTERRUPT_BIT != 0 { &stats.interrupts[code] } else { &stats.exceptions[code] };
+    counter.record(elapsed_since(start));
+}
+
+/// Counts an external interrupt of PLIC source "interrupt_id" whose handler started at "start"
+pub fn record_external_interrupt(hart_id: usize, interrupt_id: u32, start: usize){
+    if hart_id >= MAX_HARTS || interrupt_id as usize >= MAX_INTERRUPT_SOURCES { return; }
+    TRAP_STATS[hart_id].external_sources[interrupt_id as usize].record(elapsed_since(start));
+}
+
+/// Counts a PLIC claim of "interrupt_id" that found no handler. interrupt_id is 0 for a claim that returned no ID
+pub fn record_spurious_claim(hart_id: usize, interrupt_id: u32){
+    if hart_id >= MAX_HARTS { return; }
+    let stats = &TRAP_STATS[hart_id];
+    match stats.spurious_sources.get(interrupt_id as usize) {
+        Some(count) => count.fetch_add(1, Ordering::Relaxed),
+        None => stats.spurious_out_of_range.fetch_add(1, Ordering::Relaxed)
+    };
+}
+
+/// The counters of an exception on a hart
+pub fn exception_stats(hart_id: usize, exception: ExceptionType) -> TrapCounter{
+    let code = exception.code();
+    if hart_id >= MAX_HARTS || code >= NUM_CAUSE_CODES { return Counter::zero().read(); }
+    TRAP_STATS[hart_id].exceptions[code].read()
+}
+
+/// The counters of an interrupt on a hart
+pub fn interrupt_stats(hart_id: usize, interrupt: InterruptType) -> TrapCounter{
+    let code = interrupt.code();
+    if hart_id >= MAX_HARTS || code >= NUM_CAUSE_CODES { return Counter::zero().read(); }
+    TRAP_STATS[hart_id].interrupts[code].read()
+}
+
+/// The counters of a PLIC source on a hart
+pub fn external_interrupt_stats(hart_id: usize, interrupt_id: u32) -> TrapCounter{
+    if hart_id >= MAX_HARTS || interrupt_id as usize >= MAX_INTERRUPT_SOURCES { return Counter::zero().read(); }
+    TRAP_STATS[hart_id].external_sources[interrupt_id as usize].read()
+}
+
+/// The number of spurious PLIC claims on a hart, all IDs together
+pub fn spurious_claims(hart_id: usize) -> usize{
+    if hart_id >= MAX_HARTS { return 0; }
+    let stats = &TRAP_STATS[hart_id];
+    stats.spurious_sources.iter().map(|count| count.load(Ordering::Relaxed)).sum::<usize>() + stats.spurious_out_of_range.load(Ordering::Relaxed)
+}
+
+/// The number of claims of a PLIC source that found no handler on a hart
+pub fn spurious_source_claims(hart_id: usize, interrupt_id: u32) -> usize{
+    if hart_id >= MAX_HARTS || interrupt_id == 0 || interrupt_id as usize >= MAX_INTERRUPT_SOURCES { return 0; }
+    TRAP_STATS[hart_id].spurious_sources[interrupt_id as usize].load(Ordering::Relaxed)
+}
+
+/// The number of PLIC claims that returned no ID on a hart
+pub fn empty_claims(hart_id: usize) -> usize{
+    if hart_id >= MAX_HARTS { return 0; }
+    TRAP_STATS[hart_id].spurious_sources[0].load(Ordering::Relaxed)
+}
+
+/// Sets all the counters of a hart back to 0
+pub fn reset_trap_stats(hart_id: usize){
+    if hart_id >= MAX_HARTS { return; }
+    let stats = &TRAP_STATS[hart_id];
+    stats.exceptions.iter().chain(stats.interrupts.iter()).chain(stats.external_sources.iter()).for_each(|counter| counter.reset());
+    stats.spurious_sources.iter().for_each(|count| count.store(0, Ordering::Relaxed));
+    stats.spurious_out_of_range.store(0, Ordering::Relaxed);
+}
+
+fn write_counter(console: &mut dyn Write, name: fmt::Arguments, counter: TrapCounter) -> fmt::Result{
+    let name_width = 34;
+    let mut name_length = NameLength(0);
+    let _ = fmt::write(&mut name_length, name);
+    write!(console, "    {}", name)?;
+    for _ in name_length.0..name_width { console.write_char(' ')?; }
+    write!(console, "taken {:>8}   ticks {:>12}   avg {:>8}\r\n", counter.taken, counter.handler_ticks, counter.handler_ticks / counter.taken)
+}
+
+// measures the length of a formatted name, to line the columns up
+struct NameLength(usize);
+
+impl Write for NameLength{
+    fn write_str(&mut self, out: &str) -> fmt::Result{
+        self.0 += out.len();
+        Ok(())
+    }
+}
+
+/// Writes the counters of every hart that took a trap. Causes and sources that were never taken are left out
+pub fn dump_trap_stats(console: &mut dyn Write) -> fmt::Result{
+    write!(console, "Trap statistics (handler time in mtime ticks)\r\n")?;
+    for hart_id in 0..MAX_HARTS {
+        let stats = &TRAP_STATS[hart_id];
+        let spurious = spurious_claims(hart_id);
+        let mut all_counters = stats.exceptions.iter().chain(stats.interrupts.iter()).chain(stats.external_sources.iter());
+        if all_counters.all(|counter| counter.read().taken == 0) == true && spurious == 0 { continue; }
+
+        write!(console, "hart {}\r\n", hart_id)?;
+        for code in 0..NUM_CAUSE_CODES {
+            let counter = stats.exceptions[code].read();
+            if counter.taken == 0 { continue; }
+            match ExceptionType::try_from(code) {
+                Ok(ExceptionType::UnknownSync(code)) => write_counter(console, format_args!("exception {}", code), counter)?,
+                Ok(exception) => write_counter(console, format_args!("{:?}", exception), counter)?,
+                Err(_) => {}
+            }
+        }
+        for code in 0..NUM_CAUSE_CODES {
+            let counter = stats.interrupts[code].read();
+            if counter.taken == 0 { continue; }
+            match InterruptType::try_from(code | INTERRUPT_BIT) {
+                Ok(InterruptType::UnknownAsync(code)) => write_counter(console, format_args!("interrupt {}", code), counter)?,
+                Ok(interrupt) => write_counter(console, format_args!("{:?}", interrupt), counter)?,
+                Err(_) => {}
+            }
+        }
+        for interrupt_id in 1..MAX_INTERRUPT_SOURCES {
+            let counter = stats.external_sources[interrupt_id].read();
+            if counter.taken == 0 { continue; }
+            write_counter(console, format_args!("PLIC source {}", interrupt_id), counter)?;
+        }
+        write!(console, "    spurious PLIC claims {}\r\n", spurious)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! The kernel symbol table.
+//!
+//! Raw addresses in crash reports are useless without running addr2line by hand. The kernel image therefore carries
+//! its own symbol table, in the .ksymtab section : every function address with its demangled name.
+//!
+//! The table cannot be produced by the link that it describes. So the build works in two steps :
+//! 1. the kernel links with an empty .ksymtab of KSYMTAB_SIZE bytes (KSYMTAB_RESERVED below)
+//! 2. tools/embed_ksymtab.py reads the symbols of the linked image and writes the table into that section.
+//!    The section keeps its size, so no address moves. The cargo runner (tools/run.sh) does it before booting.
+//!
+//! Layout (little endian) :
+//! - header : magic "KSYM" (u32), number of entries (u32), offset of the string area (u32), size of the string area (u32)
+//! - entries, sorted by address : address (u64), offset of the name in the string area (u32), length of the name (u32)
+//! - string area : the names, one after the other, without separators
+//!
+//! The last entry marks the end of the text section, so that the last function has an end too.
+//!
+//! An image that skipped step 2 still works : lookup returns None and the addresses get printed bare.
+
+mod tests;
+
+use core::fmt;
+
+/// The room reserved for the table in the kernel image. Raise it if tools/embed_ksymtab.py reports that the table does not fit
+pub const KSYMTAB_SIZE : usize = 256 * 1024;
+
+const MAGIC : u32 = 0x4d59_534b; // "KSYM"
+const HEADER_SIZE : usize = 16;
+const ENTRY_SIZE : usize = 16;
+
+#[used]
+#[link_section = ".ksymtab"]
+static KSYMTAB_RESERVED : [u8; KSYMTAB_SIZE] = [0; KSYMTAB_SIZE];
+
+extern "C"{
+    // the table is read through the linker symbols : the compiler must not assume that the section is still zeroed
+    static KSYMTAB_START: usize;
+    static KSYMTAB_END: usize;
+}
+
+/// A position in the code : the function that contains the address, and the distance from the start of the function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SymbolLocation<'a>{
+    pub name : &'a str,
+    pub symbol_address : u64,
+    pub offset : u64
+}
+
+impl<'a> fmt::Display for SymbolLocation<'a>{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}+0x{:x}", self.name, self.offset) }
+}
+
+/// A symbol table in the layout described above
+pub struct SymbolTable<'a>{
+    entries : &'a [u8],
+    strings : &'a [u8],
+    count : usize
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32{
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64{
+    (read_u32(bytes, at) as u64) | ((read_u32(bytes, at + 4) as u64) << 32)
+}
+
+impl<'a> SymbolTable<'a>{
+    /// Checks the header and the bounds of the table. Returns None for an empty or damaged table
+    pub fn parse(bytes: &'a [u8]) -> Option<Self>{
+        if bytes.len() < HEADER_SIZE || read_u32(bytes, 0) != MAGIC { return None; }
+        let count = read_u32(bytes, 4) as usize;
+        let strings_offset = read_u32(bytes, 8) as usize;
+        let strings_size = read_u32(bytes, 12) as usize;
+        let entries_end = HEADER_SIZE.checked_add(count.checked_mul(ENTRY_SIZE)?)?;
+        let strings_end = strings_offset.checked_add(strings_size)?;
+        if entries_end > strings_offset || strings_end > bytes.len() { return None; }
+        Some(SymbolTable { entries: &bytes[HEADER_SIZE..entries_end], strings: &bytes[strings_offset..strings_end], count })
+    }
+
+    pub fn len(&self) -> usize{ self.count }
+
+    /// The address and the name of the entry at "index"
+    pub fn entry(&self, index: usize) -> Option<(u64, &'a str)>{
+        if index >= self.count { return None; }
+        let at = index * ENTRY_SIZE;
+        let name_offset = read_u32(self.entries, at + 8) as usize;
+        let name_length = read_u32(self.entries, at + 12) as usize;
+        let name_bytes = self.strings.get(name_offset..name_offset.checked_add(name_length)?)?;
+        let name = core::str::from_utf8(name_bytes).unwrap_or("<invalid name>");
+        Some((read_u64(self.entries, at), name))
+    }
+
+    /// The symbol that contains the address : the last symbol that starts at or below it.
+    /// Addresses below the first symbol, or past the last one, are not part of the table
+    pub fn lookup(&self, address: u64) -> Option<SymbolLocation<'a>>{
+        if self.count == 0 { return None; }
+        // binary search for the first symbol that starts above the address
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.entry(middle)?.0 <= address { low = middle + 1; } else { high = middle; }
+        }
+        if low == 0 || low == self.count { return None; }
+        let (symbol_address, name) = self.entry(low - 1)?;
+        Some(SymbolLocation { name, symbol_address, offset: address - symbol_address })
+    }
+}
+
+/// The table embedded in the kernel image, if the build filled it in
+pub fn kernel_symbol_table() -> Option<SymbolTable<'static>>{
+    let start = unsafe { KSYMTAB_START };
+    let end = unsafe { KSYMTAB_END };
+    if end <= start { return None; }
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    SymbolTable::parse(bytes)
+}
+
+/// The function that contains the address, as found in the kernel symbol table
+pub fn lookup(address: u64) -> Option<SymbolLocation<'static>>{
+    kernel_symbol_table()?.lookup(address)
+}
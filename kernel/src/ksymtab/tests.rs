@@ -0,0 +1,63 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use super::{SymbolTable, SymbolLocation};
+
+#[test_case]
+pub fn ksymtab_test_switch(){
+    println!("\n---------  Running ksymtab tests  ---------\n");
+    test_lookup_finds_containing_function();
+    test_lookup_outside_text_fails();
+    test_parse_rejects_bad_tables();
+}
+
+// _start at 0x8000_0000, kinit at 0x8000_0100, end of the text at 0x8000_0400
+const NAMES : &[u8] = b"_startkinit<end of text>";
+const SYMBOLS : [(u64, u32, u32); 3] = [(0x8000_0000, 0, 6), (0x8000_0100, 6, 5), (0x8000_0400, 11, 13)];
+
+// lays the symbols out the way tools/embed_ksymtab.py does
+fn build_table(buffer: &mut [u8; 128]){
+    let strings_offset = 16 + 16 * SYMBOLS.len();
+    buffer[0..4].copy_from_slice(&0x4d59_534bu32.to_le_bytes());
+    buffer[4..8].copy_from_slice(&(SYMBOLS.len() as u32).to_le_bytes());
+    buffer[8..12].copy_from_slice(&(strings_offset as u32).to_le_bytes());
+    buffer[12..16].copy_from_slice(&(NAMES.len() as u32).to_le_bytes());
+    for (index, (address, name_offset, name_length)) in SYMBOLS.iter().enumerate() {
+        let at = 16 + 16 * index;
+        buffer[at..at + 8].copy_from_slice(&address.to_le_bytes());
+        buffer[at + 8..at + 12].copy_from_slice(&name_offset.to_le_bytes());
+        buffer[at + 12..at + 16].copy_from_slice(&name_length.to_le_bytes());
+    }
+    buffer[strings_offset..strings_offset + NAMES.len()].copy_from_slice(NAMES);
+}
+
+fn test_lookup_finds_containing_function(){
+    let mut buffer = [0u8; 128];
+    build_table(&mut buffer);
+    let table = SymbolTable::parse(&buffer).unwrap();
+    let res = (table.lookup(0x8000_0000), table.lookup(0x8000_0124));
+    let suc_msg = "test_lookup_finds_containing_function    ....   [OK]";
+    let fail_msg = "test_lookup_finds_containing_function   ....    [FAIL]";
+    custom_assert((Some(SymbolLocation { name: "_start", symbol_address: 0x8000_0000, offset: 0 }),
+                   Some(SymbolLocation { name: "kinit", symbol_address: 0x8000_0100, offset: 0x24 })), res, suc_msg, fail_msg);
+}
+
+fn test_lookup_outside_text_fails(){
+    let mut buffer = [0u8; 128];
+    build_table(&mut buffer);
+    let table = SymbolTable::parse(&buffer).unwrap();
+    let res = (table.lookup(0x7fff_fff0), table.lookup(0x8000_0400), table.lookup(0x9000_0000));
+    let suc_msg = "test_lookup_outside_text_fails    ....   [OK]";
+    let fail_msg = "test_lookup_outside_text_fails   ....    [FAIL]";
+    custom_assert((None, None, None), res, suc_msg, fail_msg);
+}
+
+fn test_parse_rejects_bad_tables(){
+    let empty = [0u8; 128];
+    let mut truncated = [0u8; 128];
+    build_table(&mut truncated);
+    truncated[4] = 100; // more entries than the buffer can hold
+    let res = (SymbolTable::parse(&empty).is_none(), SymbolTable::parse(&truncated).is_none());
+    let suc_msg = "test_parse_rejects_bad_tables    ....   [OK]";
+    let fail_msg = "test_parse_rejects_bad_tables   ....    [FAIL]";
+    custom_assert((true, true), res, suc_msg, fail_msg);
+}
@@ -18,6 +18,7 @@ pub mod drivers;
 pub mod asm;
 pub mod stdout;
 pub mod stdin;
+pub mod physical_memory;
 pub mod page_manager;
 pub mod sv39_mmu;
 pub mod test_framework; 
@@ -25,6 +26,9 @@ pub mod map_kernel;
 pub mod riscv;
 pub mod interrupt_and_exception_handling;
 pub mod byte_manager;
+pub mod crash_report;
+pub mod ksymtab;
+pub mod debug_monitor;
 
 
 
@@ -33,13 +37,16 @@ use core::{arch::asm, panic::PanicInfo};
 use core::fmt::Write; // enable the use of Write functions in this scope
 pub use alloc::{string::String, vec};
 
-pub use crate::interrupt_and_exception_handling::TrapFrame;
-pub use crate::sv39_mmu::{map, show_mappings, unmap, translate};
+pub use crate::interrupt_and_exception_handling::{TrapFrame, MAX_HARTS};
+pub use crate::sv39_mmu::{map, show_mappings, unmap, translate, AddressSpace};
 pub use crate::drivers::timer::Timer;
 
 pub static mut kernel_satp_value_gl: usize = 0;
 pub static mut kernel_root_table_address_gl : usize = 0;
-pub static mut kernel_trap_frame : TrapFrame = TrapFrame::zero();
+// The frames of the kernel context of each hart. Every trap the kernel handles is delegated to supervisor mode
+pub static mut kernel_supervisor_trap_frames : [TrapFrame; MAX_HARTS] = [TrapFrame::zero(); MAX_HARTS];
+// The kernel address space. kernel_root_table_address_gl and kernel_satp_value_gl are derived from it
+pub static mut kernel_address_space_gl : Option<AddressSpace> = None;
 
 
 
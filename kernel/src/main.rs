@@ -16,36 +16,44 @@ use hobo_os::page_manager;
 use hobo_os::byte_manager;
 use hobo_os::sv39_mmu;
 use hobo_os::map_kernel;
-use hobo_os::interrupt_and_exception_handling::{TrapFrame, init_kernel_trap_handling};
+use hobo_os::crash_report;
+use hobo_os::debug_monitor;
+use hobo_os::interrupt_and_exception_handling::TrapFrame;
 
 use hobo_os::String as String;
 use hobo_os::vec::Vec; // uses the alloc crate in the background
 
 // import the BIG THREE
-use hobo_os::{kernel_trap_frame, kernel_root_table_address_gl, kernel_satp_value_gl};
+use hobo_os::{kernel_root_table_address_gl, kernel_satp_value_gl};
+use hobo_os::{kernel_address_space_gl, AddressSpace};
 
 // defining the entry point function
 // kinit returns the satp value .  
 // this value gets used to update the satp register before executing kmain
 #[no_mangle]
 pub extern "C" fn kinit () {
-    println!("I am in Machine mode... mad Chad");
+    println!("I am in Supervisor mode, running on the boot page table");
     // Initialize stuff
-        trap_handler::init_kernel_trap_handling(); // places a kernel trap frame static address in the mscratch register
+        trap_handler::init_supervisor_trap_handling(); // places a kernel trap frame static address in the sscratch register, installs stvec
         drivers::init_all_hardwired_drivers();  // configure the drivers {PLIC, CLINT, UART}. This does NOT include things like HardDisks which are attached instead of hardwired
         page_manager::init_memory();  // memory initialization... demarcates the physical memory into pages+descriptors
+        sv39_mmu::probe_paging_mode();  // pick Sv48 if the CPU supports it, otherwise stay with Sv39
+        sv39_mmu::mark_hart_online(riscv::hart_id()); // this hart takes part in TLB shootdowns
 
     
-    // import and update the BIG THREE VARIABLES that will be used by the kernel while in supervisor mode
-       let kernel_trapframe_ref: &mut TrapFrame = unsafe { &mut kernel_trap_frame };
+    // import and update the BIG THREE VARIABLES that will be used by the kernel
        let kernel_satp_value_ref = unsafe { &mut kernel_satp_value_gl };
        let kernel_root_table_address_ref = unsafe { &mut kernel_root_table_address_gl};
 
-       *kernel_root_table_address_ref = page_manager::alloc(1).unwrap();
-       *kernel_satp_value_ref = 0usize | (8 << 60) | (*kernel_root_table_address_ref >> 12);
+       let mut kernel_address_space = AddressSpace::new();
 
-    // identity map the machine memory before switching to Supervisor mode
-        map_kernel::identity_map_kernel(*kernel_root_table_address_ref);
+    // map the kernel before switching away from the boot page table
+        map_kernel::map_kernel(&mut kernel_address_space); // higher half direct map
+        map_kernel::show_kernel_memory_map();
+
+       *kernel_root_table_address_ref = kernel_address_space.root_table_address() as usize;
+       *kernel_satp_value_ref = kernel_address_space.satp();
+       unsafe { kernel_address_space_gl = Some(kernel_address_space); }
 
     // initialize the kernel heap and make it byte-accessible
     // The Rust global allocator can only affect the kernel heap only
@@ -66,7 +74,7 @@ pub extern "C" fn kinit () {
 
     // Begin the Test
     println!("\n---------------------\n");
-    println!("This is a test for the integration of the Block device into the kernel while in machine mode");
+    println!("This is a test for the integration of the Block device into the kernel, before kmain");
     println!("\n---------------------\n");
 
     println!("The Test will validate that the following actions are possible: ");
@@ -117,36 +125,36 @@ pub extern "C" fn kinit () {
 
 
 
-
-
     // Done setting things up
         println!("\n-------\n");
-        println!("Done setting things up ; Switching to Supervisor mode...");
+        println!("Done setting things up ; Switching to the kernel address space...");
 
 }
 
 #[no_mangle]
 pub extern "C" fn kmain() -> (){
     println!("\n-------\n");
-    println!("We are in Supervisor Mode!");
+    println!("We are in kmain, supervisor interrupts are on!");
 
     // import and update the BIG THREE VARIABLES 
-       let kernel_trapframe_ref: &mut TrapFrame = unsafe { &mut kernel_trap_frame };
+       let kernel_trapframe_ref: &mut TrapFrame = unsafe { &mut *trap_handler::current_trap_frame() };
        let kernel_satp_value_ref = unsafe { &mut kernel_satp_value_gl };
        let kernel_root_table_address_ref = unsafe { &mut kernel_root_table_address_gl};
 
-    riscv::satp_write(*kernel_satp_value_ref as u64);
+    // switch the MMU on. The kernel address space becomes the current address space of the page-fault handler
+    unsafe { kernel_address_space_gl.as_mut().unwrap().activate(); }
 
-    // Show that the MMU is switched on --- Mode of SATP = 8
+    // Show that the kernel address space is in use --- Mode of SATP = 8 (Sv39) or 9 (Sv48)
     println!("\n-------\n");
     let satp_value = riscv::satp_read();
-    println!("As proof, here is the satp value; The SATP_MODE = 8");
+    println!("As proof, here is the satp value; The SATP_MODE = {} ({:?})", sv39_mmu::paging_mode().satp_mode(), sv39_mmu::paging_mode());
+    println!("The kernel is linked in the higher half, at 0x{:x}", map_kernel::KERNEL_VIRT_BASE);
     println!("SATP : {:064b}", satp_value);
 
     // Show that we can still access the entire RAM
     println!("\n-------\n");
-    println!("Testing if we can still access every part of the RAM even if we are in supervisor... regardless of whether a page is allocated or not");
-    let trans_result = sv39_mmu::translate(*kernel_root_table_address_ref as u64, 0x080005000);
+    println!("Testing if we can still access every part of the RAM through the direct map... regardless of whether a page is allocated or not");
+    let trans_result = unsafe { kernel_address_space_gl.as_ref().unwrap().translate(sv39_mmu::VirtAddr::new(map_kernel::PHYS_MAP_OFFSET + 0x080005000).unwrap()) };
     if trans_result.is_err() {  println!("\t Test Failed")}
     else {  println!("Test Passed"); }
 
@@ -163,14 +171,10 @@ pub extern "C" fn kmain() -> (){
 // defining the function that will always get called after a panic
 #[panic_handler]
 fn panic_handler (panic_info: &PanicInfo) -> !{
-    // make the current CPU_core sleep endlessly and wait for Interrupt
-    let panic_location = panic_info.location().unwrap();
-    let panic_message = panic_info.message().unwrap();
-    // println!("Panic occured : in file : {}, line {}, with the message : {:?}",
-    //                                              panic_location.file(), panic_location.line(), panic_message);
-    loop {
-       unsafe { asm!("wfi");  }
-    }
+    // report the panic without relying on the heap or the UART driver, then make the current CPU_core sleep endlessly
+    crash_report::report_panic(panic_info);
+    debug_monitor::enter_after_panic(); // inspect the memory before the hart stops for good
+    crash_report::halt();
 }
 
 
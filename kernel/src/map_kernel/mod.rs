@@ -1,12 +1,22 @@
-//! This module identity maps the Kernel addresses.  
+//! This module maps the Kernel addresses into the kernel address space.  
 //! It maps the entire RAM and specific MMIO regions
 //! 
+//! The kernel lives in the higher half : physical address P is reachable at PHYS_MAP_OFFSET + P.   
+//! This direct map covers all of the RAM (kernel image + heap) and the MMIO regions. Drivers and the page allocator
+//! convert between the two worlds with phys_to_virt() and virt_to_phys().
 //! 
-//! The MMIO regions covered include : the UART, the CLINT and the PLIC.    
+//! Every mapped range comes from one declarative table, kernel_regions() : name, start, end, permissions and cacheability.   
+//! The MMIO regions covered include : the UART, the CLINT, the PLIC and the virtio MMIO window.    
 //! Now the kernel can access all relevant memory regions while using the virtual paging system
+//! 
+//! The image is linked inside the direct map (see lds/virt.lds) : every link symbol is PHYS_MAP_OFFSET + its load address,
+//! so the kernel needs no identity mapping. The low half is left to user processes.
+
+mod tests; // tests that inspect the mappings made by this module
 
-use crate::sv39_mmu::{map, show_mappings};
-use crate::page_manager::alloc;
+use crate::sv39_mmu::{AddressSpace, VirtAddr, PhysAddr, VirtPage, PhysFrame, AddressError, show_mappings};
+use crate::physical_memory::ADDRESS_ERROR_OutOfRange;
+use crate::drivers::virtio_block::virtio_protocol_abstractions as virtio;
 use crate::{print, println};
 
 // defining constants and relevant global variables
@@ -24,6 +34,7 @@ extern "C"{
 	static RODATA_END: usize;
 	static BSS_START: usize;
 	static BSS_END: usize;
+	static KERNEL_STACK_GUARD: usize; // the unmapped page right below the kernel stack
 	static KERNEL_STACK_START: usize;
 	static KERNEL_STACK_END: usize;
 	static HEAP_START: usize;
@@ -44,105 +55,198 @@ extern "C"{
 //     RISCV_ACLINT_MAX_HARTS             = 4095,
 //     RISCV_ACLINT_SWI_SIZE              = 0x4000
 // };
-static UART_ADDRESS_START : usize = 0x1000_0000;
-static UART_ADDRESS_END : usize = 0x1000_0000;
-
-// The Rest of the addresses have been adopted from the OS Site tutorial
-// How the exact addresses were calculated I DO NOT KNOW
-// static CLINT_ADDRESS : usize = 0x0200_0000;
-// static MTIMECMP_ADDRESS : usize = 0x0200_b000;
-// static MTIME_ADDRESS_START : usize = 0x0200_c000;
-// static UART_ADDRESS : usize = 
-// static UART_ADDRESS : usize = 
-
+// The CLINT of the virt machine spans 0x0200_0000 - 0x0201_0000 : the MSIP registers (SWI) come first, then MTIMECMP at +0x4000 and MTIME at +0xbff8
+const CLINT_START : usize = 0x0200_0000;
+const CLINT_END : usize = 0x0201_0000;
+const PLIC_START : usize = 0x0c00_0000;   // priorities, pending bits and enable bits
+const PLIC_END : usize = 0x0c00_2000;
+const PLIC_CONTEXTS_START : usize = 0x0c20_0000; // per-context threshold and claim/complete registers
+const PLIC_CONTEXTS_END : usize = 0x0c20_8000;
+const UART_START : usize = 0x1000_0000;
+const UART_END : usize = 0x1000_1000;
+
+/// How the memory of a region may be cached.  
+/// The base page-table format has no memory-type bits (that needs the Svpbmt extension), so the MMU does not enforce this yet.
+/// It documents the region and tells drivers to use volatile accesses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cacheability{
+    Cacheable,  // ordinary RAM
+    Device      // MMIO registers : no caching, no merging, no speculation
+}
 
-// THis function assumes that the memory has already been initialized
-// It also assumes that the KERNEL_ROOT_TABLE_ADDRESS is currently a Mutable Static Address
-pub fn identity_map_kernel(root_table_address: usize){
-    map_the_ram_sections(root_table_address);
-    map_the_mmio_sections(root_table_address);
-}
-
-/// identity_map_many_pages takes in 3 physical addresses  
-/// 1. Start Address : This is the address of the first Page in a range of contiguous pages that the kenel may need.
-/// 2. The End Address : THis is the addresss if the last Page in that range of contiguous pages
-/// 3. The root table address : This is the address of the root table. Where the translation table of the kernel process will be
-/// This function identity maps a group of pages.
-fn identity_map_many_pages( start_address: usize, end_address: usize, root_table_address: usize, access_map: u64) {
-    // align the addresses : we do not care about aligning th start addresses because we aligned them in the Linker script
-    let aligned_start_address = align(start_address, 12);
-    let aligned_end_address = align(end_address, 12);
+/// Where a boundary of a region comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionBound{
+    Physical(usize),                // a fixed physical address (the MMIO regions)
+    Symbol(&'static usize),         // a linker symbol (see asm/mem_export.s). It holds a link address, inside the direct map
+    AfterSymbol(&'static usize)     // the byte right after a linker symbol that names the last byte of a range (HEAP_END)
+}
 
-    // loop through the range of addresses in a page-wise manner:
-    let mut page_address = aligned_start_address as u64;
-    while page_address < aligned_end_address as u64{
-        map(page_address, page_address, access_map, root_table_address as u64).expect("Unable to Identity Map pages");
-        page_address += PAGE_SIZE as u64;
+impl RegionBound{
+    /// The physical address of the boundary
+    pub fn physical_address(&self) -> usize{
+        match *self {
+            RegionBound::Physical(address) => address,
+            RegionBound::Symbol(symbol) => load_address(*symbol),
+            RegionBound::AfterSymbol(symbol) => load_address(*symbol) + 1
+        }
     }
+}
 
+/// One entry of the kernel memory map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KernelRegion{
+    pub name : &'static str,
+    pub start_bound : RegionBound,  // the first byte
+    pub end_bound : RegionBound,    // the byte right after the last byte
+    pub access_map : u64,           // RWX bits, same format as the access map of the mapping function
+    pub cacheability : Cacheability
 }
 
+impl KernelRegion{
+    /// Physical address of the first byte
+    pub fn start(&self) -> usize{ self.start_bound.physical_address() }
 
-// aligns memory to the specified order
-// Before we give any address to the identity_map_many_pages(), we need to make sure the addresses are aligned to 4096
-fn align (val: usize, order: usize) -> usize{
-    let addition_mask : usize = 1 << order;    // eg if we need to find things in order of 2 ie 2^2, the number will always have 2 zeroes at the LSB
-    let over_board : usize = val + addition_mask; // so when we get a non_mutiple number, we make sure it passes the next multiple.  
-    let cut_mask : usize = !0 << order; // a mask that will be used to make the last 2 bits become zeroes
-    let result: usize = over_board & cut_mask; // replace the last 2 bits with zeroes
-    return result;
+    /// Physical address right after the last byte
+    pub fn end(&self) -> usize{ self.end_bound.physical_address() }
 }
 
-fn map_the_ram_sections(root_table_address: usize){
-    unsafe {
-        // map the text section
-        let mut access_map: u64 = 10u64; // Read-Execute access
-        identity_map_many_pages(TEXT_START, TEXT_END, root_table_address, access_map);
+/// The kernel memory map : every range that the kernel needs, with its permissions.   
+/// W^X holds for every region : text is RX, rodata is R, everything else is RW and never executable.
+/// The kernel stack is not part of it : it gets mapped on its own, with the guard page below it left out (see map_kernel_stack())   
+/// The RAM sections come from the linker script (see asm/mem_export.s). The MMIO regions cover every device the kernel drives.
+static KERNEL_REGIONS : &[KernelRegion] = unsafe { &[
+    // ----- RAM ----- //
+    KernelRegion { name: "text",   start_bound: RegionBound::Symbol(&TEXT_START),   end_bound: RegionBound::Symbol(&TEXT_END),      access_map: 10u64, cacheability: Cacheability::Cacheable }, // Read-Execute
+    KernelRegion { name: "rodata", start_bound: RegionBound::Symbol(&RODATA_START), end_bound: RegionBound::Symbol(&RODATA_END),    access_map: 2u64,  cacheability: Cacheability::Cacheable }, // Read only
+    KernelRegion { name: "data",   start_bound: RegionBound::Symbol(&DATA_START),   end_bound: RegionBound::Symbol(&DATA_END),      access_map: 6u64,  cacheability: Cacheability::Cacheable }, // Read-Write
+    KernelRegion { name: "bss",    start_bound: RegionBound::Symbol(&BSS_START),    end_bound: RegionBound::Symbol(&BSS_END),       access_map: 6u64,  cacheability: Cacheability::Cacheable }, // Read-Write
+    KernelRegion { name: "heap",   start_bound: RegionBound::Symbol(&HEAP_START),   end_bound: RegionBound::AfterSymbol(&HEAP_END), access_map: 6u64,  cacheability: Cacheability::Cacheable }, // HEAP_END is the last usable byte
+    // ----- MMIO ----- //
+    KernelRegion { name: "CLINT",         start_bound: RegionBound::Physical(CLINT_START),         end_bound: RegionBound::Physical(CLINT_END),         access_map: 6u64, cacheability: Cacheability::Device },
+    KernelRegion { name: "PLIC",          start_bound: RegionBound::Physical(PLIC_START),          end_bound: RegionBound::Physical(PLIC_END),          access_map: 6u64, cacheability: Cacheability::Device },
+    KernelRegion { name: "PLIC contexts", start_bound: RegionBound::Physical(PLIC_CONTEXTS_START), end_bound: RegionBound::Physical(PLIC_CONTEXTS_END), access_map: 6u64, cacheability: Cacheability::Device },
+    KernelRegion { name: "UART",          start_bound: RegionBound::Physical(UART_START),          end_bound: RegionBound::Physical(UART_END),          access_map: 6u64, cacheability: Cacheability::Device },
+    // MMIO_VIRTIO_END is the start of the last device
+    KernelRegion { name: "virtio",        start_bound: RegionBound::Physical(virtio::MMIO_VIRTIO_START),
+                   end_bound: RegionBound::Physical(virtio::MMIO_VIRTIO_END + virtio::MMIO_VIRTIO_STRIDE), access_map: 6u64, cacheability: Cacheability::Device },
+] };
+
+/// Returns the kernel memory map. map_higher_half_kernel consumes it
+pub fn kernel_regions() -> &'static [KernelRegion]{
+    KERNEL_REGIONS
+}
 
-        // map the rodata section
-        access_map = 10u64; // Read-Execute access
-        identity_map_many_pages(RODATA_START, RODATA_END, root_table_address, access_map);
+/// The kernel stack, mapped by map_kernel_stack(). Read-Write, never executable
+pub static KERNEL_STACK_REGION : KernelRegion = unsafe {
+    KernelRegion { name: "kernel stack", start_bound: RegionBound::Symbol(&KERNEL_STACK_START), end_bound: RegionBound::Symbol(&KERNEL_STACK_END),
+                   access_map: 6u64, cacheability: Cacheability::Cacheable }
+};
 
-        // map the data section
-        access_map = 6u64; // Read-Write access
-        identity_map_many_pages(DATA_START, DATA_END, root_table_address, access_map);
+/// Maps the whole kernel stack. The guard page right below it stays unmapped, so that an overflow faults (see is_in_kernel_stack_guard())
+pub fn map_kernel_stack(address_space: &mut AddressSpace){
+    let region = &KERNEL_STACK_REGION;
+    map_many_pages(region.start(), region.end(), PHYS_MAP_OFFSET, address_space, region.access_map);
+}
 
-        // map the bss section
-        access_map = 6u64; // Read-Write access
-        identity_map_many_pages(BSS_START, BSS_END, root_table_address, access_map);
+/// Returns true if the address falls in the guard page below the kernel stack.  
+/// The guard page is never mapped, so a fault there means that the kernel stack overflowed
+pub fn is_in_kernel_stack_guard(virt_address: u64) -> bool{
+    let guard_start = unsafe { KERNEL_STACK_GUARD } as u64;
+    virt_address >= guard_start && virt_address < guard_start + PAGE_SIZE as u64
+}
 
-        // map the Kernel stack
-        access_map = 6u64; // Read-Write access
-        identity_map_many_pages(KERNEL_STACK_END, KERNEL_STACK_END, root_table_address, access_map);
+/// Returns true if the kernel address falls inside the RAM used by the kernel : from the text section to the end of the heap.  
+/// Every kernel stack lives there, so the crash reporter uses it to tell a sane frame pointer from garbage
+pub fn is_in_kernel_ram(address: u64) -> bool{
+    let ram_start = unsafe { TEXT_START } as u64;
+    let ram_end = unsafe { HEAP_END } as u64 + 1;
+    address >= ram_start && address < ram_end
+}
 
-        // map the entire Heap
-        access_map = 6u64; // Read-Write access
-        identity_map_many_pages(HEAP_START, HEAP_END, root_table_address, access_map); 
+/// Prints the kernel memory map. Called once at boot
+pub fn show_kernel_memory_map(){
+    println!("Kernel memory map :");
+    for region in kernel_regions().iter().chain(core::iter::once(&KERNEL_STACK_REGION)) {
+        println!(" \t {:<14} 0x{:016x} - 0x{:016x} : access {:04b} : {:?}", region.name, region.start(), region.end(), region.access_map, region.cacheability);
     }
 }
 
-fn map_the_mmio_sections(root_table_address: usize){
-    unsafe{
-        // map the UART
-        let mut access_map : u64 = 6u64; // Read-Write access
-        map(0x1000_0000, 0x1000_0000, access_map, root_table_address as u64).unwrap();
+/// The start of the higher half (Sv39 sign-extends bit 38, Sv48 accepts it too). Physical address P is mapped at PHYS_MAP_OFFSET + P
+pub const PHYS_MAP_OFFSET : u64 = 0xffff_ffc0_0000_0000;
+
+/// The link address of the kernel image. The linker places .text at PHYS_MAP_OFFSET + the start of the RAM (0x8000_0000)
+pub const KERNEL_VIRT_BASE : u64 = PHYS_MAP_OFFSET + 0x8000_0000;
 
-        // map the PLIC
-        identity_map_many_pages(0x0c00_0000, 0x0c00_2000, root_table_address, access_map);
-        identity_map_many_pages(0x0c20_0000, 0x0c20_8000, root_table_address, access_map);
+/// Returns the address at which the kernel reaches an MMIO register. Usable in constants
+pub const fn direct_map_address(physical_address: usize) -> usize{
+    PHYS_MAP_OFFSET as usize + physical_address
+}
 
-        // map the CLINT
-            // MSIP
-            map(0x0200_0000, 0x0200_0000, access_map, root_table_address as u64).unwrap();
+// the physical address behind a link symbol : the image is linked at PHYS_MAP_OFFSET + its load address
+fn load_address(link_address: usize) -> usize{
+    link_address - PHYS_MAP_OFFSET as usize
+}
 
-            // MTIME
-            map(0x0200_c000, 0x0200_c000, access_map, root_table_address as u64).unwrap();
+/// Returns the address at which the kernel reaches a physical address through the direct map
+pub fn phys_to_virt(physical_address: PhysAddr) -> VirtAddr{
+    let virt_address = PHYS_MAP_OFFSET.checked_add(physical_address.as_u64())
+                                      .expect("phys_to_virt : the direct map only covers the RAM and the MMIO regions");
+    VirtAddr::new(virt_address).expect("phys_to_virt : the direct map only covers the RAM and the MMIO regions")
+}
 
-            // MTIMECMP
-            map(0x0200_b000, 0x0200_b000, access_map, root_table_address as u64).unwrap();
+/// Returns the physical address behind a direct map address.   
+/// Low addresses belong to user processes, they are rejected
+pub fn virt_to_phys(virt_address: VirtAddr) -> Result<PhysAddr, AddressError>{
+    if virt_address.as_u64() < PHYS_MAP_OFFSET { return Err(ADDRESS_ERROR_OutOfRange); }
+    PhysAddr::new(virt_address.as_u64() - PHYS_MAP_OFFSET)
+}
 
+// THis function assumes that the memory has already been initialized
+// None of the mappings own their physical pages. Dropping the kernel address space will not free the kernel sections
+/// Maps the kernel in the higher half (the direct map). 
+/// Every kernel mapping is global (G bit), and so is every root entry it uses.
+/// Process address spaces get the kernel with AddressSpace::stamp_kernel_mappings()
+pub fn map_kernel(address_space: &mut AddressSpace){
+    map_higher_half_kernel(address_space);
+    map_kernel_stack(address_space);
+    address_space.mark_root_entries_global();
+}
+
+/// Maps the regions of the kernel memory map at PHYS_MAP_OFFSET + their physical address
+pub fn map_higher_half_kernel(address_space: &mut AddressSpace){
+    for region in kernel_regions().iter() {
+        map_many_pages(region.start(), region.end(), PHYS_MAP_OFFSET, address_space, region.access_map);
     }
 }
 
+/// map_many_pages takes in 2 physical addresses  
+/// 1. Start Address : the first byte of the range. The page that contains it is the first page mapped
+/// 2. The End Address : the byte right after the range. The page that contains the last byte is the last page mapped
+/// 3. The offset : every page gets mapped at (its physical address + offset)
+/// 4. The address space : This is the kernel address space. Its root table is where the translation table of the kernel process will be
+/// This function maps a group of pages.
+fn map_many_pages( start_address: usize, end_address: usize, offset: u64, address_space: &mut AddressSpace, access_map: u64) {
+    let aligned_start_address = start_address & !(PAGE_SIZE - 1);
+    let aligned_end_address = align_up(end_address);
 
+    // loop through the range of addresses in a page-wise manner:
+    let mut page_address = aligned_start_address as u64;
+    while page_address < aligned_end_address as u64{
+        map_page(page_address, offset, address_space, access_map);
+        page_address += PAGE_SIZE as u64;
+    }
+
+}
+
+// maps one frame at (its address + offset)
+fn map_page(physical_address: u64, offset: u64, address_space: &mut AddressSpace, access_map: u64){
+    let page = VirtPage::new(physical_address + offset).expect("kernel page outside the virtual range");
+    let frame = PhysFrame::new(physical_address).expect("kernel frame outside the physical range");
+    address_space.map_global(page, frame, access_map).expect("Unable to map kernel pages");
+}
 
+// rounds an address up to the next multiple of 4096. Addresses that are already aligned stay as they are
+fn align_up(val: usize) -> usize{
+    (val + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
@@ -0,0 +1,239 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use crate::sv39_mmu::AddressSpace;
+use crate::sv39_mmu::{VirtAddr, PhysAddr, VirtPage, PhysFrame, UnmapError, errors};
+use super::{kernel_regions, KERNEL_STACK_REGION, is_in_kernel_stack_guard, KERNEL_STACK_GUARD, KERNEL_STACK_START, KERNEL_STACK_END, Cacheability, map_kernel, phys_to_virt, virt_to_phys, PHYS_MAP_OFFSET, TEXT_START, RODATA_START, DATA_START};
+
+#[test_case]
+pub fn map_kernel_test_switch(){
+    println!("\n---------  Running map_kernel tests  ---------\n");
+    test_text_is_executable_and_not_writable();
+    test_rodata_is_not_writable();
+    test_data_is_writable_and_not_executable();
+    test_phys_to_virt_and_back();
+    test_virt_to_phys_rejects_low_address();
+    test_higher_half_text_mapping();
+    test_kernel_regions_do_not_overlap();
+    test_kernel_regions_leave_out_the_stack();
+    test_virtio_window_is_mapped();
+    test_rodata_is_read_only();
+    test_stack_is_mapped_and_guard_is_not();
+    test_kernel_mappings_are_global();
+    test_process_sees_kernel_text();
+    test_dropping_process_keeps_kernel_mappings();
+    test_stamp_refuses_taken_root_entry();
+    test_process_cannot_map_into_kernel_root_entry();
+    test_process_cannot_unmap_or_protect_kernel_pages();
+}
+
+// the physical address behind a link symbol
+fn load_address(link_address: usize) -> u64{
+    link_address as u64 - PHYS_MAP_OFFSET
+}
+
+// returns (readable, writable, executable) for the range that maps the address, None if the address is not mapped
+fn permissions_at(address_space: &AddressSpace, virt_address: u64) -> Option<(bool, bool, bool)>{
+    address_space.mappings()
+        .find(|mapping| mapping.contains(virt_address))
+        .map(|mapping| (mapping.is_readable(), mapping.is_writable(), mapping.is_executable()))
+}
+
+fn test_text_is_executable_and_not_writable(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let res = permissions_at(&address_space, unsafe { TEXT_START } as u64);
+    let suc_msg = "test_text_is_executable_and_not_writable    ....   [OK]";
+    let fail_msg = "test_text_is_executable_and_not_writable   ....    [FAIL]";
+    custom_assert(Some((true, false, true)), res, suc_msg, fail_msg);
+}
+
+fn test_rodata_is_not_writable(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let res = permissions_at(&address_space, unsafe { RODATA_START } as u64).map(|(_, writable, _)| writable);
+    let suc_msg = "test_rodata_is_not_writable    ....   [OK]";
+    let fail_msg = "test_rodata_is_not_writable   ....    [FAIL]";
+    custom_assert(Some(false), res, suc_msg, fail_msg);
+}
+
+fn test_data_is_writable_and_not_executable(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let res = permissions_at(&address_space, unsafe { DATA_START } as u64);
+    let suc_msg = "test_data_is_writable_and_not_executable    ....   [OK]";
+    let fail_msg = "test_data_is_writable_and_not_executable   ....    [FAIL]";
+    custom_assert(Some((true, true, false)), res, suc_msg, fail_msg);
+}
+
+fn test_phys_to_virt_and_back(){
+    let physical_address = PhysAddr::new(0x8020_1234).unwrap();
+    let virt_address = phys_to_virt(physical_address);
+    let res = (virt_address.as_u64(), virt_to_phys(virt_address));
+    let suc_msg = "test_phys_to_virt_and_back    ....   [OK]";
+    let fail_msg = "test_phys_to_virt_and_back   ....    [FAIL]";
+    custom_assert((0xffff_ffc0_8020_1234, Ok(physical_address)), res, suc_msg, fail_msg);
+}
+
+// the low half belongs to user processes, the kernel has no identity mapping anymore
+fn test_virt_to_phys_rejects_low_address(){
+    let res = virt_to_phys(VirtAddr::new(0x1000_0000).unwrap()).is_err();
+    let suc_msg = "test_virt_to_phys_rejects_low_address    ....   [OK]";
+    let fail_msg = "test_virt_to_phys_rejects_low_address   ....    [FAIL]";
+    custom_assert(true, res, suc_msg, fail_msg);
+}
+
+// the text section is linked in the direct map : its link address translates to its load address
+fn test_higher_half_text_mapping(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let text_start = unsafe { TEXT_START } as u64;
+    let res = (permissions_at(&address_space, text_start),
+               address_space.translate(VirtAddr::new(text_start).unwrap()).map(|physical_address| physical_address.as_u64()),
+               permissions_at(&address_space, load_address(unsafe { TEXT_START })));
+    let suc_msg = "test_higher_half_text_mapping    ....   [OK]";
+    let fail_msg = "test_higher_half_text_mapping   ....    [FAIL]";
+    custom_assert((Some((true, false, true)), Ok(load_address(unsafe { TEXT_START })), None), res, suc_msg, fail_msg);
+}
+
+fn test_kernel_regions_do_not_overlap(){
+    let regions = kernel_regions();
+    let mut overlaps = 0;
+    for (index, region) in regions.iter().enumerate() {
+        for other in regions[index + 1..].iter() {
+            if region.start() < other.end() && other.start() < region.end() { overlaps += 1; }
+        }
+    }
+    let suc_msg = "test_kernel_regions_do_not_overlap    ....   [OK]";
+    let fail_msg = "test_kernel_regions_do_not_overlap   ....    [FAIL]";
+    custom_assert(0, overlaps, suc_msg, fail_msg);
+}
+
+// the stack and its guard page are mapped on their own (see map_kernel_stack)
+fn test_kernel_regions_leave_out_the_stack(){
+    let guard = load_address(unsafe { KERNEL_STACK_GUARD }) as usize;
+    let overlaps = kernel_regions().iter()
+        .filter(|region| region.start() < KERNEL_STACK_REGION.end() && guard < region.end())
+        .count();
+    let suc_msg = "test_kernel_regions_leave_out_the_stack    ....   [OK]";
+    let fail_msg = "test_kernel_regions_leave_out_the_stack   ....    [FAIL]";
+    custom_assert(0, overlaps, suc_msg, fail_msg);
+}
+
+// every virtio slot (0x1000_1000 - 0x1000_8fff) must be reachable once paging is on
+fn test_virtio_window_is_mapped(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let virtio = kernel_regions().iter().find(|region| region.name == "virtio").map(|region| (region.start(), region.end(), region.cacheability));
+    let res = (virtio,
+               permissions_at(&address_space, PHYS_MAP_OFFSET + 0x1000_1000),
+               permissions_at(&address_space, PHYS_MAP_OFFSET + 0x1000_8ffc));
+    let suc_msg = "test_virtio_window_is_mapped    ....   [OK]";
+    let fail_msg = "test_virtio_window_is_mapped   ....    [FAIL]";
+    custom_assert((Some((0x1000_1000, 0x1000_9000, Cacheability::Device)), Some((true, true, false)), Some((true, true, false))), res, suc_msg, fail_msg);
+}
+
+// W^X : rodata can only be read
+fn test_rodata_is_read_only(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let res = permissions_at(&address_space, unsafe { RODATA_START } as u64);
+    let suc_msg = "test_rodata_is_read_only    ....   [OK]";
+    let fail_msg = "test_rodata_is_read_only   ....    [FAIL]";
+    custom_assert(Some((true, false, false)), res, suc_msg, fail_msg);
+}
+
+fn test_stack_is_mapped_and_guard_is_not(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let (guard, stack_start, stack_end) = unsafe { (KERNEL_STACK_GUARD as u64, KERNEL_STACK_START as u64, KERNEL_STACK_END as u64) };
+    let res = (permissions_at(&address_space, guard),
+               permissions_at(&address_space, stack_start),
+               permissions_at(&address_space, stack_end - 8),
+               is_in_kernel_stack_guard(guard + 0x10),
+               is_in_kernel_stack_guard(stack_start));
+    let suc_msg = "test_stack_is_mapped_and_guard_is_not    ....   [OK]";
+    let fail_msg = "test_stack_is_mapped_and_guard_is_not   ....    [FAIL]";
+    custom_assert((None, Some((true, true, false)), Some((true, true, false)), true, false), res, suc_msg, fail_msg);
+}
+
+fn test_kernel_mappings_are_global(){
+    let mut address_space = AddressSpace::new();
+    map_kernel(&mut address_space);
+    let res = address_space.mappings().all(|mapping| mapping.flags & 32u64 == 32u64);
+    let suc_msg = "test_kernel_mappings_are_global    ....   [OK]";
+    let fail_msg = "test_kernel_mappings_are_global   ....    [FAIL]";
+    custom_assert(true, res, suc_msg, fail_msg);
+}
+
+fn test_process_sees_kernel_text(){
+    let mut kernel = AddressSpace::new();
+    map_kernel(&mut kernel);
+    let process = AddressSpace::new_process(&kernel);
+    let text = VirtAddr::new(unsafe { TEXT_START } as u64).unwrap();
+    let res = (process.translate(text).map(|address| address.as_u64()).ok(), process.diff(&kernel).len());
+    let suc_msg = "test_process_sees_kernel_text    ....   [OK]";
+    let fail_msg = "test_process_sees_kernel_text   ....    [FAIL]";
+    custom_assert((Some(load_address(unsafe { TEXT_START })), 0), res, suc_msg, fail_msg);
+}
+
+fn test_dropping_process_keeps_kernel_mappings(){
+    let mut kernel = AddressSpace::new();
+    map_kernel(&mut kernel);
+    let count_before = kernel.mappings().count();
+    {
+        let mut process = AddressSpace::new_process(&kernel);
+        let user_frame = PhysFrame::new(0x8800_0000).unwrap();
+        // a lower half page : its root entry belongs to the process, not to the kernel
+        process.map(VirtPage::new(0x1000).unwrap(), user_frame, 6u64).unwrap();
+    }
+    let res = kernel.mappings().count();
+    let suc_msg = "test_dropping_process_keeps_kernel_mappings    ....   [OK]";
+    let fail_msg = "test_dropping_process_keeps_kernel_mappings   ....    [FAIL]";
+    custom_assert(count_before, res, suc_msg, fail_msg);
+}
+
+fn test_stamp_refuses_taken_root_entry(){
+    let mut kernel = AddressSpace::new();
+    map_kernel(&mut kernel);
+    let mut process = AddressSpace::new();
+    // the text page shares its root entry with the kernel
+    let text_page = VirtPage::new(unsafe { TEXT_START } as u64 & !0xfff).unwrap();
+    process.map(text_page, PhysFrame::new(0x8800_0000).unwrap(), 2u64).unwrap();
+    let res = process.stamp_kernel_mappings(&kernel);
+    let suc_msg = "test_stamp_refuses_taken_root_entry    ....   [OK]";
+    let fail_msg = "test_stamp_refuses_taken_root_entry   ....    [FAIL]";
+    custom_assert(Err(errors::MAPPING_ERROR_KernelRootEntryTaken), res, suc_msg, fail_msg);
+}
+
+// the stamped subtrees are the tables of the kernel : a process mapping there would show up in every address space
+fn test_process_cannot_map_into_kernel_root_entry(){
+    let mut kernel = AddressSpace::new();
+    map_kernel(&mut kernel);
+    let mut process = AddressSpace::new_process(&kernel);
+    let page = VirtPage::new(unsafe { TEXT_START } as u64 & !0xfff).unwrap();
+    let frame = PhysFrame::new(0x8800_0000).unwrap();
+    let res = (process.map(page, frame, 2u64), process.map_owned(page, frame, 2u64), process.map_global(page, frame, 2u64));
+    let suc_msg = "test_process_cannot_map_into_kernel_root_entry    ....   [OK]";
+    let fail_msg = "test_process_cannot_map_into_kernel_root_entry   ....    [FAIL]";
+    custom_assert((Err(errors::MAPPING_ERROR_KernelRootEntryTaken), Err(errors::MAPPING_ERROR_KernelRootEntryTaken), Err(errors::MAPPING_ERROR_KernelRootEntryTaken)),
+                  res, suc_msg, fail_msg);
+}
+
+// the text page of the kernel stays mapped, read-execute, in the kernel and in the process
+fn test_process_cannot_unmap_or_protect_kernel_pages(){
+    let mut kernel = AddressSpace::new();
+    map_kernel(&mut kernel);
+    let mut process = AddressSpace::new_process(&kernel);
+    let page = VirtPage::new(unsafe { TEXT_START } as u64 & !0xfff).unwrap();
+    let refused = (process.unmap(page), process.protect(page, 6u64));
+
+    let still_mapped = (kernel.translate(page.start_address()).map(|address| address.as_u64()),
+                        process.translate(page.start_address()).map(|address| address.as_u64()));
+    let flags = kernel.mappings().find(|mapping| mapping.contains(page.as_u64())).map(|mapping| mapping.flags & 0b1110);
+    let res = (refused, still_mapped, flags);
+    let suc_msg = "test_process_cannot_unmap_or_protect_kernel_pages    ....   [OK]";
+    let fail_msg = "test_process_cannot_unmap_or_protect_kernel_pages   ....    [FAIL]";
+    let text_frame = load_address(unsafe { TEXT_START }) & !0xfff;
+    custom_assert(((Err(UnmapError::Translation(errors::TRANS_ERROR_KernelRootEntry)), Err(errors::MAPPING_ERROR_KernelRootEntryTaken)),
+                   (Ok(text_frame), Ok(text_frame)), Some(10u64)), res, suc_msg, fail_msg);
+}
@@ -5,6 +5,7 @@
 //! 2. Page Allocation
 //! 3. Page Deallocation
 //! 4. Heap Monitoring  
+//! 5. Page sharing counts (used when address spaces share pages, eg. copy-on-write)
 
 mod memory_abstractions;
 mod memory_errors;
@@ -12,9 +13,11 @@ mod tests; // tests that test the functions defined in this module
 
 
 use memory_abstractions::{FullHeapLayout, DescriptorValue, PageDescriptor, Page, PageMMIO};
-use memory_errors::{MemoryDeallocationError, MemoryAllocationError};
+pub use memory_errors::{MemoryDeallocationError, MemoryAllocationError};
 use core::mem::size_of;
 use crate::{print, println};
+use crate::physical_memory::PhysFrame;
+use crate::map_kernel::PHYS_MAP_OFFSET;
 
 
 // get the heap memory labels from the /asm/memory_export.s assembly file
@@ -29,6 +32,12 @@ static mut ALLOC_START : usize = 0;
 static mut NUM_DP : usize = 0; // the number of pages in the heap. THe number of pages also equals the number of descriptors
 pub const PAGE_SIZE: usize = 4096;
 
+// Per-page sharing information. Entry i counts how many EXTRA mappings use the page at ALLOC_START + (4096 * i).
+// A page that has a single user has a count of zero, so the freshly zeroed table needs no further initialization.
+// The table itself lives in pages taken from the page allocator during init_memory()
+static mut SHARE_COUNTS : *mut u16 = core::ptr::null_mut();
+static mut NUM_SHARE_COUNTS : usize = 0;
+
 // The FullHeapLayout contains all metadata about the Heap stats, The allocations and deallocations
 // The FullHeapLayout contents get updated by the following functions :
 // 1. The init_memory_abtraction
@@ -36,6 +45,17 @@ pub const PAGE_SIZE: usize = 4096;
 // 3. The Dealloc function
 static mut HEAP_LAYOUT : FullHeapLayout = FullHeapLayout::new();
 
+// The heap is reached through its link addresses, inside the direct map (see map_kernel). The allocator works with those,
+// and converts at its boundary : the frames it hands out and takes back are physical
+fn frame_of_page(page_addr: usize) -> PhysFrame{
+    PhysFrame::new((page_addr as u64).wrapping_sub(PHYS_MAP_OFFSET)).expect("the page allocator handed out an invalid frame")
+}
+
+// a frame outside the heap gives an address outside the heap, that the checks below reject
+fn page_of_frame(frame: PhysFrame) -> usize{
+    frame.as_u64().wrapping_add(PHYS_MAP_OFFSET) as usize
+}
+
 // calculates total Heap size in bytes
 fn get_heap_size() -> usize{
     let heap_memory_size = ((*END + 1) - *START) * size_of::<u8>(); // size in bytes
@@ -54,6 +74,20 @@ pub fn init_memory(){
     // Determine Heap layout, and update the global variables {ALLOC_START, NUM_DP}.
     determine_heap_layout();
 
+    // Reserve the table that records how many mappings share each page
+    init_share_counts();
+}
+
+// Allocates the share count table : one u16 for every page in the Pages segment
+fn init_share_counts(){
+    let num_of_pages = unsafe { ((*END + 1) - ALLOC_START) / PAGE_SIZE };
+    let table_size = num_of_pages * size_of::<u16>();
+    let table_pages = (table_size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let table_frame = alloc(table_pages).expect("unable to allocate pages for the share count table");
+    unsafe {
+        SHARE_COUNTS = page_of_frame(table_frame) as *mut u16;
+        NUM_SHARE_COUNTS = num_of_pages;
+    }
 }
 
 // This function traverses the entire heap and makes sure each value under each address is zero
@@ -121,10 +155,10 @@ fn determine_heap_layout(){
     
 }
 
-/// This function takes in the number of requested pages and returns the memory address of the first page of the contiguous page allocation     
+/// This function takes in the number of requested pages and returns the first frame of the contiguous page allocation     
 /// alloc() returns an error if requested zero pages    
 /// it also returns an error if No sufficient contiguous free space is found. At that point, you may need to fragment things
-pub fn alloc(req_pages: usize) -> Result<usize, MemoryAllocationError>{
+pub fn alloc(req_pages: usize) -> Result<PhysFrame, MemoryAllocationError>{
     // println!(">>>> Allocating {} Pages....", req_pages);  // [test] Add this line when running integration tests 9 and below
     // check if required pages is zero. If its zero, throw an error...
     if req_pages == 0 { return Err(MemoryAllocationError::ZeroPagesRequested("Zero pages were requested from the allocator"));}
@@ -149,7 +183,7 @@ pub fn alloc(req_pages: usize) -> Result<usize, MemoryAllocationError>{
 
         // Finally return the address of the Page that directly corresponds with the First Descriptor
         let page_address = get_page_addr_from_page_index(first_descriptor_index);
-        return  Ok(page_address);
+        return  Ok(frame_of_page(page_address));
     }
 }
 
@@ -319,14 +353,15 @@ pub fn check_descriptor_ordering() -> bool{
 
 
 /// This function ...   
-/// 1. takes the Address of the first Page of a contiguous page allocation
+/// 1. takes the first frame of a contiguous page allocation
 /// and frees all of the associated contiguous pages. Freeing here means ZEROING all bytes of the page.   
 /// 2. It updates the corresponding descriptors associated with the freed Pages  
 /// 
 /// Errors thrown include:  
 /// 1. Address passed to the function is not the first in its associated contiguous allocation
 /// 2. Address passed is not a valid address. because it is not found within the Heap Page section, or it is not a Page's first byte. 
-pub fn dealloc(page_addr: usize) -> Result<(), MemoryDeallocationError>{
+pub fn dealloc(frame: PhysFrame) -> Result<(), MemoryDeallocationError>{
+    let page_addr = page_of_frame(frame);
     println!(">>>> Deallocating contiguous memory at address : 0x{:x}...", page_addr);
     // validate page_addr... and move on to deallocation
     if check_if_page_within_heap(page_addr) == false { 
@@ -360,6 +395,49 @@ pub fn dealloc(page_addr: usize) -> Result<(), MemoryDeallocationError>{
 // unimplemented!()
 }
 
+/// Records that one more mapping uses the page. Used when address spaces share a physical page (eg. copy-on-write)    
+/// The page must be a page that was handed out by alloc(). Fails, and leaves the count alone, once the count cannot go any higher
+pub fn share_page(frame: PhysFrame) -> Result<(), MemoryDeallocationError>{
+    let index = validate_shared_page(page_of_frame(frame))?;
+    unsafe {
+        match (*SHARE_COUNTS.add(index)).checked_add(1) {
+            Some(count) => *SHARE_COUNTS.add(index) = count,
+            None => return Err(memory_errors::SHARE_COUNT_OVERFLOW)
+        }
+    }
+    return Ok(());
+}
+
+/// Returns the number of mappings that use the page. A page that is not shared has a count of 1
+pub fn get_share_count(frame: PhysFrame) -> usize{
+    match validate_shared_page(page_of_frame(frame)) {
+        Ok(index) => unsafe { *SHARE_COUNTS.add(index) as usize + 1 },
+        Err(_) => 1
+    }
+}
+
+/// Drops one user of the page.   
+/// If other mappings still use the page, only the share count goes down. When the last user lets go, the page gets deallocated
+pub fn release_page(frame: PhysFrame) -> Result<(), MemoryDeallocationError>{
+    let index = validate_shared_page(page_of_frame(frame))?;
+    unsafe {
+        if *SHARE_COUNTS.add(index) > 0 {
+            *SHARE_COUNTS.add(index) = *SHARE_COUNTS.add(index) - 1;
+            return Ok(());
+        }
+    }
+    dealloc(frame)
+}
+
+// makes sure that a page can have a share count and returns the index of that count
+fn validate_shared_page(page_addr: usize) -> Result<usize, MemoryDeallocationError>{
+    if check_if_page_within_heap(page_addr) == false { return Err(memory_errors::NON_HEAP_ADDRESS); }
+    if check_if_page_addr(page_addr) == false { return Err(memory_errors::NON_PAGE_ADDRESS); }
+    let index = get_page_index_from_addr(page_addr);
+    if index >= unsafe { NUM_SHARE_COUNTS } { return Err(memory_errors::NON_HEAP_ADDRESS); }
+    return Ok(index);
+}
+
 // Finds page index ; its location in the array of pages.  
 // It assumes that the First page is at ALLOC start and has the index 0 
 fn get_page_index_from_addr(page_addr: usize) -> usize{
@@ -0,0 +1,94 @@
+//! Physical addresses.
+//!
+//! The page allocator hands out physical frames and the translation tables store them. Both sides use the types of this module,
+//! so the page allocator does not have to depend on the MMU code :
+//! - PhysAddr : any byte address within the 56-bit physical range
+//! - PhysFrame : a page aligned PhysAddr, ie. the start of a physical page (a frame)
+//!
+//! The virtual counterparts (VirtAddr, VirtPage) live in sv39_mmu, because their range depends on the paging mode.
+//! Converting back to a raw number is explicit (as_u64 / as_usize), for the places that talk to the hardware.
+
+mod tests;
+
+use core::fmt;
+
+const FRAME_SIZE : u64 = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError{
+    OutOfRange(&'static str), // beyond the 39/48 bit virtual range, or beyond the 56 bit physical range
+    Unaligned(&'static str)   // a page or a frame must start at a multiple of 4096
+}
+
+pub const ADDRESS_ERROR_OutOfRange : AddressError = AddressError::OutOfRange("The address is outside the addressable range");
+pub const ADDRESS_ERROR_Unaligned : AddressError = AddressError::Unaligned("Page and frame addresses must be divisible by 4096");
+
+/// A physical byte address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct PhysAddr(u64);
+
+/// The start address of a physical page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct PhysFrame(u64);
+
+impl PhysAddr{
+    /// Fails if the address is outside the 56-bit physical range
+    pub fn new(address: u64) -> Result<Self, AddressError>{
+        if validate_physical_address(address & !(FRAME_SIZE - 1)) == false { return Err(ADDRESS_ERROR_OutOfRange); }
+        Ok(PhysAddr(address))
+    }
+
+    pub fn as_u64(&self) -> u64{ self.0 }
+    pub fn as_usize(&self) -> usize{ self.0 as usize }
+
+    /// The byte offset of the address inside its frame
+    pub fn page_offset(&self) -> u64{ self.0 & (FRAME_SIZE - 1) }
+
+    /// The frame that contains the address
+    pub fn containing_frame(&self) -> PhysFrame{ PhysFrame(self.0 & !(FRAME_SIZE - 1)) }
+}
+
+impl PhysFrame{
+    /// Fails if the address is out of range or not divisible by 4096
+    pub fn new(address: u64) -> Result<Self, AddressError>{
+        if address % FRAME_SIZE != 0 { return Err(ADDRESS_ERROR_Unaligned); }
+        if validate_physical_address(address) == false { return Err(ADDRESS_ERROR_OutOfRange); }
+        Ok(PhysFrame(address))
+    }
+
+    pub fn start_address(&self) -> PhysAddr{ PhysAddr(self.0) }
+    pub fn as_u64(&self) -> u64{ self.0 }
+    pub fn as_usize(&self) -> usize{ self.0 as usize }
+
+    /// The physical page number, the value that goes into the PPN fields of a table entry
+    pub fn number(&self) -> u64{ self.0 / FRAME_SIZE }
+
+    /// The frame that comes "count" frames after this one. Fails if it falls outside the physical range
+    pub fn add(&self, count: u64) -> Result<PhysFrame, AddressError>{
+        PhysFrame::new(self.0 + count * FRAME_SIZE)
+    }
+}
+
+impl fmt::Display for PhysAddr{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "P:0x{:x}", self.0) }
+}
+
+impl fmt::Display for PhysFrame{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "Frame:0x{:x}", self.0) }
+}
+
+// checks if a function :
+// 1. is within the 56 bit range
+// 2. is divisible by 4096
+fn validate_physical_address(address: u64) -> bool{
+    // check if address is under the 56-bit threshold
+    if address > 2u64.pow(56){ return false; }
+
+    // check if address has 12 trailing zeroes, ie. It is divisible by 4096
+    if address % 4096 != 0 { return false; }
+
+    // else return true
+    true
+}
@@ -0,0 +1,43 @@
+use crate::test_framework::custom_assert;
+use crate::{println, print};
+use super::{validate_physical_address, PhysAddr, PhysFrame, ADDRESS_ERROR_OutOfRange};
+
+#[test_case]
+pub fn physical_memory_test_switch(){
+    println!("\n---------  Running Physical Address tests  ---------\n");
+    test_validate_physical_address_within_range();
+    test_validate_physical_address_above_range();
+    test_phys_frame_rejects_out_of_range_address();
+    test_phys_addr_containing_frame();
+}
+
+fn test_validate_physical_address_within_range(){
+    let test_address: u64 = 2u64.pow(56);
+    let res = validate_physical_address(test_address);
+    let suc_msg = "test_validate_physical_address_within_range    ....   [OK]";
+    let fail_msg = "test_validate_physical_address_within_range   ....    [FAIL]";
+    custom_assert(true, res, suc_msg, fail_msg);
+}
+
+fn test_validate_physical_address_above_range(){
+    let test_address: u64 = 2u64.pow(60);
+    let res = validate_physical_address(test_address);
+    let suc_msg = "test_validate_physical_address_above_range    ....   [OK]";
+    let fail_msg = "test_validate_physical_address_above_range   ....    [FAIL]";
+    custom_assert(false, res, suc_msg, fail_msg);
+}
+
+fn test_phys_frame_rejects_out_of_range_address(){
+    let res = (PhysFrame::new(2u64.pow(57)), PhysAddr::new(2u64.pow(57) + 8).is_err());
+    let suc_msg = "test_phys_frame_rejects_out_of_range_address    ....   [OK]";
+    let fail_msg = "test_phys_frame_rejects_out_of_range_address   ....    [FAIL]";
+    custom_assert((Err(ADDRESS_ERROR_OutOfRange), true), res, suc_msg, fail_msg);
+}
+
+fn test_phys_addr_containing_frame(){
+    let physical_address = PhysAddr::new(0x8000_2004).unwrap();
+    let res = (physical_address.containing_frame(), physical_address.containing_frame().add(1).map(|frame| frame.as_u64()));
+    let suc_msg = "test_phys_addr_containing_frame    ....   [OK]";
+    let fail_msg = "test_phys_addr_containing_frame   ....    [FAIL]";
+    custom_assert((PhysFrame::new(0x8000_2000).unwrap(), Ok(0x8000_3000)), res, suc_msg, fail_msg);
+}
@@ -7,6 +7,25 @@
 
 use core::arch::asm;
 
+/// Reads the ID of the hart that is running the code. Only works in machine mode
+pub fn mhartid_read() -> usize{
+    let value: usize;
+    unsafe{
+        asm!("csrr  {}, mhartid", out(reg) value);
+    }
+    value
+}
+
+/// Returns the ID of the current hart from any privilege mode.   
+/// mhartid cannot be read in supervisor mode, so the boot code copies it into the tp register before kinit. Nothing else writes tp
+pub fn hart_id() -> usize{
+    let value: usize;
+    unsafe{
+        asm!("mv  {}, tp", out(reg) value);
+    }
+    value
+}
+
 /// This function reads the value contained in the mstatus register and returns the value
 pub fn mstatus_read() -> usize{
     unsafe{
@@ -115,6 +134,24 @@ pub fn mtval_read() -> usize{
     }
 }
 
+/// This function reads the value contained in the scause register and returns the value
+pub fn scause_read() -> usize{
+    unsafe{
+        let value: u64;
+        asm!("csrr  {}, scause", out(reg) value);
+        return value as usize;
+    }
+}
+
+/// This function reads the value contained in the stval register and returns the value
+pub fn stval_read() -> usize{
+    unsafe{
+        let value: u64;
+        asm!("csrr  {}, stval", out(reg) value);
+        return value as usize;
+    }
+}
+
 // ------------ Writing Functions ----------------------- //
 pub fn mscratch_write(val: u64){
     unsafe{
@@ -158,6 +195,41 @@ pub fn sepc_write(val: u64){
     }
 }
 
+/// Sets the bits of the mask in the sstatus register, the other bits are left alone
+pub fn sstatus_set_bits(mask: u64){
+    unsafe{
+        asm!("csrs  sstatus, {}", in(reg) mask);
+    }
+}
+
+/// Clears the bits of the mask in the sstatus register, the other bits are left alone
+pub fn sstatus_clear_bits(mask: u64){
+    unsafe{
+        asm!("csrc  sstatus, {}", in(reg) mask);
+    }
+}
+
+/// Sets the bits of the mask in the sie register, the other bits are left alone
+pub fn sie_set_bits(mask: u64){
+    unsafe{
+        asm!("csrs  sie, {}", in(reg) mask);
+    }
+}
+
+/// Sets the bits of the mask in the sip register. Only SSIP is writable from supervisor mode
+pub fn sip_set_bits(mask: u64){
+    unsafe{
+        asm!("csrs  sip, {}", in(reg) mask);
+    }
+}
+
+/// Clears the bits of the mask in the sip register. Only SSIP is writable from supervisor mode
+pub fn sip_clear_bits(mask: u64){
+    unsafe{
+        asm!("csrc  sip, {}", in(reg) mask);
+    }
+}
+
 
 pub fn clear_TLB(){
     unsafe{
@@ -165,6 +237,42 @@ pub fn clear_TLB(){
     }
 }
 
+/// Flushes the TLB entries of one virtual address, for all address spaces. Global mappings included
+pub fn sfence_vma_address(virt_address: usize){
+    unsafe{
+        asm!("sfence.vma {}, zero", in(reg) virt_address);
+    }
+}
+
+/// Flushes the TLB entries of one address space (ASID). Global mappings are kept
+pub fn sfence_vma_asid(asid: usize){
+    unsafe{
+        asm!("sfence.vma zero, {}", in(reg) asid);
+    }
+}
+
+/// Flushes the TLB entries of one virtual address inside one address space (ASID). Global mappings are kept
+pub fn sfence_vma_address_asid(virt_address: usize, asid: usize){
+    unsafe{
+        asm!("sfence.vma {}, {}", in(reg) virt_address, in(reg) asid);
+    }
+}
+
+
+// ----------- instructions -------------------------------- //
+/// 2 for a compressed instruction, 4 otherwise. Only the lowest halfword is needed
+pub fn instruction_length(instruction: u32) -> usize{
+    if instruction & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+/// Reads the instruction at "pc". pc only has to be 2-byte aligned
+pub fn read_instruction(pc: usize) -> u32{
+    let low = unsafe { (pc as *const u16).read_volatile() } as u32;
+    if instruction_length(low) == 2 { return low; }
+    let high = unsafe { ((pc + 2) as *const u16).read_volatile() } as u32;
+    low | (high << 16)
+}
+
 
 // ----------- control functions -------------------------------- //
 pub fn call_mret(){
@@ -8,12 +8,13 @@ pub static mut STDOUT_BUFFER: [u8; 100] = [27; 100]; // fill the Buffer with esc
 
 
 /// This macro prints a formatted string to the console.  
-/// This macro is callable across the whole crate. It can also be called by external crates
+/// This macro is callable across the whole crate. It can also be called by external crates.  
+/// Nothing gets printed while GDB is attached : the console carries the remote serial protocol then (see debug_monitor::gdb_stub)
 #[macro_export]
 macro_rules! print {
     // a token is anything : from a costant to a variablle to a struct. Anything
     // print accepts one or more tokens and prints them.... 
-    ($($token: tt)+) => ({
+    ($($token: tt)+) => (if !$crate::debug_monitor::gdb_stub::is_attached() {
         // Uart::new is public
         // Although we re_create the buffer each time, we target the same memory location each time
         use core::fmt::Write;  // remove this to see t The Rust compiler takes the matched arm and extracts the variable from the argument stringhe error. I am confused about the differences between the Writes
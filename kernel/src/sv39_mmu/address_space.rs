@@ -0,0 +1,281 @@
+//! An AddressSpace owns a root translation table together with every table hanging below it.
+//!
+//! Instead of passing a raw root_table_address around, the kernel and each process hold an AddressSpace.
+//! The AddressSpace remembers which leaves own their physical pages (using the RSW bit 8 of the leaf entry).
+//! When the AddressSpace gets dropped, all the translation tables get freed, together with the owned pages.
+//! Pages that were mapped without ownership (MMIO, the kernel image) are left untouched.
+//!
+//! An address space can be duplicated cheaply with [AddressSpace::duplicate_copy_on_write].
+//! Only the translation tables get copied, the owned pages end up shared read-only by both address spaces.
+//! The first write to a shared page raises a StorePageFault, and the exception handler gives the writer a private copy.
+//!
+//! The kernel mappings live in a few top-level subtrees of the kernel address space, marked with the G (global) bit.
+//! A process address space does not copy them : stamp_kernel_mappings points its root entries at the very same subtrees.
+//! A change below those root entries is therefore seen by every address space at once, and traps keep working without switching satp.
+//! The stamped entries still belong to the kernel : dropping a process leaves them alone.
+//!
+//! An address space also keeps its virtual memory areas (see the vma module) : virtual ranges that are meant to be mapped,
+//! even if no page backs them yet. The pages of an area get filled on demand, when the page-fault handler finds the faulting address inside an area.
+
+use super::{map, translate, unmap_page, protect, unmap_owned, find_leaf_entry, make_satp, for_each_leaf, table_index, paging_mode, table_at};
+use super::tlb::{TlbFlush, flush_local, shootdown};
+use super::walker::{mappings, diff_mappings, Mappings, MappingDifference};
+use super::errors::{self, MappingError, TranslationError, UnmapError, VmaError};
+use super::vma::{Vma, VmaMap};
+use alloc::vec::Vec;
+use super::addresses::{VirtAddr, VirtPage};
+use crate::physical_memory::{PhysAddr, PhysFrame};
+use crate::page_manager;
+use crate::riscv;
+use crate::{print, println};
+
+// The root entries that cover the upper half : the top bit of the root index is the sign bit of the address, in Sv39 and in Sv48.
+// Only those entries hold kernel subtrees, the lower half is left to the processes
+const FIRST_HIGHER_HALF_ROOT_ENTRY : usize = 256;
+
+// The address space that is currently loaded in satp. The page-fault handler works on it
+static mut CURRENT_ADDRESS_SPACE : *mut AddressSpace = core::ptr::null_mut();
+
+/// Returns the address space that was last activated, if any
+pub fn current_address_space() -> Option<&'static mut AddressSpace>{
+    unsafe {
+        if CURRENT_ADDRESS_SPACE.is_null() { None }
+        else { Some(&mut *CURRENT_ADDRESS_SPACE) }
+    }
+}
+
+pub struct AddressSpace{
+    root_table: PhysFrame,
+    areas: VmaMap,
+    kernel_root_entries: [u64; 8] // bit i is set when root entry i was stamped from the kernel address space (512 bits)
+}
+
+impl AddressSpace{
+    /// Creates an empty address space. The root table gets allocated from the page allocator (it comes zeroed)
+    pub fn new() -> Self{
+        let root_table = page_manager::alloc(1).expect("unable to allocate a Page for the Root Table");
+        AddressSpace { root_table, areas: VmaMap::new(), kernel_root_entries: [0; 8] }
+    }
+
+    /// Loads this address space into satp and records it as the current address space.   
+    /// The address space must not move in memory while it is active (keep it in a static or on the heap)
+    pub fn activate(&mut self){
+        riscv::satp_write(self.satp() as u64);
+        flush_local(TlbFlush::All);
+        unsafe { CURRENT_ADDRESS_SPACE = self as *mut AddressSpace; }
+    }
+
+    /// The frame of the root table
+    pub fn root_table(&self) -> PhysFrame{
+        self.root_table
+    }
+
+    /// The physical address of the root table
+    pub fn root_table_address(&self) -> u64{
+        self.root_table.as_u64()
+    }
+
+    /// The value to be written to the satp register in order to switch to this address space
+    pub fn satp(&self) -> usize{
+        make_satp(self.root_table.as_usize())
+    }
+
+    /// Maps a virtual page to a physical page that does NOT belong to this address space.
+    /// Unmapping or dropping the address space will not free the physical page.
+    /// Fails if the page falls under a root entry stamped from the kernel : those subtrees belong to the kernel address space
+    pub fn map(&mut self, page: VirtPage, frame: PhysFrame, access_map: u64) -> Result<(), MappingError>{
+        self.check_not_in_kernel_subtree(page)?;
+        map(page, frame, access_map, self.root_table)
+    }
+
+    /// Maps a kernel page : not owned, and marked global (G bit) because it is meant to appear in every address space
+    pub fn map_global(&mut self, page: VirtPage, frame: PhysFrame, access_map: u64) -> Result<(), MappingError>{
+        self.check_not_in_kernel_subtree(page)?;
+        map(page, frame, access_map, self.root_table)?;
+        match find_leaf_entry(self.root_table, page) {
+            Ok(leaf_entry) => { leaf_entry.set_as_global(); Ok(()) },
+            Err(_) => Err(errors::MAPPING_ERROR_InvalidVirtualAddress)
+        }
+    }
+
+    /// Marks every valid root entry of the higher half as global. Called once the kernel mappings are in place :
+    /// each of those root entries then heads one of the shared kernel subtrees that stamp_kernel_mappings hands out
+    pub fn mark_root_entries_global(&mut self){
+        let root_table = table_at(self.root_table.as_u64());
+        for root_entry in root_table.content[FIRST_HIGHER_HALF_ROOT_ENTRY..].iter_mut() {
+            if root_entry.check_if_valid() == true { root_entry.set_as_global(); }
+        }
+    }
+
+    /// Points the higher half root entries of this address space at the global subtrees of the kernel address space.   
+    /// Nothing gets copied : later changes inside those subtrees show up here too.
+    /// Fails if this address space already uses one of the root entries that the kernel needs
+    pub fn stamp_kernel_mappings(&mut self, kernel: &AddressSpace) -> Result<(), MappingError>{
+        let kernel_root = table_at(kernel.root_table.as_u64());
+        let own_root = table_at(self.root_table.as_u64());
+
+        // check everything first, so that a failure leaves the address space untouched
+        for index in FIRST_HIGHER_HALF_ROOT_ENTRY..512 {
+            let kernel_entry = &kernel_root.content[index];
+            if kernel_entry.check_if_valid() == false || kernel_entry.check_if_global() == false { continue; }
+            if own_root.content[index].check_if_valid() == true && self.is_kernel_root_entry(index) == false {
+                return Err(errors::MAPPING_ERROR_KernelRootEntryTaken);
+            }
+        }
+        for index in FIRST_HIGHER_HALF_ROOT_ENTRY..512 {
+            let kernel_entry = &kernel_root.content[index];
+            if kernel_entry.check_if_valid() == false || kernel_entry.check_if_global() == false { continue; }
+            own_root.content[index].val = kernel_entry.get_val();
+            self.kernel_root_entries[index / 64] |= 1u64 << (index % 64);
+        }
+        Ok(())
+    }
+
+    /// Creates a process address space that already contains the kernel mappings
+    pub fn new_process(kernel: &AddressSpace) -> Self{
+        let mut address_space = AddressSpace::new();
+        address_space.stamp_kernel_mappings(kernel).expect("a fresh address space has no root entries");
+        address_space
+    }
+
+    /// true if the root entry was stamped from the kernel address space
+    pub fn is_kernel_root_entry(&self, index: usize) -> bool{
+        self.kernel_root_entries[index / 64] & (1u64 << (index % 64)) != 0
+    }
+
+    // a mapping below a stamped root entry would land in the tables of the kernel, and show up in every address space.
+    // The same goes for unmapping or reprotecting a page there
+    fn check_not_in_kernel_subtree(&self, page: VirtPage) -> Result<(), MappingError>{
+        let root_index = table_index(page.as_u64(), paging_mode().levels() - 1);
+        if self.is_kernel_root_entry(root_index) == true { return Err(errors::MAPPING_ERROR_KernelRootEntryTaken); }
+        Ok(())
+    }
+
+    /// Maps a virtual page to a physical page that belongs to this address space.
+    /// The physical page gets freed when the page is unmapped or when the address space is dropped. Fails like [AddressSpace::map]
+    pub fn map_owned(&mut self, page: VirtPage, frame: PhysFrame, access_map: u64) -> Result<(), MappingError>{
+        self.check_not_in_kernel_subtree(page)?;
+        map(page, frame, access_map, self.root_table)?;
+        match find_leaf_entry(self.root_table, page) {
+            Ok(leaf_entry) => { leaf_entry.set_as_owned(); Ok(()) },
+            Err(_) => Err(errors::MAPPING_ERROR_InvalidVirtualAddress)
+        }
+    }
+
+    /// Removes the mapping of a virtual page and returns the frame it used to point to.
+    /// If the frame was owned, it gets freed (or its share count drops if other address spaces still use it).
+    /// A frame that cannot be released is reported with UnmapError::Deallocation : the page is unmapped all the same.
+    /// The pages below the stamped kernel root entries cannot be unmapped here
+    pub fn unmap(&mut self, page: VirtPage) -> Result<PhysFrame, UnmapError>{
+        self.check_not_in_kernel_subtree(page).map_err(|_| UnmapError::Translation(errors::TRANS_ERROR_KernelRootEntry))?;
+        let removed_entry = unmap_page(self.root_table, page).map_err(UnmapError::Translation)?;
+        let frame = PhysFrame::new(removed_entry.get_address()).map_err(|_| UnmapError::Translation(errors::TRANS_ERROR_InvalidPhysicalAddress))?;
+        if removed_entry.check_if_owned() == true { // the page may still be shared copy-on-write with another address space
+            page_manager::release_page(frame).map_err(UnmapError::Deallocation)?;
+        }
+        Ok(frame)
+    }
+
+    /// Translates a virtual address into the physical address it is mapped to
+    pub fn translate(&self, virt_address: VirtAddr) -> Result<PhysAddr, TranslationError>{
+        translate(self.root_table, virt_address)
+    }
+
+    /// Changes the access permissions of an already mapped virtual page. Fails like [AddressSpace::map] below the kernel root entries
+    pub fn protect(&mut self, page: VirtPage, access_map: u64) -> Result<(), MappingError>{
+        self.check_not_in_kernel_subtree(page)?;
+        protect(self.root_table, page, access_map)
+    }
+
+    /// Records a virtual memory area. Its pages get filled on demand by the page-fault handler
+    pub fn add_area(&mut self, vma: Vma) -> Result<(), VmaError>{
+        self.areas.insert(vma)
+    }
+
+    /// Returns the virtual memory area that contains the virtual address
+    pub fn find_area(&self, virt_address: VirtAddr) -> Option<Vma>{
+        self.areas.lookup(virt_address.as_u64()).copied()
+    }
+
+    /// The virtual memory areas of this address space
+    pub fn areas(&self) -> &VmaMap{
+        &self.areas
+    }
+
+    pub fn areas_mut(&mut self) -> &mut VmaMap{
+        &mut self.areas
+    }
+
+    /// The mapped ranges of this address space, in increasing virtual address order
+    pub fn mappings(&self) -> Mappings{
+        mappings(self.root_table)
+    }
+
+    /// The virtual ranges that this address space maps differently from the other one.
+    /// "first" describes this address space, "second" the other one
+    pub fn diff(&self, other: &AddressSpace) -> Vec<MappingDifference>{
+        diff_mappings(self.root_table, other.root_table)
+    }
+
+    /// Creates a copy of this address space without copying the pages themselves.  
+    /// 1. Every owned page gets shared by both address spaces. Its share count goes up by one.  
+    /// 2. Owned pages that were writable become read-only + copy-on-write in BOTH address spaces.  
+    /// 3. Pages that are not owned (MMIO) are mapped the same way in the copy.  
+    /// 4. The kernel subtrees are shared with the copy, exactly like stamp_kernel_mappings does.  
+    /// The TLB of every hart gets flushed because this address space lost its write permissions
+    pub fn duplicate_copy_on_write(&mut self) -> AddressSpace{
+        let mut duplicate = AddressSpace::new();
+        let duplicate_root = duplicate.root_table;
+
+        let kernel_root_entries = self.kernel_root_entries;
+        let root_level = paging_mode().levels() - 1;
+        for_each_leaf(self.root_table, &mut |page, leaf_entry| {
+            // the kernel subtrees are shared, not copied (see below)
+            let root_index = table_index(page.as_u64(), root_level);
+            if kernel_root_entries[root_index / 64] & (1u64 << (root_index % 64)) != 0 { return; }
+
+            if leaf_entry.check_if_owned() == true {
+                if leaf_entry.check_if_writable() == true {
+                    leaf_entry.set_as_non_writable();
+                    leaf_entry.set_as_copy_on_write();
+                }
+                page_manager::share_page(leaf_entry.get_frame()).expect("unable to share a copy-on-write page");
+            }
+
+            // build the path to the leaf in the duplicate, then copy the leaf entry bit-for-bit
+            let access_map = leaf_entry.get_val() & 14u64;
+            map(page, leaf_entry.get_frame(), access_map, duplicate_root).expect("unable to duplicate a mapping");
+            let duplicate_entry = find_leaf_entry(duplicate_root, page).expect("duplicated mapping went missing");
+            duplicate_entry.val = leaf_entry.get_val();
+        });
+
+        // hand the same kernel subtrees to the duplicate
+        let own_root = table_at(self.root_table.as_u64());
+        let duplicate_root_table = table_at(duplicate_root.as_u64());
+        for index in 0..512 {
+            if self.is_kernel_root_entry(index) == false { continue; }
+            duplicate_root_table.content[index].val = own_root.content[index].get_val();
+        }
+        duplicate.kernel_root_entries = self.kernel_root_entries;
+
+        shootdown(TlbFlush::All);
+        duplicate.areas = self.areas.clone();
+        return duplicate;
+    }
+}
+
+impl Drop for AddressSpace{
+    fn drop(&mut self){
+        unsafe {
+            if CURRENT_ADDRESS_SPACE == self as *mut AddressSpace { CURRENT_ADDRESS_SPACE = core::ptr::null_mut(); }
+        }
+        // the kernel subtrees belong to the kernel address space : cut them off before freeing the tables
+        let root_table = table_at(self.root_table.as_u64());
+        for index in 0..512 {
+            if self.is_kernel_root_entry(index) == true { root_table.content[index].val = 0; }
+        }
+        if let Err(error) = unmap_owned(self.root_table) {
+            println!("Dropping an address space leaked pages : {:?}", error);
+        }
+    }
+}
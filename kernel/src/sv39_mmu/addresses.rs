@@ -0,0 +1,72 @@
+//! Typed addresses.
+//!
+//! The memory APIs used to pass plain u64s and usizes around, so nothing stopped a virtual address from being passed
+//! where a physical one was expected. These newtypes make the kind of the address part of the type :
+//! - VirtAddr : any byte address within the virtual range of the current paging mode
+//! - VirtPage : a page aligned VirtAddr, ie. the start of a virtual page
+//!
+//! The physical side (PhysAddr, PhysFrame) lives in the physical_memory module, where the page allocator can use it without depending on the MMU code.
+//! The constructors apply the same rules as validate_virtual_address.
+//! Converting back to a raw number is explicit (as_u64 / as_usize), for the places that talk to the hardware.
+
+use super::validate_virtual_address;
+use super::errors::{self, AddressError};
+use core::fmt;
+
+const PAGE_SIZE : u64 = 4096;
+
+/// A virtual byte address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct VirtAddr(u64);
+
+/// The start address of a virtual page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct VirtPage(u64);
+
+impl VirtAddr{
+    /// Fails if the address is outside the range of the current paging mode
+    pub fn new(address: u64) -> Result<Self, AddressError>{
+        // the validator also wants page alignment, so only its range rule is applied here
+        if validate_virtual_address(address & !(PAGE_SIZE - 1)) == false { return Err(errors::ADDRESS_ERROR_OutOfRange); }
+        Ok(VirtAddr(address))
+    }
+
+    pub fn as_u64(&self) -> u64{ self.0 }
+    pub fn as_usize(&self) -> usize{ self.0 as usize }
+
+    /// The byte offset of the address inside its page
+    pub fn page_offset(&self) -> u64{ self.0 & (PAGE_SIZE - 1) }
+
+    /// The page that contains the address
+    pub fn containing_page(&self) -> VirtPage{ VirtPage(self.0 & !(PAGE_SIZE - 1)) }
+}
+
+impl VirtPage{
+    /// Fails if the address is out of range or not divisible by 4096
+    pub fn new(address: u64) -> Result<Self, AddressError>{
+        if address % PAGE_SIZE != 0 { return Err(errors::ADDRESS_ERROR_Unaligned); }
+        if validate_virtual_address(address) == false { return Err(errors::ADDRESS_ERROR_OutOfRange); }
+        Ok(VirtPage(address))
+    }
+
+    pub fn start_address(&self) -> VirtAddr{ VirtAddr(self.0) }
+    pub fn as_u64(&self) -> u64{ self.0 }
+
+    /// The virtual page number (the address divided by 4096)
+    pub fn number(&self) -> u64{ self.0 / PAGE_SIZE }
+
+    /// The page that comes "count" pages after this one. Fails if it falls outside the virtual range
+    pub fn add(&self, count: u64) -> Result<VirtPage, AddressError>{
+        VirtPage::new(self.0 + count * PAGE_SIZE)
+    }
+}
+
+impl fmt::Display for VirtAddr{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "V:0x{:x}", self.0) }
+}
+
+impl fmt::Display for VirtPage{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "VPage:0x{:x}", self.0) }
+}
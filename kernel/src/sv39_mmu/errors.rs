@@ -1,9 +1,11 @@
 #[derive(Debug, PartialEq)]
 pub enum MappingError{
     InvalidPhysicalAddress(&'static str), // Address must be within the 56 bit range + It should be a Page_Address
-    InvalidVirtualAddress(&'static str), // Page_Address(ends wit 12 zeroes), within the 39 bit range 
+    InvalidVirtualAddress(&'static str), // Page_Address(ends wit 12 zeroes), within the 39 bit range (48 bits in Sv48)
     InvalidRootTableAddress(&'static str), // Address must be Taken, Page_Address(ends wit 12 zeroes), within the 56 bit range 
-    InvalidAccessMap(&'static str) // an access map is valid ONLY when at least one of the RXW is defined AND all other bits are ZERO
+    InvalidAccessMap(&'static str), // an access map is valid ONLY when at least one of the RXW is defined AND all other bits are ZERO
+    KernelRootEntryTaken(&'static str), // a process already uses a root entry that the kernel shares with every address space
+    AlreadyMapped(&'static str) // the virtual page is mapped already. It has to be unmapped first
 }
 
 
@@ -11,16 +13,44 @@ pub const MAPPING_ERROR_InvalidAccessMap : MappingError = MappingError::InvalidA
 pub const MAPPING_ERROR_InvalidRootTableAddress : MappingError = MappingError::InvalidRootTableAddress("Invalid Root table address passed to mapping function");
 pub const MAPPING_ERROR_InvalidVirtualAddress : MappingError = MappingError::InvalidVirtualAddress("Invalid Virtual address passed to mapping function");
 pub const MAPPING_ERROR_InvalidPhysicalAddress : MappingError = MappingError::InvalidPhysicalAddress("Invalid Physical address passed to mapping function");
+pub const MAPPING_ERROR_KernelRootEntryTaken : MappingError = MappingError::KernelRootEntryTaken("The address space already uses a root entry reserved for the kernel");
+pub const MAPPING_ERROR_AlreadyMapped : MappingError = MappingError::AlreadyMapped("The virtual page is already mapped, unmap it first");
 
 #[derive(Debug, PartialEq)]
 pub enum TranslationError{
     NonRangeVirtualAddress(&'static str),
     InvalidRootTableAddress(&'static str),
     UnallocatedVirtualAddress(&'static str),
-    InvalidPhysicalAddress(&'static str)
+    InvalidPhysicalAddress(&'static str),
+    KernelRootEntry(&'static str) // the page lies below a root entry that the kernel shares with every address space
 }
 
 pub const TRANS_ERROR_InvalidRootTableAddress : TranslationError = TranslationError::InvalidRootTableAddress("Invalid Root table address passed to translating function");
 pub const TRANS_ERROR_NonRangeVirtualAddress : TranslationError = TranslationError::NonRangeVirtualAddress("Out of Range virtual address passed to the translating function");
 pub const TRANS_ERROR_UnallocatedVirtualAddress : TranslationError = TranslationError::UnallocatedVirtualAddress("Attempted to translate an unmapped virtual address");
 pub const TRANS_ERROR_InvalidPhysicalAddress : TranslationError = TranslationError::InvalidPhysicalAddress("The Physical address has no access permissions");
+pub const TRANS_ERROR_KernelRootEntry : TranslationError = TranslationError::KernelRootEntry("The page belongs to a root entry reserved for the kernel");
+
+#[derive(Debug, PartialEq)]
+pub enum UnmapError{
+    Translation(TranslationError),          // the page could not be found in the tables
+    Deallocation(MemoryDeallocationError)   // the mapping is gone, but its owned page could not be released
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VmaError{
+    UnalignedRange(&'static str),   // the start or the length of the area is not a multiple of 4096, or the length is zero
+    InvalidAccessMap(&'static str), // same rules as the access map of the mapping function
+    OverlappingArea(&'static str),  // the new area overlaps an area that is already recorded
+    NoSuchArea(&'static str)        // no area contains the address
+}
+
+pub const VMA_ERROR_UnalignedRange : VmaError = VmaError::UnalignedRange("The start and length of a memory area must be non-zero multiples of 4096");
+pub const VMA_ERROR_InvalidAccessMap : VmaError = VmaError::InvalidAccessMap("Invalid access_map passed for a memory area");
+pub const VMA_ERROR_OverlappingArea : VmaError = VmaError::OverlappingArea("The memory area overlaps an existing memory area");
+pub const VMA_ERROR_NoSuchArea : VmaError = VmaError::NoSuchArea("No memory area contains the address");
+
+pub use crate::page_manager::MemoryDeallocationError;
+
+// The address errors are shared with the physical side
+pub use crate::physical_memory::{AddressError, ADDRESS_ERROR_OutOfRange, ADDRESS_ERROR_Unaligned};
@@ -18,6 +18,7 @@
 //! 
 
 use volatile_register::{RO, RW};
+use crate::physical_memory::PhysFrame;
 
 // THe SATP register
 #[repr(C)]
@@ -32,6 +33,44 @@ impl SATP_reg {
 }
 
 
+/// The translation schemes that the page-table walker understands.
+/// Both schemes use the same 4096-byte tables of 512 entries. They only differ in the number of table levels
+/// and in the width of the virtual address.
+/// Sv39 : 3 levels, 39-bit virtual addresses
+/// Sv48 : 4 levels, 48-bit virtual addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PagingMode{
+    Sv39,
+    Sv48
+}
+
+impl PagingMode{
+    /// The number of table levels that have to be walked to reach a leaf entry
+    pub const fn levels(&self) -> usize{
+        match self {
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+        }
+    }
+
+    /// The number of bits in a virtual address
+    pub const fn virtual_address_bits(&self) -> u32{
+        match self {
+            PagingMode::Sv39 => 39,
+            PagingMode::Sv48 => 48,
+        }
+    }
+
+    /// The value that goes into the MODE field (bits 60-63) of the satp register
+    pub const fn satp_mode(&self) -> usize{
+        match self {
+            PagingMode::Sv39 => 8,
+            PagingMode::Sv48 => 9,
+        }
+    }
+}
+
+
 
 
 // A Table Entry
@@ -71,6 +110,29 @@ impl TableEntry{
     pub fn set_as_executable(&mut self) { self.val = self.val | 8u64; }
     pub fn set_as_non_executable(&mut self) { self.val = self.val & !8u64; }
     pub fn set_as_usermode_only(&mut self) { self.val = self.val | 16u64; }
+    // The G bit marks a mapping that exists in every address space (the kernel). On a branch it covers the whole subtree below
+    pub fn set_as_global(&mut self) { self.val = self.val | 32u64; }
+    pub fn set_as_not_global(&mut self) { self.val = self.val & !32u64; }
+
+    /// replaces the RWX bits of the entry with the ones found in the access map. The other bits stay as they were
+    pub fn set_access_map(&mut self, access_map: u64){
+        self.val = (self.val & !14u64) | (access_map & 14u64);
+    }
+
+    // Bit 8 is one of the two RSW bits that the hardware ignores. We use it to mark leaves whose physical page
+    // belongs to the address space, so that the page gets freed together with the mapping
+    pub fn set_as_owned(&mut self) { self.val = self.val | (1u64 << 8); }
+    pub fn set_as_not_owned(&mut self) { self.val = self.val & !(1u64 << 8); }
+
+    // Bit 9 is the second RSW bit. It marks a page that was made read-only because it is shared copy-on-write.
+    // The first write to such a page gets a private copy of the page
+    pub fn set_as_copy_on_write(&mut self) { self.val = self.val | (1u64 << 9); }
+    pub fn set_as_not_copy_on_write(&mut self) { self.val = self.val & !(1u64 << 9); }
+
+    /// points the entry to another physical page while keeping all the flag bits
+    pub fn replace_address(&mut self, address: u64){
+        self.val = (self.val & 0b11_1111_1111) | (address >> 2);
+    }
 }
 
 // getter funtions
@@ -84,6 +146,12 @@ impl TableEntry{
         let address = (val << 2) & !0b111111111111; // zero out the last 12 bits
         return address;
     }
+
+    /// Gets the frame being refered to. Only the 44-bit PPN field is read, so the frame is always in range
+    pub fn get_frame(&self) -> PhysFrame {
+        let ppn = (self.get_val() >> 10) & ((1u64 << 44) - 1);
+        PhysFrame::new(ppn << 12).expect("a 44-bit PPN is always a valid frame")
+    }
 }
 
 // checker functions
@@ -122,6 +190,21 @@ impl TableEntry{
         if self.val & 16u64 == 16u64 { true }
         else {  false   }
     }
+
+    pub fn check_if_global(&self) -> bool{
+        if self.val & 32u64 == 32u64 { true }
+        else {  false   }
+    }
+
+    pub fn check_if_owned(&self) -> bool{
+        if self.val & (1u64 << 8) == (1u64 << 8) { true }
+        else {  false   }
+    }
+
+    pub fn check_if_copy_on_write(&self) -> bool{
+        if self.val & (1u64 << 9) == (1u64 << 9) { true }
+        else {  false   }
+    }
 }
 
 
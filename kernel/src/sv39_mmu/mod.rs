@@ -1,32 +1,97 @@
 //! This module abstracts the Riscv SV39 MMU. It provides mapping, unmapping and translation functions. You can additionally inspect the tables
+//! 
+//! The page-table walker is not tied to three levels. It also handles Sv48 (four levels, 48-bit virtual addresses).  
+//! The mode is chosen once at boot by [probe_paging_mode] and every map/translate/unmap call walks the number of levels of that mode.
 
 mod mmu_abstractions;
-mod errors;
+pub(crate) mod errors;
+mod address_space;
+mod vma;
+mod addresses;
+mod walker;
+mod tlb;
 mod tests;
 
-pub use mmu_abstractions::{Table, TableEntry};
-use errors::MappingError;
+pub use mmu_abstractions::{Table, TableEntry, PagingMode};
+pub use address_space::{AddressSpace, current_address_space};
+pub use vma::{Vma, VmaBacking, VmaMap};
+pub use addresses::{VirtAddr, VirtPage};
+pub use crate::physical_memory::{PhysAddr, PhysFrame};
+pub use tlb::{TlbFlush, flush_local, shootdown, handle_shootdown_interrupt, mark_hart_online, mark_hart_offline};
+pub use walker::{Mapping, Mappings, MappingDifference, mappings, diff_mappings};
+pub use errors::{MappingError, TranslationError, UnmapError, VmaError, AddressError};
 use crate::page_manager;
+use crate::map_kernel;
 use crate::{print, println};
 
+// The paging mode used by all the address spaces. It stays Sv39 until probe_paging_mode() finds out that the CPU can do better
+static mut PAGING_MODE : PagingMode = PagingMode::Sv39;
+
+/// Returns the paging mode that the walker is currently using
+pub fn paging_mode() -> PagingMode{
+    unsafe { PAGING_MODE }
+}
+
+extern "C"{
+    // the satp MODE that stuck when machine mode tried Sv48 (see asm/booze.s)
+    static BOOT_PROBED_SATP_MODE: usize;
+}
+
+/// Finds out the widest paging mode supported by the CPU and makes the walker use it.  
+/// The satp register is WARL : writing an unsupported MODE leaves the register unchanged. 
+/// Machine mode writes Sv48 into satp at boot and keeps the MODE that it reads back. If the MODE stuck, Sv48 is supported.   
+/// (Supervisor mode cannot probe : an Sv48 satp would switch its own translation on the spot)
+/// 
+/// This function has to be called before any page table gets built.
+pub fn probe_paging_mode() -> PagingMode{
+    let probed_mode = unsafe { BOOT_PROBED_SATP_MODE };
+    let mode = if probed_mode == PagingMode::Sv48.satp_mode() { PagingMode::Sv48 }
+               else { PagingMode::Sv39 };
+    unsafe { PAGING_MODE = mode; }
+    return mode;
+}
+
+/// Builds the satp value that makes the MMU use the translation tables found at root_table_address    
+/// The MODE field is taken from the current paging mode. The ASID is left as zero
+pub fn make_satp(root_table_address: usize) -> usize{
+    (paging_mode().satp_mode() << 60) | (root_table_address >> 12)
+}
+
+/// Sign-extends a virtual address the way the MMU expects it : all the bits above the top bit of the paging mode
+/// must be copies of that top bit. Addresses in the upper half (the higher-half kernel) start with 0xffff...
+pub fn canonical_virtual_address(virt_address: u64) -> u64{
+    let bits = paging_mode().virtual_address_bits();
+    if virt_address & (1u64 << (bits - 1)) != 0 { virt_address | (!0u64 << bits) }
+    else { virt_address }
+}
+
+// The translation tables live in physical frames. The kernel reaches them through the direct map
+pub(crate) fn table_at(table_address: u64) -> &'static mut Table{
+    let physical_address = PhysAddr::new(table_address).expect("translation table outside the physical range");
+    unsafe { &mut *(map_kernel::phys_to_virt(physical_address).as_u64() as *mut Table) }
+}
+
+// Extracts the index of the table entry used at the given level of the walk.   
+// Level 0 is the leaf table. Each level consumes 9 bits of the virtual address, starting after the 12-bit page offset
+fn table_index(virt_address: u64, level: usize) -> usize{
+    ((virt_address >> (12 + 9 * level)) & 0b111111111) as usize
+}
+
 /// The Map Function 
 /// Each Process gets its own Translation Tables ie. Root_Table, Mid_Table, Leaf_Table  
 /// The Map Function populates the Translation tables for that process   
 /// Whenever a process gets a new page allocated to it, the new Virtual page address and the corresponding new physical Address need to 
 /// get stored to the translation Tables of the specific process.  
 ///  
-/// The Map Function takes the Virtual address and the Physical address and puts them in the Translation Table (populates the table)
+/// The Map Function takes the Virtual page and the Physical frame and puts them in the Translation Table (populates the table)
 /// Inputs for the function :   
-///       1. Root Physical address extracted from SATP  
-///       2. Valid Virtual address to a Page    
-///          1. within the 39 bit range 
-///          2. divisible by 4096... ie it is a valid page address  
-///       3. Valid Physical address to a page   
-///          1. Extracting from the Page allocator will guaratee validity   
-///          2. within the 56 bit range 
-///          3. divisible by 4096   
+///       1. The frame of the Root table (the one found in SATP)  
+///       2. The virtual page. VirtPage::new() already checked that it is within the range of the paging mode and divisible by 4096  
+///       3. The physical frame. Extracting it from the Page allocator will guaratee validity   
 ///       4. Valid access permissions {at least one specification to be provided}  
 /// 
+/// A page that is already mapped is refused (MAPPING_ERROR_AlreadyMapped) : overwriting the leaf would leak the old frame
+/// and leave its translation in the TLBs. Unmap the page first (unmap_page flushes the TLBs)
 ///  
 /// 
 /// [remove]
@@ -39,77 +104,43 @@ use crate::{print, println};
 /// THis model of unmapping was chosen based on the design that at no point will physical addresses be shared by processes.... 
 /// unless the kernel lends both processes its own space by syscalls
 
-//    2. Errors : incorrect access specifications 
-pub fn map(virt_address: u64, physical_address: u64, access_map: u64, root_table_address: u64) -> Result<(), errors::MappingError>{
-    // validate all function inputs
-        if validate_virtual_address(virt_address) == false {  
-            return Err(errors::MAPPING_ERROR_InvalidVirtualAddress);
-        }
-
-        else if validate_physical_address(physical_address) == false {  
-            return Err(errors::MAPPING_ERROR_InvalidPhysicalAddress);
-        }
-
-        else if validate_access_map(access_map) == false {  
+//    2. Errors : incorrect access specifications
+pub fn map(page: VirtPage, frame: PhysFrame, access_map: u64, root_table: PhysFrame) -> Result<(), errors::MappingError>{
+    // the typed page and frame are valid by construction, only the access map is left to check
+        if validate_access_map(access_map) == false {  
             return Err(errors::MAPPING_ERROR_InvalidAccessMap);
         }
 
         else{
-            // Extract the parts of the virtual address
-            let root_table_index = (virt_address >> 30) &  0b111111111;
-            let mid_table_index = (virt_address >> 21) &  0b111111111;
-            let leaf_table_index = (virt_address >> 12) &  0b111111111;
-            let page_offset = virt_address & 0b111111111111;
-
-            // mutably access root entry
-            let root_table_ptr = root_table_address as *mut Table;
-            let root_table_ref = unsafe {&mut *root_table_ptr};
-            let root_table_entry = &mut root_table_ref.content[root_table_index as usize];
-            
-            // We need to traverse the Page tables up to the leaf table.    
+            let virt_address = page.as_u64();
+            // We need to traverse the Page tables from the root table down to the leaf table.    
             // The virtual address will define our traversal path. We will store the physical address on an entry of the leaf table
-
-            // Get the address of the mid_page_table    
-            // The address is stored in the entry of the root table
-                let mut mid_table_address : u64;
-                // check if entry points to a valid mid_page_table in the first place  
-                if root_table_entry.check_if_valid() == true {
-                    mid_table_address = root_table_entry.get_address();
+            // Sv39 has one middle level, Sv48 has two. Every level that is not the leaf level is handled the same way
+            let mut table_address = root_table.as_u64();
+            for level in (1..paging_mode().levels()).rev(){
+                // mutably access the entry of the current table
+                let table_ref = table_at(table_address);
+                let table_entry = &mut table_ref.content[table_index(virt_address, level)];
+
+                // check if entry points to a valid next-level table in the first place  
+                if table_entry.check_if_valid() == true {
+                    table_address = table_entry.get_address();
                 }
                 else { // make that table entry to point at a valid Page Table
-                    let new_table = page_manager::alloc(1).expect("unable to allocate a Page for the Mid Table") as u64;
-                    // println!("***** Mid table was given the address : {:016x}",new_table );
-                    root_table_entry.set_address(new_table);
-                    root_table_entry.set_as_valid();
-                    mid_table_address = new_table;
-                }
-                // get a mutable reference to the mid table entry
-                let mid_table_ptr = mid_table_address as *mut Table;
-                let mid_table_ref = unsafe {&mut *mid_table_ptr};
-                let mid_table_entry = &mut mid_table_ref.content[mid_table_index as usize];
-
-            // Get the address of the leaf_page_table
-                let mut leaf_table_address : u64;
-                
-                // check if mid table entry points to a valid leaf_page_table in the first place 
-                if mid_table_entry.check_if_valid() == true {
-                    leaf_table_address = mid_table_entry.get_address();
-                }
-                else { // make that table entry to point at a valid Page Table ie. Leaf Page Table
-                    let new_table = page_manager::alloc(1).expect("unable to allocate a Page for the Leaf Table") as u64;
-                    // println!("***** Leaf table was given the address : {:016x}",new_table );
-                    mid_table_entry.set_address(new_table);
-                    mid_table_entry.set_as_valid();
-                    leaf_table_address = new_table;
+                    let new_table = page_manager::alloc(1).expect("unable to allocate a Page for a Translation Table").as_u64();
+                    table_entry.set_address(new_table);
+                    table_entry.set_as_valid();
+                    table_address = new_table;
                 }
+            }
 
             // Get a mutable reference to the Leaf table entry
-                let leaf_table_ptr = leaf_table_address as *mut Table;
-                let leaf_table_ref = unsafe {&mut *leaf_table_ptr};
-                let leaf_table_entry = &mut leaf_table_ref.content[leaf_table_index as usize];
+                let leaf_table_ref = table_at(table_address);
+                let leaf_table_entry = &mut leaf_table_ref.content[table_index(virt_address, 0)];
+                if leaf_table_entry.check_if_valid() == true { return Err(errors::MAPPING_ERROR_AlreadyMapped); }
                 
             // Set the leaf entry to point to the physical Page address
-                leaf_table_entry.set_address(physical_address);
+                leaf_table_entry.set_address(frame.as_u64());
                 leaf_table_entry.set_as_valid();
                 leaf_table_entry.add_access_mask(access_map);
             
@@ -120,56 +151,38 @@ pub fn map(virt_address: u64, physical_address: u64, access_map: u64, root_table
 
 
 /// This function returns the physical address that corresponds to the input virtual address     
-/// If the virtual address cannot be transalted, a None value is returned   
-/// If the virtual address can be translated, the physical address is returned in a Some() wrapper   
+/// If the virtual address cannot be transalted, an error is returned   
 /// A virtual address may not get translated because :  
-///     1. The virtual address is beyond the 39 bit value range     
-///     2. The Virtual address is referencing an address that has not yet been allocated to the process that is using that address
-
-pub fn translate(root_table_address: u64, virt_address: u64) -> Result<u64, errors::TranslationError>{
-    // validate the virtual address
-        // check if address is out of range
-        if virt_address > 2u64.pow(39) { return Err(errors::TRANS_ERROR_NonRangeVirtualAddress); }
-        else { /* continue with the function... */}
-    
-    // after validation, we move through the traslation table till we hit a dead End or find a leat Page Table Entry
-        // extract the table indexes and the offset from the virtual address
-            let root_table_index = (virt_address >> 30) &  0b111111111;
-            let mid_table_index = (virt_address >> 21) &  0b111111111;
-            let leaf_table_index = (virt_address >> 12) &  0b111111111;
-            let page_offset = virt_address & 0b111111111111;
-
-        // loop through the translation table
-            // get entry under the root table
-            let root_table_ptr = root_table_address as *const Table;
-            let root_table_ref = unsafe { & *root_table_ptr};
-            let root_table_entry = & root_table_ref.content[root_table_index as usize];
-
-            // check if entry is valid or not
-            if root_table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
-            else { /* continue */} 
-
-            // get entry under the mid level Page
-            let mid_table_ptr = root_table_entry.get_address() as *const Table;
-            let mid_table_ref = unsafe { & *mid_table_ptr};
-            let mid_table_entry = & mid_table_ref.content[mid_table_index as usize];
-
-            // check if entry is valid or not
-            if mid_table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
-            else { /* continue */}
+///     1. The Virtual address is referencing an address that has not yet been allocated to the process that is using that address
+///     2. The leaf entry holds no access permissions
+
+pub fn translate(root_table: PhysFrame, virt_address: VirtAddr) -> Result<PhysAddr, errors::TranslationError>{
+    // VirtAddr::new() already made sure that the address is within range.
+    // we move through the traslation table till we hit a dead End or find a leat Page Table Entry
+        let page_offset = virt_address.page_offset();
+        let virt_address = virt_address.as_u64();
+
+        // loop through the non-leaf levels of the translation table
+            let mut table_address = root_table.as_u64();
+            for level in (1..paging_mode().levels()).rev(){
+                let table_ref = table_at(table_address);
+                let table_entry = & table_ref.content[table_index(virt_address, level)];
+
+                // check if entry is valid or not
+                if table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
+                else { table_address = table_entry.get_address(); } 
+            }
 
             // get entry under the leaf level Page
-            let leaf_table_ptr = mid_table_entry.get_address() as *const Table;
-            let leaf_table_ref = unsafe { & *leaf_table_ptr};
-            let leaf_table_entry = & leaf_table_ref.content[leaf_table_index as usize];
+            let leaf_table_ref = table_at(table_address);
+            let leaf_table_entry = & leaf_table_ref.content[table_index(virt_address, 0)];
 
             // check if entry is valid or not
             if leaf_table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
             else {  // extract the physical address
                 if leaf_table_entry.check_if_branch() == true {    return Err(errors::TRANS_ERROR_InvalidPhysicalAddress);        }
                 let physical_page_address = leaf_table_entry.get_address();
-                let physical_byte_address = physical_page_address + page_offset;
-                return Ok(physical_byte_address);
+                return PhysAddr::new(physical_page_address + page_offset).map_err(|_| errors::TRANS_ERROR_InvalidPhysicalAddress);
             }
 
 }
@@ -177,80 +190,159 @@ pub fn translate(root_table_address: u64, virt_address: u64) -> Result<u64, erro
 /// This function frees the following pages :   
 /// 1. All the physical Pages referenced in the translation tables
 /// 2. All the translation tables themselves
-pub fn unmap(root_table_address: u64){
-
-    let root_table_ptr = root_table_address as *mut Table;
-    let root_table_ref = unsafe { &mut *root_table_ptr};
-
+/// 
+/// A page that fails to get freed does not stop the walk : everything else still gets freed and the first error is returned
+pub fn unmap(root_table: PhysFrame) -> Result<(), page_manager::MemoryDeallocationError>{
     // we will loop through the root table entries, one by one. 
-    // if a entry points to a mid_level page table... we visit that mid level table
-    // once we are in that mid level page, we loop through the entries.
-    // if an entry points to a leaf table, we visit that table
+    // if a entry points to a lower level page table... we visit that table
+    // once we are in that table, we loop through the entries... until we get to the leaf tables
     // if a leaf entry points to a physical page, you free that page
+    unmap_table(root_table, paging_mode().levels() - 1, false)
+}
 
-    for root_index in (0..512){
-        // access the root table entry
-        let root_table_entry = &root_table_ref.content[root_index as usize];
-        if root_table_entry.check_if_valid() == false { continue; }
-        else { // access the mid_level_page
-            let mid_table_ptr = root_table_entry.get_address() as *const Table;
-            let mid_table_ref = unsafe {& *mid_table_ptr};
-
-            // loop through the mid table entries and when you are done, deallocate the mid Table itself
-            for mid_index in (0..512){
-                // access each mid entry
-                let mid_table_entry = &mid_table_ref.content[mid_index as usize];
-
-                if mid_table_entry.check_if_valid() == false { /* do nothing */}
-                else { // access leaf Table Page
-                    let leaf_table_ptr = mid_table_entry.get_address() as *const Table;
-                    let leaf_table_ref = unsafe { & *leaf_table_ptr};
-
-                    // loop through the entries of the leaf table, and when youre done, deallocate the leaf table itself
-                    for leaf_index in (0..512){
-                        let leaf_table_entry = &leaf_table_ref.content[leaf_index as usize];
-                        if leaf_table_entry.check_if_valid() == false { /* do nothing */}
-                        else { // deallocate the physical address being referenced 
-                           page_manager::dealloc(leaf_table_entry.get_address() as usize);
-                        //    println!(" >>>> I WILL DEALLOCATE : {:016x}", leaf_table_entry.get_address() as usize);
-                        }
-                    }
-
-                    // deallocate leaf table itself
-                    // println!(" >>>> I WILL DEALLOCATE leaf address: {:016x}", leaf_table_ptr as usize);
-                    page_manager::dealloc(leaf_table_ptr as usize);
-                }
-            }
+/// This function frees all the translation tables, but only frees the physical pages of the leaves that are marked as owned.   
+/// Pages that were mapped without ownership (eg. MMIO or kernel image pages) are left alone. Errors are handled like in [unmap]
+pub fn unmap_owned(root_table: PhysFrame) -> Result<(), page_manager::MemoryDeallocationError>{
+    unmap_table(root_table, paging_mode().levels() - 1, true)
+}
 
-            // deallocate the Mid Table itself
-            page_manager::dealloc(mid_table_ptr as usize);
+// Frees everything referenced by the table found on the frame, and then frees the table itself.   
+// The level tells how far the table is from the leaves. Leaf tables are at level 0.
+// Keeps going after a failed deallocation and returns the first error it met
+fn unmap_table(table: PhysFrame, level: usize, only_owned_pages: bool) -> Result<(), page_manager::MemoryDeallocationError>{
+    let table_ref = table_at(table.as_u64());
+    let mut first_error = Ok(());
+
+    for index in 0..512{
+        let table_entry = &table_ref.content[index];
+        let result = if table_entry.check_if_valid() == false { continue; }
+        else if level == 0 { // deallocate the physical address being referenced 
+            if only_owned_pages == false { page_manager::dealloc(table_entry.get_frame()) }
+            else if table_entry.check_if_owned() == true { // owned pages may be shared copy-on-write with other address spaces
+                page_manager::release_page(table_entry.get_frame())
+            }
+            else { continue; }
         }
+        else { // visit the lower level table, it deallocates itself when done
+            unmap_table(table_entry.get_frame(), level - 1, only_owned_pages)
+        };
+        if first_error.is_ok() == true { first_error = result; }
     }
 
-    // deallocate the Root table itself
-    page_manager::dealloc(root_table_address as usize);
+    // deallocate the table itself
+    let result = page_manager::dealloc(table);
+    if first_error.is_ok() == true { first_error = result; }
+    first_error
 }
 
-// Function validates a virtual address. It returns true if the address is ...  
-// 1. Within a 39 bit range
-// 2. Not divisible by 4096
-fn validate_virtual_address(address: u64) -> bool{
-    // check if address is under the 39-bit threshold
-    if address > 2u64.pow(39){ return false; }
+/// Removes the mapping of a single virtual page and returns the leaf entry that was removed.  
+/// The physical page itself is NOT freed, the caller decides what to do with it (check the owned bit of the returned entry).    
+/// The TLB of every hart gets flushed because the removed translation may still be cached. After the return, the page can be reused
+pub fn unmap_page(root_table: PhysFrame, page: VirtPage) -> Result<TableEntry, errors::TranslationError>{
+    let leaf_table_entry = find_leaf_entry(root_table, page)?;
+    let removed_entry = TableEntry { val: leaf_table_entry.get_val() };
+    leaf_table_entry.val = 0;
+    shootdown(TlbFlush::Page(page.as_u64()));
+    return Ok(removed_entry);
+}
 
-    // check if address has 12 trailing zeroes, ie. It is divisible by 4096
-    if address % 4096 != 0 { return false; }
+/// Changes the access permissions of an already mapped virtual page.  
+/// The access map follows the same rules as the one passed to the map function
+pub fn protect(root_table: PhysFrame, page: VirtPage, access_map: u64) -> Result<(), errors::MappingError>{
+    if validate_access_map(access_map) == false {  
+        return Err(errors::MAPPING_ERROR_InvalidAccessMap);
+    }
+    match find_leaf_entry(root_table, page) {
+        Ok(leaf_table_entry) => leaf_table_entry.set_access_map(access_map),
+        Err(_) => return Err(errors::MAPPING_ERROR_InvalidVirtualAddress)
+    }
+    shootdown(TlbFlush::Page(page.as_u64()));
+    return Ok(());
+}
 
-    // else return true
+/// Calls the function f for every valid leaf entry found under the root table.  
+/// The function receives the virtual page that the leaf maps, and a mutable reference to the leaf entry itself
+pub fn for_each_leaf(root_table: PhysFrame, f: &mut dyn FnMut(VirtPage, &mut TableEntry)){
+    for_each_leaf_in_table(root_table.as_u64(), paging_mode().levels() - 1, 0, f);
+}
+
+fn for_each_leaf_in_table(table_address: u64, level: usize, virt_address_prefix: u64, f: &mut dyn FnMut(VirtPage, &mut TableEntry)){
+    let table_ref = table_at(table_address);
+    for index in 0..512{
+        let combined_virt_address = canonical_virtual_address(virt_address_prefix | ((index as u64) << (12 + 9 * level)));
+        let table_entry = &mut table_ref.content[index];
+        if table_entry.check_if_valid() == false { continue; }
+        else if level == 0 { f(VirtPage::new(combined_virt_address).expect("a leaf maps a canonical page"), table_entry); }
+        else { for_each_leaf_in_table(table_entry.get_address(), level - 1, combined_virt_address, f); }
+    }
+}
+
+/// Extracts the frame of the root table from a satp value.  
+/// Returns None if the satp value has translation switched off (Bare mode)
+pub fn root_table_from_satp(satp_value: usize) -> Option<PhysFrame>{
+    if (satp_value >> 60) == 0 { return None; }
+    let root_ppn = satp_value & ((1usize << 44) - 1);
+    return PhysFrame::new((root_ppn << 12) as u64).ok();
+}
+
+// true if every page of the range is mapped with the permission, in the page tables that are active
+fn is_mapped(root_table: PhysFrame, address: usize, length: usize, write: bool) -> bool{
+    let last = address + length - 1;
+    let mut page = address & !(page_manager::PAGE_SIZE - 1);
+    while page <= last {
+        let allowed = match VirtPage::new(page as u64).map_err(|_| errors::TRANS_ERROR_NonRangeVirtualAddress).and_then(|page| find_leaf_entry(root_table, page)) {
+            Ok(leaf_entry) => if write == true { leaf_entry.check_if_writable() } else { leaf_entry.check_if_readable() },
+            Err(_) => false
+        };
+        if allowed == false { return false; }
+        page += page_manager::PAGE_SIZE;
+    }
     true
 }
 
-// checks if a function :   
-// 1. is within the 56 bit range
-// 2. is divisible by 4096
-fn validate_physical_address(address: u64) -> bool{
-    // check if address is under the 56-bit threshold
-    if address > 2u64.pow(56){ return false; }
+/// true if the "length" bytes at "address" can be accessed through the page tables that are active. Without translation,
+/// only the kernel memory map is accessible.   
+/// Code that touches memory on behalf of a trapped context (the debug monitor, the misaligned access emulation) checks with it first
+pub fn is_accessible(address: usize, length: usize, write: bool) -> bool{
+    let end = match address.checked_add(length) { Some(end) if length > 0 => end, _ => return false };
+    match root_table_from_satp(crate::riscv::satp_read()) {
+        Some(root_table) => is_mapped(root_table, address, length, write),
+        None => map_kernel::kernel_regions().iter().chain(core::iter::once(&map_kernel::KERNEL_STACK_REGION))
+                                            .any(|region| address >= region.start() && end <= region.end())
+    }
+}
+
+/// Walks the translation tables and returns a mutable reference to the valid leaf entry that maps the virtual page.   
+/// Unlike the map function, no table gets allocated on the way
+pub fn find_leaf_entry(root_table: PhysFrame, page: VirtPage) -> Result<&'static mut TableEntry, errors::TranslationError>{
+    let virt_address = page.as_u64();
+    let mut table_address = root_table.as_u64();
+    for level in (1..paging_mode().levels()).rev(){
+        let table_ref = table_at(table_address);
+        let table_entry = &table_ref.content[table_index(virt_address, level)];
+        if table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
+        table_address = table_entry.get_address();
+    }
+
+    let leaf_table_ref = table_at(table_address);
+    let leaf_table_entry = &mut leaf_table_ref.content[table_index(virt_address, 0)];
+    if leaf_table_entry.check_if_valid() == false { return  Err(errors::TRANS_ERROR_UnallocatedVirtualAddress); }
+    return Ok(leaf_table_entry);
+}
+
+// Function validates a virtual address. It returns true if the address is ...  
+// 1. Canonical in the current paging mode : below 2^38 for Sv39 (2^47 for Sv48), or a sign-extended upper half address
+// 2. Not divisible by 4096
+fn validate_virtual_address(address: u64) -> bool{
+    validate_virtual_address_in_mode(address, paging_mode())
+}
+
+// Validates a virtual address against the range of a specific paging mode
+fn validate_virtual_address_in_mode(address: u64, mode: PagingMode) -> bool{
+    // check if address is in the lower half of the paging mode (top bit clear), or sign-extended into the upper half
+    let bits = mode.virtual_address_bits();
+    let upper_half_start = !0u64 << (bits - 1);
+    if address >= 1u64 << (bits - 1) && address < upper_half_start { return false; }
 
     // check if address has 12 trailing zeroes, ie. It is divisible by 4096
     if address % 4096 != 0 { return false; }
@@ -267,51 +359,9 @@ fn validate_access_map(map: u64) -> bool{
     else { false }
 }
 
-/// Shows the virtual-to-physical Table
-pub fn show_mappings(root_table_address: u64){
-
-    let root_table_ptr = root_table_address as *mut Table;
-    let root_table_ref = unsafe { &mut *root_table_ptr};
-
-    // parts for reconstructiong the virtual address
-    let mut virt_address_root : u64;
-    let mut virt_address_mid : u64;
-    let mut virt_address_leaf : u64;
-
-    for root_index in (0..512){
-        virt_address_root = root_index;
-        // access the root table entry
-        let root_table_entry = &root_table_ref.content[root_index as usize];
-        if root_table_entry.check_if_valid() == false { continue; }
-        else { // access the mid_level_page
-            let mid_table_ptr = root_table_entry.get_address() as *const Table;
-            let mid_table_ref = unsafe {& *mid_table_ptr};
-
-            // loop through the mid table entries and when you are done, deallocate the mid Table itself
-            for mid_index in (0..512){
-                virt_address_mid = mid_index;
-                // access each mid entry
-                let mid_table_entry = &mid_table_ref.content[mid_index as usize];
-
-                if mid_table_entry.check_if_valid() == false { /* do nothing */}
-                else { // access leaf Table Page
-                    let leaf_table_ptr = mid_table_entry.get_address() as *const Table;
-                    let leaf_table_ref = unsafe { & *leaf_table_ptr};
-
-                    // loop through the entries of the leaf table
-                    for leaf_index in (0..512){
-                        virt_address_leaf = leaf_index;
-                        let leaf_table_entry = &leaf_table_ref.content[leaf_index as usize];
-                        if leaf_table_entry.check_if_valid() == false { /* do nothing */}
-                        else { // print the physical address being referenced 
-                           let combined_virt_address = (virt_address_root << 30) | (virt_address_mid << 21) | (virt_address_leaf << 12);
-                           let physical_address = leaf_table_entry.get_address();
-                           println!(" \t >>>> {:016x} : {:016x}", combined_virt_address, physical_address);
-                        }
-                    }
-
-                }
-            }
-        }
+/// Shows the virtual-to-physical Table, one line per contiguous range (see [mappings])
+pub fn show_mappings(root_table: PhysFrame){
+    for mapping in mappings(root_table) {
+        println!(" \t >>>> {:016x} - {:016x} : {:016x} : flags {:010b}", mapping.virt_start, mapping.virt_end(), mapping.phys_start, mapping.flags);
     }
 }
@@ -1,7 +1,6 @@
 use crate::test_framework::custom_assert;
 use crate::{println, print};
 use crate::sv39_mmu::validate_virtual_address;
-use crate::sv39_mmu::validate_physical_address;
 use crate::sv39_mmu::errors;
 use crate::sv39_mmu::*;
 
@@ -10,13 +9,33 @@ pub fn sv39_mmu_test_switch(){
     println!("\n---------  Running sv39_mmu tests  ---------\n");
     test_validate_virtual_address_above_range();
     test_validate_virtual_address_within_range();
-    test_validate_physical_address_within_range();
-    test_validate_physical_address_above_range();
     test_validate_access_map_messed();
     test_validate_access_map_RWX();
     test_map_function_catches_bad_phy_addr();
     test_map_function_catches_bad_virt_addr();
     test_map_function_catches_bad_access_map();
+    test_map_function_refuses_a_mapped_page();
+    test_unmap_reports_an_unreleasable_page();
+    test_validate_virtual_address_sv48_within_range();
+    test_validate_virtual_address_sv48_above_range();
+    test_validate_virtual_address_upper_half();
+    test_table_index_per_level();
+    test_set_access_map_keeps_owned_bit();
+    test_global_bit_keeps_access_map();
+    test_replace_address_keeps_copy_on_write_bit();
+    test_root_table_from_satp();
+    test_vma_contains();
+    test_vma_map_rejects_overlap();
+    test_vma_map_lookup();
+    test_vma_map_split();
+    test_vma_map_merge();
+    test_vma_map_remove_range();
+    test_virt_page_rejects_unaligned_address();
+    test_virt_addr_containing_page();
+    test_mappings_coalesce_contiguous_pages();
+    test_diff_mappings();
+    test_tlb_flush_survives_the_mailbox();
+    test_remote_harts_skip_the_initiator();
 }
 
 fn test_validate_virtual_address_above_range(){
@@ -28,29 +47,51 @@ fn test_validate_virtual_address_above_range(){
 }
 
 fn test_validate_virtual_address_within_range(){
-    let test_address: u64 = 2u64.pow(39); 
+    let test_address: u64 = 2u64.pow(37); 
     let res = validate_virtual_address(test_address);
     let suc_msg = "test_validate_virtual_address_within_range    ....   [OK]";
     let fail_msg = "test_validate_virtual_address_within_range   ....    [FAIL]";
     custom_assert(true, res, suc_msg, fail_msg);
 }
 
-fn test_validate_physical_address_within_range(){
-    let test_address: u64 = 2u64.pow(56); 
-    let res = validate_physical_address(test_address);
-    let suc_msg = "test_validate_physical_address_within_range    ....   [OK]";
-    let fail_msg = "test_validate_physical_address_within_range   ....    [FAIL]";
+fn test_validate_virtual_address_sv48_within_range(){
+    let test_address: u64 = 2u64.pow(46); 
+    let res = validate_virtual_address_in_mode(test_address, PagingMode::Sv48);
+    let suc_msg = "test_validate_virtual_address_sv48_within_range    ....   [OK]";
+    let fail_msg = "test_validate_virtual_address_sv48_within_range   ....    [FAIL]";
     custom_assert(true, res, suc_msg, fail_msg);
 }
 
-fn test_validate_physical_address_above_range(){
-    let test_address: u64 = 2u64.pow(60); 
-    let res = validate_physical_address(test_address);
-    let suc_msg = "test_validate_physical_address_above_range    ....   [OK]";
-    let fail_msg = "test_validate_physical_address_above_range   ....    [FAIL]";
+fn test_validate_virtual_address_sv48_above_range(){
+    let test_address: u64 = 2u64.pow(49); 
+    let res = validate_virtual_address_in_mode(test_address, PagingMode::Sv48);
+    let suc_msg = "test_validate_virtual_address_sv48_above_range    ....   [OK]";
+    let fail_msg = "test_validate_virtual_address_sv48_above_range   ....    [FAIL]";
     custom_assert(false, res, suc_msg, fail_msg);
 }
 
+// sign-extended addresses are valid, addresses in the hole between the two halves are not.
+// An address with the top bit of the mode set but no sign extension (2^38 in Sv39, 2^47 in Sv48) falls in the hole
+fn test_validate_virtual_address_upper_half(){
+    let res = (validate_virtual_address_in_mode(0xffff_ffc0_8000_0000, PagingMode::Sv39),
+               validate_virtual_address_in_mode(0xffff_ff80_0000_0000, PagingMode::Sv39),
+               validate_virtual_address_in_mode(0xffff_ffc0_8000_0000, PagingMode::Sv48),
+               validate_virtual_address_in_mode(2u64.pow(38), PagingMode::Sv39),
+               validate_virtual_address_in_mode(2u64.pow(47), PagingMode::Sv48));
+    let suc_msg = "test_validate_virtual_address_upper_half    ....   [OK]";
+    let fail_msg = "test_validate_virtual_address_upper_half   ....    [FAIL]";
+    custom_assert((true, false, true, false, false), res, suc_msg, fail_msg);
+}
+
+// the fourth Sv48 level uses bits 39-47 of the virtual address
+fn test_table_index_per_level(){
+    let test_address: u64 = (5u64 << 39) | (4u64 << 30) | (3u64 << 21) | (2u64 << 12) | 0x123; 
+    let res = [table_index(test_address, 3), table_index(test_address, 2), table_index(test_address, 1), table_index(test_address, 0)];
+    let suc_msg = "test_table_index_per_level    ....   [OK]";
+    let fail_msg = "test_table_index_per_level   ....    [FAIL]";
+    custom_assert([5, 4, 3, 2], res, suc_msg, fail_msg);
+}
+
 fn test_validate_access_map_messed(){
     let test_map: u64 = 0b11110;
     let res = validate_access_map(test_map);
@@ -88,52 +129,259 @@ fn test_validate_access_map_RO(){
 // --------------------  test the Map Function -------------------------- //
 
 fn test_map_function_catches_bad_phy_addr(){
-    let bad_physical_address: u64 = 2u64.pow(61);
-    let good_virtual_address: u64 = 2u64.pow(24);
-    let good_access_map : u64 = 2u64; // read Only access map
-    let good_root_table_adress: u64 = 2u64.pow(20);
-
-    let res = map(good_virtual_address, bad_physical_address, good_access_map, good_root_table_adress); 
-    match res {
-        Ok(x) => println!("test_map_function_catches_bad_phy_addr ...  [FAIL]"),
-        Err(x) => {
-            if x == MappingError::InvalidPhysicalAddress("Invalid Physical address passed to mapping function"){
-                println!("test_map_function_catches_bad_phy_addr ...  [OK]")
-            }
-        }
-    }
+    // a bad physical address cannot even become a frame, so it never reaches the map function
+    let res = PhysFrame::new(2u64.pow(61));
+    let suc_msg = "test_map_function_catches_bad_phy_addr    ....   [OK]";
+    let fail_msg = "test_map_function_catches_bad_phy_addr   ....    [FAIL]";
+    custom_assert(Err(errors::ADDRESS_ERROR_OutOfRange), res, suc_msg, fail_msg);
 }
 
 fn test_map_function_catches_bad_virt_addr(){
-    let good_physical_address: u64 = 2u64.pow(48);
-    let bad_virtual_address: u64 = 2u64.pow(48);
-    let good_access_map : u64 = 2u64; // read Only access map
-    let good_root_table_adress: u64 = 2u64.pow(20);
-
-    let res = map(bad_virtual_address, good_physical_address, good_access_map, good_root_table_adress); 
-    match res {
-        Ok(x) => println!("test_map_function_catches_bad_virt_addr ...  [FAIL]"),
-        Err(x) => {
-            if x == MappingError::InvalidVirtualAddress("Invalid Virtual address passed to mapping function"){
-                println!("test_map_function_catches_bad_virt_addr ...  [OK]")
-            }
-        }
-    }
+    let res = VirtPage::new(2u64.pow(48));
+    let suc_msg = "test_map_function_catches_bad_virt_addr    ....   [OK]";
+    let fail_msg = "test_map_function_catches_bad_virt_addr   ....    [FAIL]";
+    custom_assert(Err(errors::ADDRESS_ERROR_OutOfRange), res, suc_msg, fail_msg);
 }
 
 fn test_map_function_catches_bad_access_map(){
-    let good_physical_address: u64 = 2u64.pow(48);
-    let good_virtual_address: u64 = 2u64.pow(24);
+    let good_physical_frame = PhysFrame::new(2u64.pow(48)).unwrap();
+    let good_virtual_page = VirtPage::new(2u64.pow(24)).unwrap();
     let bad_access_map : u64 = 0b100110; 
-    let good_root_table_adress: u64 = 2u64.pow(20);
-
-    let res = map(good_virtual_address, good_physical_address, bad_access_map, good_root_table_adress); 
-    match res {
-        Ok(x) => println!("test_map_function_catches_bad_access_map ...  [FAIL]"),
-        Err(x) => {
-            if x == MappingError::InvalidAccessMap("Invalid access_map passed to mapping function"){
-                println!("test_map_function_catches_bad_access_map ...  [OK]")
-            }
-        }
+    let good_root_table = PhysFrame::new(2u64.pow(20)).unwrap();
+
+    let res = map(good_virtual_page, good_physical_frame, bad_access_map, good_root_table); 
+    let suc_msg = "test_map_function_catches_bad_access_map    ....   [OK]";
+    let fail_msg = "test_map_function_catches_bad_access_map   ....    [FAIL]";
+    custom_assert(Err(errors::MAPPING_ERROR_InvalidAccessMap), res, suc_msg, fail_msg);
+}
+
+// the first mapping stays in place until the page gets unmapped
+fn test_map_function_refuses_a_mapped_page(){
+    let mut address_space = AddressSpace::new();
+    let page = VirtPage::new(0x4000_0000).unwrap();
+    let first_frame = PhysFrame::new(0x8010_0000).unwrap();
+    let second_frame = PhysFrame::new(0x8020_0000).unwrap();
+    address_space.map(page, first_frame, 6u64).unwrap();
+
+    let remapped = address_space.map(page, second_frame, 6u64);
+    let translated = address_space.translate(page.start_address()).map(|address| address.as_u64());
+    let unmapped = address_space.unmap(page);
+    let mapped_again = address_space.map(page, second_frame, 6u64);
+
+    let res = (remapped, translated, unmapped, mapped_again);
+    let suc_msg = "test_map_function_refuses_a_mapped_page    ....   [OK]";
+    let fail_msg = "test_map_function_refuses_a_mapped_page   ....    [FAIL]";
+    custom_assert((Err(errors::MAPPING_ERROR_AlreadyMapped), Ok(0x8010_0000), Ok(first_frame), Ok(())), res, suc_msg, fail_msg);
+}
+
+// an owned frame outside the heap (here the UART) cannot be released : the error comes back, and the page is unmapped anyway
+fn test_unmap_reports_an_unreleasable_page(){
+    let mut address_space = AddressSpace::new();
+    let page = VirtPage::new(0x4000_0000).unwrap();
+    address_space.map_owned(page, PhysFrame::new(0x1000_0000).unwrap(), 6u64).unwrap();
+
+    let unmapped = address_space.unmap(page);
+    let res = (matches!(unmapped, Err(UnmapError::Deallocation(_))), address_space.translate(page.start_address()).is_err());
+    let suc_msg = "test_unmap_reports_an_unreleasable_page    ....   [OK]";
+    let fail_msg = "test_unmap_reports_an_unreleasable_page   ....    [FAIL]";
+    custom_assert((true, true), res, suc_msg, fail_msg);
+}
+
+// --------------------  test the Table Entry flags -------------------------- //
+
+fn test_set_access_map_keeps_owned_bit(){
+    let mut entry = TableEntry::new();
+    entry.set_address(0x8000_5000);
+    entry.set_as_valid();
+    entry.add_access_mask(6u64); // Read-Write
+    entry.set_as_owned();
+    entry.set_access_map(2u64); // Read only
+
+    let res = (entry.check_if_owned(), entry.check_if_writable(), entry.check_if_readable(), entry.get_address());
+    let suc_msg = "test_set_access_map_keeps_owned_bit    ....   [OK]";
+    let fail_msg = "test_set_access_map_keeps_owned_bit   ....    [FAIL]";
+    custom_assert((true, false, true, 0x8000_5000), res, suc_msg, fail_msg);
+}
+
+fn test_global_bit_keeps_access_map(){
+    let mut entry = TableEntry::new();
+    entry.set_address(0x8000_5000);
+    entry.set_as_valid();
+    entry.add_access_mask(10u64); // Read-Execute
+    entry.set_as_global();
+    let was_global = entry.check_if_global();
+    entry.set_as_not_global();
+
+    let res = (was_global, entry.check_if_global(), entry.check_if_executable(), entry.get_address());
+    let suc_msg = "test_global_bit_keeps_access_map    ....   [OK]";
+    let fail_msg = "test_global_bit_keeps_access_map   ....    [FAIL]";
+    custom_assert((true, false, true, 0x8000_5000), res, suc_msg, fail_msg);
+}
+
+fn test_replace_address_keeps_copy_on_write_bit(){
+    let mut entry = TableEntry::new();
+    entry.set_address(0x8000_5000);
+    entry.set_as_valid();
+    entry.add_access_mask(2u64); // Read only
+    entry.set_as_owned();
+    entry.set_as_copy_on_write();
+    entry.replace_address(0x8000_9000);
+
+    let res = (entry.check_if_copy_on_write(), entry.check_if_owned(), entry.check_if_valid(), entry.get_address());
+    let suc_msg = "test_replace_address_keeps_copy_on_write_bit    ....   [OK]";
+    let fail_msg = "test_replace_address_keeps_copy_on_write_bit   ....    [FAIL]";
+    custom_assert((true, true, true, 0x8000_9000), res, suc_msg, fail_msg);
+}
+
+fn test_root_table_from_satp(){
+    let res = (root_table_from_satp((8usize << 60) | 0x80123), root_table_from_satp(0x80123));
+    let suc_msg = "test_root_table_from_satp    ....   [OK]";
+    let fail_msg = "test_root_table_from_satp   ....    [FAIL]";
+    custom_assert((Some(PhysFrame::new(0x8012_3000).unwrap()), None), res, suc_msg, fail_msg);
+}
+
+// --------------------  test the Virtual Memory Areas -------------------------- //
+
+fn test_vma_contains(){
+    let vma = Vma::new(0x4000_0000, 0x2000, 6u64, VmaBacking::Anonymous);
+    let res = (vma.contains(0x3fff_ffff), vma.contains(0x4000_0000), vma.contains(0x4000_1fff), vma.contains(0x4000_2000));
+    let suc_msg = "test_vma_contains    ....   [OK]";
+    let fail_msg = "test_vma_contains   ....    [FAIL]";
+    custom_assert((false, true, true, false), res, suc_msg, fail_msg);
+}
+
+fn test_vma_map_rejects_overlap(){
+    let mut vma_map = VmaMap::new();
+    vma_map.insert(Vma::new(0x4000_0000, 0x2000, 6u64, VmaBacking::Anonymous)).unwrap();
+    let res = (
+        vma_map.insert(Vma::new(0x4000_1000, 0x2000, 6u64, VmaBacking::Anonymous)),
+        vma_map.insert(Vma::new(0x3fff_f000, 0x2000, 6u64, VmaBacking::Anonymous)),
+        vma_map.insert(Vma::new(0x4000_2000, 0x1000, 6u64, VmaBacking::Anonymous)),
+        vma_map.insert(Vma::new(0x4001_0000, 0x1001, 6u64, VmaBacking::Anonymous))
+    );
+    let suc_msg = "test_vma_map_rejects_overlap    ....   [OK]";
+    let fail_msg = "test_vma_map_rejects_overlap   ....    [FAIL]";
+    custom_assert((Err(errors::VMA_ERROR_OverlappingArea), Err(errors::VMA_ERROR_OverlappingArea), Ok(()), Err(errors::VMA_ERROR_UnalignedRange)),
+                  res, suc_msg, fail_msg);
+}
+
+fn test_vma_map_lookup(){
+    let mut vma_map = VmaMap::new();
+    vma_map.insert(Vma::new(0x4000_0000, 0x2000, 6u64, VmaBacking::Anonymous)).unwrap();
+    vma_map.insert(Vma::new(0x5000_0000, 0x1000, 2u64, VmaBacking::Stack)).unwrap();
+    let res = (
+        vma_map.lookup(0x4000_1abc).map(|vma| vma.start),
+        vma_map.lookup(0x4000_2000).map(|vma| vma.start),
+        vma_map.lookup(0x5000_0fff).map(|vma| vma.start)
+    );
+    let suc_msg = "test_vma_map_lookup    ....   [OK]";
+    let fail_msg = "test_vma_map_lookup   ....    [FAIL]";
+    custom_assert((Some(0x4000_0000), None, Some(0x5000_0000)), res, suc_msg, fail_msg);
+}
+
+fn test_vma_map_split(){
+    let mut vma_map = VmaMap::new();
+    vma_map.insert(Vma::new(0x1000_0000, 0x3000, 6u64, VmaBacking::Device { physical_start: 0x1000_0000 })).unwrap();
+    vma_map.split(0x1000_1000).unwrap();
+    let res = (vma_map.len(), vma_map.lookup(0x1000_0000).copied(), vma_map.lookup(0x1000_2000).copied());
+    let suc_msg = "test_vma_map_split    ....   [OK]";
+    let fail_msg = "test_vma_map_split   ....    [FAIL]";
+    custom_assert((2,
+                   Some(Vma::new(0x1000_0000, 0x1000, 6u64, VmaBacking::Device { physical_start: 0x1000_0000 })),
+                   Some(Vma::new(0x1000_1000, 0x2000, 6u64, VmaBacking::Device { physical_start: 0x1000_1000 }))),
+                  res, suc_msg, fail_msg);
+}
+
+fn test_vma_map_merge(){
+    let mut vma_map = VmaMap::new();
+    vma_map.insert(Vma::new(0x4000_0000, 0x1000, 6u64, VmaBacking::Anonymous)).unwrap();
+    vma_map.insert(Vma::new(0x4000_1000, 0x1000, 6u64, VmaBacking::Anonymous)).unwrap();
+    vma_map.insert(Vma::new(0x4000_2000, 0x1000, 2u64, VmaBacking::Anonymous)).unwrap(); // different permissions : stays apart
+    let merged = vma_map.merge(0x4000_1000);
+    let res = (merged, vma_map.len());
+    let suc_msg = "test_vma_map_merge    ....   [OK]";
+    let fail_msg = "test_vma_map_merge   ....    [FAIL]";
+    custom_assert((Ok(Vma::new(0x4000_0000, 0x2000, 6u64, VmaBacking::Anonymous)), 2), res, suc_msg, fail_msg);
+}
+
+fn test_vma_map_remove_range(){
+    let mut vma_map = VmaMap::new();
+    vma_map.insert(Vma::new(0x4000_0000, 0x4000, 6u64, VmaBacking::Anonymous)).unwrap();
+    let removed = vma_map.remove_range(0x4000_1000, 0x2000).unwrap();
+    let res = (removed.len(), removed[0], vma_map.len(), vma_map.lookup(0x4000_1000).is_none(), vma_map.lookup(0x4000_3000).is_some());
+    let suc_msg = "test_vma_map_remove_range    ....   [OK]";
+    let fail_msg = "test_vma_map_remove_range   ....    [FAIL]";
+    custom_assert((1, Vma::new(0x4000_1000, 0x2000, 6u64, VmaBacking::Anonymous), 2, true, true), res, suc_msg, fail_msg);
+}
+
+// --------------------  test the typed addresses -------------------------- //
+
+fn test_virt_page_rejects_unaligned_address(){
+    let res = (VirtPage::new(0x4000_0010), VirtPage::new(0x4000_0000).map(|page| page.as_u64()));
+    let suc_msg = "test_virt_page_rejects_unaligned_address    ....   [OK]";
+    let fail_msg = "test_virt_page_rejects_unaligned_address   ....    [FAIL]";
+    custom_assert((Err(errors::ADDRESS_ERROR_Unaligned), Ok(0x4000_0000)), res, suc_msg, fail_msg);
+}
+
+fn test_virt_addr_containing_page(){
+    let virt_address = VirtAddr::new(0x4000_1abc).unwrap();
+    let res = (virt_address.containing_page().as_u64(), virt_address.page_offset(), virt_address.containing_page().number());
+    let suc_msg = "test_virt_addr_containing_page    ....   [OK]";
+    let fail_msg = "test_virt_addr_containing_page   ....    [FAIL]";
+    custom_assert((0x4000_1000, 0xabc, 0x40001), res, suc_msg, fail_msg);
+}
+
+// --------------------  test the page-table walker -------------------------- //
+
+fn test_mappings_coalesce_contiguous_pages(){
+    let mut address_space = AddressSpace::new();
+    for index in 0..3u64 { // three pages that continue each other, then one page with other permissions
+        address_space.map(VirtPage::new(0x4000_0000 + index * 4096).unwrap(), PhysFrame::new(0x8010_0000 + index * 4096).unwrap(), 6u64).unwrap();
     }
-}
\ No newline at end of file
+    address_space.map(VirtPage::new(0x4000_3000).unwrap(), PhysFrame::new(0x8010_3000).unwrap(), 2u64).unwrap();
+
+    let res : alloc::vec::Vec<(u64, u64, u64, bool)> = address_space.mappings()
+        .map(|mapping| (mapping.virt_start, mapping.phys_start, mapping.size, mapping.is_writable()))
+        .collect();
+    let suc_msg = "test_mappings_coalesce_contiguous_pages    ....   [OK]";
+    let fail_msg = "test_mappings_coalesce_contiguous_pages   ....    [FAIL]";
+    custom_assert(alloc::vec![(0x4000_0000, 0x8010_0000, 0x3000, true), (0x4000_3000, 0x8010_3000, 0x1000, false)], res, suc_msg, fail_msg);
+}
+
+fn test_diff_mappings(){
+    let mut first = AddressSpace::new();
+    let mut second = AddressSpace::new();
+    // same mapping on both sides : not reported
+    first.map(VirtPage::new(0x4000_0000).unwrap(), PhysFrame::new(0x8010_0000).unwrap(), 6u64).unwrap();
+    second.map(VirtPage::new(0x4000_0000).unwrap(), PhysFrame::new(0x8010_0000).unwrap(), 6u64).unwrap();
+    // same page, other frame
+    first.map(VirtPage::new(0x4000_1000).unwrap(), PhysFrame::new(0x8010_1000).unwrap(), 6u64).unwrap();
+    second.map(VirtPage::new(0x4000_1000).unwrap(), PhysFrame::new(0x8020_0000).unwrap(), 6u64).unwrap();
+    // only mapped in the second
+    second.map(VirtPage::new(0x5000_0000).unwrap(), PhysFrame::new(0x8030_0000).unwrap(), 2u64).unwrap();
+
+    let res = first.diff(&second);
+    let suc_msg = "test_diff_mappings    ....   [OK]";
+    let fail_msg = "test_diff_mappings   ....    [FAIL]";
+    custom_assert(alloc::vec![
+                      MappingDifference { virt_start: 0x4000_1000, size: 0x1000, first: Some((0x8010_1000, 7u64)), second: Some((0x8020_0000, 7u64)) },
+                      MappingDifference { virt_start: 0x5000_0000, size: 0x1000, first: None, second: Some((0x8030_0000, 3u64)) }
+                  ], res, suc_msg, fail_msg);
+}
+
+// --------------------  test the TLB shootdown helpers -------------------------- //
+
+fn test_tlb_flush_survives_the_mailbox(){
+    let requests = [TlbFlush::All, TlbFlush::Page(0x4000_1000), TlbFlush::Asid(3), TlbFlush::PageInAsid(0x4000_1000, 3)];
+    let res = requests.map(|flush| { let (kind, virt_address, asid) = flush.encode(); TlbFlush::decode(kind, virt_address, asid) });
+    let suc_msg = "test_tlb_flush_survives_the_mailbox    ....   [OK]";
+    let fail_msg = "test_tlb_flush_survives_the_mailbox   ....    [FAIL]";
+    custom_assert(requests, res, suc_msg, fail_msg);
+}
+
+fn test_remote_harts_skip_the_initiator(){
+    let res = (super::tlb::remote_harts(0b1011, 1), super::tlb::remote_harts(0b0001, 0));
+    let suc_msg = "test_remote_harts_skip_the_initiator    ....   [OK]";
+    let fail_msg = "test_remote_harts_skip_the_initiator   ....    [FAIL]";
+    custom_assert((0b1001, 0), res, suc_msg, fail_msg);
+}
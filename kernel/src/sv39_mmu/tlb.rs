@@ -0,0 +1,155 @@
+//! TLB maintenance.
+//!
+//! Changing a translation table is not enough : every hart may still hold the old translation in its TLB.
+//! 1. flush_local flushes the TLB of the current hart with a targeted sfence.vma (address and/or ASID operands)
+//! 2. shootdown flushes the current hart AND every other online hart.
+//!
+//! The shootdown protocol uses the CLINT software interrupts (MSIP) :
+//! - every hart has a mailbox. The initiator writes the flush request into the mailbox of each remote hart,
+//!   marks it pending and raises a MachineSoftwareInterrupt on that hart. The machine mode shim clears it
+//!   and passes it on as a SupervisorSoftwareInterrupt (see delegation.rs).
+//! - the remote hart runs handle_shootdown_interrupt : it flushes its TLB and clears the pending flag (the acknowledgement).
+//! - the initiator waits for every acknowledgement before returning. Only then may the old frames be reused.
+//!
+//! Only one shootdown runs at a time. While waiting, the initiator keeps serving its own mailbox,
+//! so two harts that shoot at each other do not wait forever.
+
+use crate::drivers::timer::SoftwareInterrupt;
+use crate::interrupt_and_exception_handling::MAX_HARTS;
+use crate::riscv;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// What has to be flushed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlbFlush{
+    All,                        // every entry
+    Page(u64),                  // the entries of one virtual address, in every address space
+    Asid(usize),                // the entries of one address space
+    PageInAsid(u64, usize)      // the entries of one virtual address in one address space
+}
+
+impl TlbFlush{
+    // packs the request into (kind, address, asid) so that it fits in a mailbox
+    pub(super) fn encode(&self) -> (usize, u64, usize){
+        match *self {
+            TlbFlush::All => (0, 0, 0),
+            TlbFlush::Page(virt_address) => (1, virt_address, 0),
+            TlbFlush::Asid(asid) => (2, 0, asid),
+            TlbFlush::PageInAsid(virt_address, asid) => (3, virt_address, asid),
+        }
+    }
+
+    pub(super) fn decode(kind: usize, virt_address: u64, asid: usize) -> TlbFlush{
+        match kind {
+            1 => TlbFlush::Page(virt_address),
+            2 => TlbFlush::Asid(asid),
+            3 => TlbFlush::PageInAsid(virt_address, asid),
+            _ => TlbFlush::All, // flushing too much is always safe
+        }
+    }
+}
+
+// A flush request sent to one hart. "pending" is set by the initiator and cleared by the hart once it has flushed
+struct Mailbox{
+    pending : AtomicBool,
+    kind : AtomicUsize,
+    virt_address : AtomicU64,
+    asid : AtomicUsize
+}
+
+impl Mailbox{
+    const fn new() -> Self{
+        Mailbox { pending: AtomicBool::new(false), kind: AtomicUsize::new(0), virt_address: AtomicU64::new(0), asid: AtomicUsize::new(0) }
+    }
+}
+
+const EMPTY_MAILBOX : Mailbox = Mailbox::new();
+static MAILBOXES : [Mailbox; MAX_HARTS] = [EMPTY_MAILBOX; MAX_HARTS];
+static ONLINE_HARTS : AtomicUsize = AtomicUsize::new(0);   // bit i is set when hart i takes part in shootdowns
+static SHOOTDOWN_LOCK : AtomicBool = AtomicBool::new(false);
+
+/// Registers a hart as running. Only online harts get shootdown requests
+pub fn mark_hart_online(hart_id: usize){
+    if hart_id < MAX_HARTS { ONLINE_HARTS.fetch_or(1 << hart_id, Ordering::SeqCst); }
+}
+
+pub fn mark_hart_offline(hart_id: usize){
+    if hart_id < MAX_HARTS { ONLINE_HARTS.fetch_and(!(1 << hart_id), Ordering::SeqCst); }
+}
+
+/// The mask of the harts that have to be notified : every online hart except the initiator
+pub(super) fn remote_harts(online_mask: usize, initiator: usize) -> usize{
+    online_mask & !(1 << initiator)
+}
+
+/// Flushes the TLB of the current hart only
+pub fn flush_local(flush: TlbFlush){
+    match flush {
+        TlbFlush::All => riscv::clear_TLB(),
+        TlbFlush::Page(virt_address) => riscv::sfence_vma_address(virt_address as usize),
+        TlbFlush::Asid(asid) => riscv::sfence_vma_asid(asid),
+        TlbFlush::PageInAsid(virt_address, asid) => riscv::sfence_vma_address_asid(virt_address as usize, asid),
+    }
+}
+
+/// Flushes the TLB of every online hart and waits until all of them are done.
+/// Returns the number of remote harts that were notified
+pub fn shootdown(flush: TlbFlush) -> usize{
+    let initiator = riscv::hart_id();
+    let remote_mask = remote_harts(ONLINE_HARTS.load(Ordering::SeqCst), initiator);
+    if remote_mask == 0 { // single hart : no protocol needed
+        flush_local(flush);
+        return 0;
+    }
+
+    while SHOOTDOWN_LOCK.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        serve_mailbox(initiator);
+    }
+
+    // post the request, then ring the harts
+    let (kind, virt_address, asid) = flush.encode();
+    let mut notified = 0;
+    for hart in 0..MAX_HARTS {
+        if remote_mask & (1 << hart) == 0 { continue; }
+        let mailbox = &MAILBOXES[hart];
+        mailbox.kind.store(kind, Ordering::Relaxed);
+        mailbox.virt_address.store(virt_address, Ordering::Relaxed);
+        mailbox.asid.store(asid, Ordering::Relaxed);
+        mailbox.pending.store(true, Ordering::Release);
+        SoftwareInterrupt::raise(hart);
+        notified += 1;
+    }
+
+    flush_local(flush);
+
+    // wait for the acknowledgements
+    for hart in 0..MAX_HARTS {
+        if remote_mask & (1 << hart) == 0 { continue; }
+        while MAILBOXES[hart].pending.load(Ordering::Acquire) == true {
+            serve_mailbox(initiator);
+            core::hint::spin_loop();
+        }
+    }
+
+    SHOOTDOWN_LOCK.store(false, Ordering::Release);
+    notified
+}
+
+// Performs the flush waiting in the mailbox of the hart, if any, then acknowledges it
+fn serve_mailbox(hart_id: usize){
+    assert!(hart_id < MAX_HARTS, "hart {} has no TLB mailbox, MAX_HARTS is {}", hart_id, MAX_HARTS);
+    let mailbox = &MAILBOXES[hart_id];
+    if mailbox.pending.load(Ordering::Acquire) == false { return; }
+    let flush = TlbFlush::decode(mailbox.kind.load(Ordering::Relaxed),
+                                 mailbox.virt_address.load(Ordering::Relaxed),
+                                 mailbox.asid.load(Ordering::Relaxed));
+    flush_local(flush);
+    mailbox.pending.store(false, Ordering::Release);
+}
+
+/// Called by the trap handler on a SupervisorSoftwareInterrupt (cause 1).
+/// Serves the shootdown request of this hart, the shim already cleared the MachineSoftwareInterrupt
+pub fn handle_shootdown_interrupt(){
+    serve_mailbox(riscv::hart_id());
+}
+
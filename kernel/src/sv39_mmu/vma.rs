@@ -0,0 +1,180 @@
+//! Virtual Memory Areas (VMAs)
+//!
+//! The translation tables only say what IS mapped. A VMA says what is MEANT to be mapped :
+//! a virtual range, the access permissions of that range, and where the content of its pages comes from (the backing).
+//! Each address space keeps its VMAs in a VmaMap : a map of non-overlapping areas sorted by their start address.
+//!
+//! The page-fault handler uses the VmaMap to fill pages on demand. mmap-like calls and memory reports can build on it too.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use super::errors::{self, VmaError};
+use crate::{print, println};
+
+/// Where the content of the pages of an area comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VmaBacking{
+    Anonymous,                              // zeroed pages, allocated the first time they are touched
+    File { device: usize, offset: u64 },    // pages are read from a block device, the first page of the area is at the byte offset
+    Device { physical_start: u64 },         // MMIO : the area maps the physical range that starts at physical_start
+    Stack                                   // zeroed pages like Anonymous, the area is used as a stack
+}
+
+impl VmaBacking{
+    // The backing of the part of an area that starts "distance" bytes after the start of the area
+    fn advanced_by(&self, distance: u64) -> VmaBacking{
+        match *self {
+            VmaBacking::Anonymous => VmaBacking::Anonymous,
+            VmaBacking::File { device, offset } => VmaBacking::File { device, offset: offset + distance },
+            VmaBacking::Device { physical_start } => VmaBacking::Device { physical_start: physical_start + distance },
+            VmaBacking::Stack => VmaBacking::Stack,
+        }
+    }
+}
+
+/// A single virtual memory area
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vma{
+    pub start : u64,        // page aligned
+    pub length : u64,       // in bytes, a non-zero multiple of 4096
+    pub access_map : u64,   // RWX bits, same format as the access map of the mapping function
+    pub backing : VmaBacking
+}
+
+impl Vma{
+    pub fn new(start: u64, length: u64, access_map: u64, backing: VmaBacking) -> Self{
+        Vma { start, length, access_map, backing }
+    }
+
+    /// The first address after the area
+    pub fn end(&self) -> u64{
+        self.start + self.length
+    }
+
+    /// checks if the virtual address falls inside the area
+    pub fn contains(&self, virt_address: u64) -> bool{
+        virt_address >= self.start && virt_address < self.end()
+    }
+
+    /// Two areas can become one if they touch, have the same permissions and their backings continue each other
+    pub fn can_merge_with(&self, next: &Vma) -> bool{
+        self.end() == next.start
+            && self.access_map == next.access_map
+            && self.backing.advanced_by(self.length) == next.backing
+    }
+}
+
+/// The sorted collection of the areas of one address space. Areas never overlap
+#[derive(Debug, Clone)]
+pub struct VmaMap{
+    areas : BTreeMap<u64, Vma> // key : start address of the area
+}
+
+impl VmaMap{
+    pub const fn new() -> Self{
+        VmaMap { areas: BTreeMap::new() }
+    }
+
+    /// Records a new area. The area must be page aligned, have a valid access map and must not overlap any recorded area
+    pub fn insert(&mut self, vma: Vma) -> Result<(), VmaError>{
+        if vma.start % 4096 != 0 || vma.length % 4096 != 0 || vma.length == 0 {
+            return Err(errors::VMA_ERROR_UnalignedRange);
+        }
+        if vma.access_map & !14u64 != 0 || vma.access_map == 0 {
+            return Err(errors::VMA_ERROR_InvalidAccessMap);
+        }
+        // only the closest area below the end of the new one can overlap it
+        if let Some((_, previous)) = self.areas.range(..vma.end()).next_back() {
+            if previous.end() > vma.start { return Err(errors::VMA_ERROR_OverlappingArea); }
+        }
+        self.areas.insert(vma.start, vma);
+        Ok(())
+    }
+
+    /// Returns the area that contains the virtual address
+    pub fn lookup(&self, virt_address: u64) -> Option<&Vma>{
+        match self.areas.range(..=virt_address).next_back() {
+            Some((_, vma)) if vma.contains(virt_address) => Some(vma),
+            _ => None
+        }
+    }
+
+    /// Cuts the area that contains the address into two areas. The second area starts at the address.
+    /// Splitting exactly at the start of an area does nothing
+    pub fn split(&mut self, virt_address: u64) -> Result<(), VmaError>{
+        if virt_address % 4096 != 0 { return Err(errors::VMA_ERROR_UnalignedRange); }
+        let vma = match self.lookup(virt_address) {
+            Some(vma) => *vma,
+            None => return Err(errors::VMA_ERROR_NoSuchArea)
+        };
+        if vma.start == virt_address { return Ok(()); }
+
+        let first_length = virt_address - vma.start;
+        let first = Vma::new(vma.start, first_length, vma.access_map, vma.backing);
+        let second = Vma::new(virt_address, vma.length - first_length, vma.access_map, vma.backing.advanced_by(first_length));
+        self.areas.insert(first.start, first);
+        self.areas.insert(second.start, second);
+        Ok(())
+    }
+
+    /// Merges the area that contains the address with its neighbours, where possible.
+    /// Returns the area that contains the address after the merge
+    pub fn merge(&mut self, virt_address: u64) -> Result<Vma, VmaError>{
+        let mut vma = match self.lookup(virt_address) {
+            Some(vma) => *vma,
+            None => return Err(errors::VMA_ERROR_NoSuchArea)
+        };
+
+        // absorb the area that comes right after
+        if let Some(next) = self.areas.get(&vma.end()).copied() {
+            if vma.can_merge_with(&next) {
+                self.areas.remove(&next.start);
+                vma.length = vma.length + next.length;
+            }
+        }
+        // get absorbed by the area that comes right before
+        if let Some((_, previous)) = self.areas.range(..vma.start).next_back() {
+            let previous = *previous;
+            if previous.can_merge_with(&vma) {
+                self.areas.remove(&vma.start);
+                vma = Vma::new(previous.start, previous.length + vma.length, previous.access_map, previous.backing);
+            }
+        }
+        self.areas.insert(vma.start, vma);
+        Ok(vma)
+    }
+
+    /// Forgets the range [start, start + length). Areas that are partly inside the range get split first.
+    /// Returns the removed pieces, so that the caller can unmap their pages
+    pub fn remove_range(&mut self, start: u64, length: u64) -> Result<Vec<Vma>, VmaError>{
+        if start % 4096 != 0 || length % 4096 != 0 || length == 0 {
+            return Err(errors::VMA_ERROR_UnalignedRange);
+        }
+        let end = start + length;
+        if self.lookup(start).is_some() { self.split(start)?; }
+        if self.lookup(end).is_some() { self.split(end)?; }
+
+        let removed_starts : Vec<u64> = self.areas.range(start..end).map(|(key, _)| *key).collect();
+        let mut removed = Vec::new();
+        for key in removed_starts {
+            if let Some(vma) = self.areas.remove(&key) { removed.push(vma); }
+        }
+        Ok(removed)
+    }
+
+    /// Iterates over the areas in increasing address order
+    pub fn iter(&self) -> impl Iterator<Item = &Vma>{
+        self.areas.values()
+    }
+
+    pub fn len(&self) -> usize{
+        self.areas.len()
+    }
+
+    /// Prints the areas in the same style as show_mappings
+    pub fn show(&self){
+        for vma in self.iter() {
+            println!(" \t >>>> {:016x} - {:016x} : access {:04b} : {:?}", vma.start, vma.end(), vma.access_map, vma.backing);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! A structured walk over the translation tables.
+//!
+//! show_mappings used to be the only way to look at the tables, and it could only print.
+//! Mappings is an iterator that yields every valid leaf as a Mapping : (virtual start, physical start, size, flags).
+//! Leaves that continue each other (contiguous virtual AND physical addresses, same flags) are coalesced into one range.
+//! Superpages (leaves found above level 0) are reported with their real size.
+//!
+//! diff_mappings compares two sets of tables and returns the virtual ranges where they disagree.
+
+use super::{paging_mode, canonical_virtual_address, table_at};
+use alloc::vec::Vec;
+use crate::physical_memory::PhysFrame;
+
+// The flags that a Mapping reports : V R W X U G and the two RSW bits.
+// The Accessed and Dirty bits are left out. The hardware sets them behind our back, they would break ranges apart
+const FLAGS_MASK : u64 = 0b11_0011_1111;
+
+/// A range of virtual addresses mapped onto a range of physical addresses of the same size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping{
+    pub virt_start : u64,
+    pub phys_start : u64,
+    pub size : u64,     // in bytes
+    pub flags : u64     // the low bits of the leaf entries, without the A and D bits
+}
+
+impl Mapping{
+    pub fn virt_end(&self) -> u64{ self.virt_start + self.size }
+    pub fn phys_end(&self) -> u64{ self.phys_start + self.size }
+
+    pub fn contains(&self, virt_address: u64) -> bool{
+        virt_address >= self.virt_start && virt_address < self.virt_end()
+    }
+
+    /// The physical address that the virtual address is mapped to, if the virtual address is inside the range
+    pub fn translate(&self, virt_address: u64) -> Option<u64>{
+        if self.contains(virt_address) == false { return None; }
+        Some(self.phys_start + (virt_address - self.virt_start))
+    }
+
+    pub fn is_readable(&self) -> bool{ self.flags & 2u64 == 2u64 }
+    pub fn is_writable(&self) -> bool{ self.flags & 4u64 == 4u64 }
+    pub fn is_executable(&self) -> bool{ self.flags & 8u64 == 8u64 }
+
+    // true if "next" starts exactly where this range ends, both virtually and physically, with the same flags
+    fn is_continued_by(&self, next: &Mapping) -> bool{
+        self.virt_end() == next.virt_start && self.phys_end() == next.phys_start && self.flags == next.flags
+    }
+}
+
+// One table that is being walked : where it lives, its level, the next entry to look at
+// and the virtual address bits contributed by the tables above it
+#[derive(Clone, Copy)]
+struct WalkPosition{
+    table_address : u64,
+    level : usize,
+    next_index : usize,
+    virt_address_prefix : u64
+}
+
+/// Yields the leaves one by one, without coalescing. Walks with an explicit stack instead of recursion
+struct Leaves{
+    stack : [WalkPosition; 4], // one position per table level, 4 levels at most (Sv48)
+    depth : usize              // number of positions in use. 0 means the walk is over
+}
+
+impl Leaves{
+    fn new(root_table_address: u64) -> Self{
+        let root = WalkPosition { table_address: root_table_address, level: paging_mode().levels() - 1, next_index: 0, virt_address_prefix: 0 };
+        Leaves { stack: [root; 4], depth: 1 }
+    }
+}
+
+impl Iterator for Leaves{
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping>{
+        while self.depth > 0 {
+            let position = &mut self.stack[self.depth - 1];
+            if position.next_index == 512 { self.depth -= 1; continue; } // this table is done, go back to its parent
+
+            let index = position.next_index;
+            position.next_index += 1;
+            let table_ref = table_at(position.table_address);
+            let table_entry = &table_ref.content[index];
+            if table_entry.check_if_valid() == false { continue; }
+
+            // reconstruct the part of the virtual address that this entry covers
+            let level = position.level;
+            let virt_address = canonical_virtual_address(position.virt_address_prefix | ((index as u64) << (12 + 9 * level)));
+            if table_entry.check_if_leaf() == true {
+                return Some(Mapping { virt_start: virt_address,
+                                      phys_start: table_entry.get_address(),
+                                      size: 4096u64 << (9 * level),
+                                      flags: table_entry.get_val() & FLAGS_MASK });
+            }
+            else if level > 0 { // a branch : walk the table below before moving on to the next entry
+                self.stack[self.depth] = WalkPosition { table_address: table_entry.get_address(), level: level - 1, next_index: 0, virt_address_prefix: virt_address };
+                self.depth += 1;
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the mapped ranges of a set of translation tables, in increasing virtual address order
+pub struct Mappings{
+    leaves : Leaves,
+    pending : Option<Mapping> // a leaf that was read ahead but could not be coalesced with the previous range
+}
+
+impl Iterator for Mappings{
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping>{
+        let mut current = match self.pending.take() {
+            Some(mapping) => mapping,
+            None => self.leaves.next()?
+        };
+        while let Some(next) = self.leaves.next() {
+            if current.is_continued_by(&next) == true { current.size += next.size; }
+            else { self.pending = Some(next); break; }
+        }
+        Some(current)
+    }
+}
+
+/// Walks the tables found under the root table
+pub fn mappings(root_table: PhysFrame) -> Mappings{
+    Mappings { leaves: Leaves::new(root_table.as_u64()), pending: None }
+}
+
+/// A virtual range where two sets of tables disagree.
+/// Each side is None if the range is unmapped on that side, or (physical start, flags) if it is mapped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MappingDifference{
+    pub virt_start : u64,
+    pub size : u64,
+    pub first : Option<(u64, u64)>,
+    pub second : Option<(u64, u64)>
+}
+
+impl MappingDifference{
+    pub fn virt_end(&self) -> u64{ self.virt_start + self.size }
+}
+
+// The (physical address, flags) that a virtual address maps to, looked up in a sorted list of ranges
+fn lookup(ranges: &[Mapping], virt_address: u64) -> Option<(u64, u64)>{
+    let index = ranges.partition_point(|mapping| mapping.virt_end() <= virt_address);
+    match ranges.get(index) {
+        Some(mapping) if mapping.contains(virt_address) => Some((mapping.translate(virt_address)?, mapping.flags)),
+        _ => None
+    }
+}
+
+// true if "later" is what "earlier" looks like after moving "distance" bytes forward
+fn side_continues(earlier: Option<(u64, u64)>, later: Option<(u64, u64)>, distance: u64) -> bool{
+    match (earlier, later) {
+        (None, None) => true,
+        (Some((phys, flags)), Some((later_phys, later_flags))) => phys + distance == later_phys && flags == later_flags,
+        _ => false
+    }
+}
+
+/// Compares the mappings of two sets of tables.
+/// Returns the virtual ranges that are mapped on one side only, or mapped differently (other physical address or other flags).
+/// Ranges that are mapped the same way on both sides are left out
+pub fn diff_mappings(first_root_table: PhysFrame, second_root_table: PhysFrame) -> Vec<MappingDifference>{
+    let first : Vec<Mapping> = mappings(first_root_table).collect();
+    let second : Vec<Mapping> = mappings(second_root_table).collect();
+
+    // between two consecutive boundaries, each side is either unmapped or mapped by a single range
+    let mut boundaries : Vec<u64> = Vec::new();
+    for mapping in first.iter().chain(second.iter()) {
+        boundaries.push(mapping.virt_start);
+        boundaries.push(mapping.virt_end());
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut differences : Vec<MappingDifference> = Vec::new();
+    for pair in boundaries.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let first_side = lookup(&first, start);
+        let second_side = lookup(&second, start);
+        if first_side == second_side { continue; }
+
+        // extend the previous difference if this one simply continues it
+        if let Some(previous) = differences.last_mut() {
+            let distance = start - previous.virt_start;
+            if previous.virt_end() == start
+                && side_continues(previous.first, first_side, distance)
+                && side_continues(previous.second, second_side, distance) {
+                previous.size += end - start;
+                continue;
+            }
+        }
+        differences.push(MappingDifference { virt_start: start, size: end - start, first: first_side, second: second_side });
+    }
+    differences
+}
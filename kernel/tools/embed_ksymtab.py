@@ -0,0 +1,119 @@
+#!/usr/bin/env python3
+# Fills the .ksymtab section of a linked kernel image with the kernel symbol table.
+#
+# The kernel links with an empty .ksymtab section of KSYMTAB_SIZE bytes (see src/ksymtab/mod.rs for the layout).
+# This script reads the function symbols of the image, demangles them, and writes the table into that section, in place.
+# The size of the section does not change, so every address in the image stays valid.
+#
+# usage : embed_ksymtab.py <kernel elf>
+
+import struct
+import sys
+
+MAGIC = 0x4d59534b          # "KSYM"
+STT_FUNC = 2
+END_OF_TEXT = "<end of text>"
+
+# the escapes used by the legacy Rust mangling scheme
+RUST_ESCAPES = {"$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">", "$LP$": "(", "$RP$": ")",
+                "$C$": ",", "$u7e$": "~", "$u20$": " ", "$u27$": "'", "$u5b$": "[", "$u5d$": "]",
+                "$u7b$": "{", "$u7d$": "}", "$u3b$": ";", "$u2b$": "+", "$u22$": "\""}
+
+
+def demangle(name):
+    """Demangles a legacy Rust symbol (_ZN...E) and drops the hash. Other names are returned as they are"""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+    body, parts, index = name[3:-1], [], 0
+    while index < len(body) and body[index].isdigit():
+        start = index
+        while body[index].isdigit():
+            index += 1
+        length = int(body[start:index])
+        parts.append(body[index:index + length])
+        index += length
+    if parts and len(parts[-1]) == 17 and parts[-1][0] == "h":
+        parts.pop()  # the hash
+    demangled = []
+    for part in parts:
+        if part.startswith("_$"):
+            part = part[1:]
+        for escape, replacement in RUST_ESCAPES.items():
+            part = part.replace(escape, replacement)
+        demangled.append(part.replace("..", "::"))
+    return "::".join(demangled)
+
+
+def read_sections(image):
+    """Returns {name: (file offset, size, address, link)} for every section of an ELF64 little endian image"""
+    if image[:4] != b"\x7fELF" or image[4] != 2 or image[5] != 1:
+        sys.exit("embed_ksymtab : not an ELF64 little endian image")
+    section_table, = struct.unpack_from("<Q", image, 0x28)
+    entry_size, count, names_index = struct.unpack_from("<HHH", image, 0x3a)
+    headers = [struct.unpack_from("<IIQQQQIIQQ", image, section_table + i * entry_size) for i in range(count)]
+    names_offset = headers[names_index][4]
+
+    sections = {}
+    for name_offset, _, _, address, offset, size, link, _, _, _ in headers:
+        end = image.index(b"\0", names_offset + name_offset)
+        sections[image[names_offset + name_offset:end].decode()] = (offset, size, address, link)
+    return sections
+
+
+def function_symbols(image, sections):
+    """The (address, demangled name) of every function, plus the end of the text section"""
+    symtab_offset, symtab_size, _, _ = sections[".symtab"]
+    strtab_offset, _, _, _ = sections[".strtab"]
+    symbols = {}
+    text_end = None
+    for at in range(symtab_offset, symtab_offset + symtab_size, 24):
+        name_offset, info, _, _, value, _ = struct.unpack_from("<IBBHQQ", image, at)
+        end = image.index(b"\0", strtab_offset + name_offset)
+        name = image[strtab_offset + name_offset:end].decode(errors="replace")
+        if name == "_text_end":
+            text_end = value
+        if info & 0xf == STT_FUNC and value != 0:
+            symbols.setdefault(value, demangle(name))  # aliases : keep the first name
+    if text_end is None:
+        _, text_size, text_address, _ = sections[".text"]
+        text_end = text_address + text_size
+    symbols[text_end] = END_OF_TEXT
+    return sorted(symbols.items())
+
+
+def build_table(symbols):
+    strings = bytearray()
+    entries = bytearray()
+    for address, name in symbols:
+        encoded = name.encode()
+        entries += struct.pack("<QII", address, len(strings), len(encoded))
+        strings += encoded
+    strings_offset = 16 + len(entries)
+    return struct.pack("<IIII", MAGIC, len(symbols), strings_offset, len(strings)) + entries + strings
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage : embed_ksymtab.py <kernel elf>")
+    path = sys.argv[1]
+    with open(path, "rb") as elf:
+        image = bytearray(elf.read())
+
+    sections = read_sections(image)
+    if ".ksymtab" not in sections:
+        sys.exit("embed_ksymtab : the image has no .ksymtab section")
+    offset, size, _, _ = sections[".ksymtab"]
+
+    symbols = function_symbols(image, sections)
+    table = build_table(symbols)
+    if len(table) > size:
+        sys.exit("embed_ksymtab : the table needs %d bytes but .ksymtab only has %d. Raise KSYMTAB_SIZE" % (len(table), size))
+
+    image[offset:offset + size] = table + bytes(size - len(table))
+    with open(path, "wb") as elf:
+        elf.write(image)
+    print("embed_ksymtab : %d symbols, %d of %d bytes used" % (len(symbols), len(table), size))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,10 @@
+#!/bin/sh
+# cargo runner : embeds the kernel symbol table into the image, then boots the image in QEMU
+# usage : run.sh <kernel elf> [extra QEMU arguments]
+set -e
+KERNEL="$1"
+shift
+python3 "$(dirname "$0")/embed_ksymtab.py" "$KERNEL"
+exec qemu-system-riscv64 -machine virt -cpu rv64 -smp 4 -m 128M \
+    -drive if=none,format=raw,file=hdd.dsk,id=attic -device virtio-blk-device,scsi=off,drive=attic \
+    -serial mon:stdio -nographic -bios none -kernel "$KERNEL" "$@"